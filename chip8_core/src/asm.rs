@@ -0,0 +1,980 @@
+//assembler for the Cowgod style mnemonics printed by the disassembler
+//
+//    ; comments start with a semicolon
+//    SPEED   equ 2              ; constants
+//    start:  LD V0, SPEED * 2   ; labels and expressions
+//            LD I, sprite
+//            DRW V0, V1, sprite_end - sprite
+//            JP start
+//    sprite: db 0xF0, 0x90, %1111_0000
+//    sprite_end:
+//            dw 0x1234
+//            org 0x300          ; pads with zeros up to an address
+//
+//numbers are decimal, 0x / $ hex or 0b / % binary, `$` alone is the current address
+//mnemonics, registers and directives are case insensitive, labels and constants are not
+
+use std::collections::HashMap;
+use std::fmt;
+
+//an error together with where in the source it happened, both 1-based
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+//assembles source for a program loaded at base, the result can go straight into Emu::load_at
+pub fn assemble(source: &str, base: u16) -> Result<Vec<u8>, AsmError> {
+    let mut statements = Vec::new();
+    let mut symbols: HashMap<String, Symbol> = HashMap::new();
+
+    //first pass: parse, lay out addresses and collect labels
+    let mut addr = base as i64;
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let parsed = parse_line(text, line)?;
+        for (name, column) in parsed.labels {
+            define(&mut symbols, name, Symbol::Value(addr), line, column)?;
+        }
+        let Some(stmt) = parsed.stmt else {
+            continue;
+        };
+        match &stmt.kind {
+            StmtKind::Equ(name, expr) => {
+                define(
+                    &mut symbols,
+                    name.clone(),
+                    Symbol::Expr(expr.clone(), addr),
+                    line,
+                    stmt.column,
+                )?;
+                continue;
+            }
+            StmtKind::Org(expr) => {
+                let target = eval(expr, &symbols, addr, line, 0)?;
+                if target < addr {
+                    return Err(AsmError {
+                        line,
+                        column: stmt.column,
+                        message: format!(
+                            "org {:#X} is behind the current address {:#X}",
+                            target, addr
+                        ),
+                    });
+                }
+                statements.push((stmt, addr));
+                addr = target;
+            }
+            _ => {
+                let size = stmt.size();
+                statements.push((stmt, addr));
+                addr += size as i64;
+            }
+        }
+        if addr > 0x10000 {
+            return Err(AsmError {
+                line,
+                column: 1,
+                message: "program does not fit into 64K".to_string(),
+            });
+        }
+    }
+
+    //second pass: every label is known now, encode
+    let mut out = Vec::new();
+    for (stmt, addr) in statements.iter() {
+        let offset = (*addr - base as i64) as usize;
+        out.resize(offset, 0);
+        encode(stmt, &symbols, *addr, &mut out)?;
+    }
+    //a trailing org pads up to its address too
+    out.resize((addr - base as i64) as usize, 0);
+    Ok(out)
+}
+
+enum Symbol {
+    //label or already known value
+    Value(i64),
+    //equ constant, evaluated on use with the address it was defined at
+    Expr(Expr, i64),
+}
+
+//operand keywords and registers, a symbol with one of these names could never be referred to
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    register(&upper).is_some()
+        || matches!(
+            upper.as_str(),
+            "I" | "DT" | "ST" | "K" | "F" | "HF" | "B" | "R" | "LONG"
+        )
+}
+
+fn define(
+    symbols: &mut HashMap<String, Symbol>,
+    name: String,
+    symbol: Symbol,
+    line: usize,
+    column: usize,
+) -> Result<(), AsmError> {
+    if is_reserved(&name) {
+        return Err(AsmError {
+            line,
+            column,
+            message: format!("`{}` is a reserved name", name),
+        });
+    }
+    if symbols.contains_key(&name) {
+        return Err(AsmError {
+            line,
+            column,
+            message: format!("`{}` is already defined", name),
+        });
+    }
+    symbols.insert(name, symbol);
+    Ok(())
+}
+
+//=== expressions ===
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(i64),
+    Here,
+    Sym(String, usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>, usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl BinOp {
+    //higher binds tighter, same order as C
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::Xor => 2,
+            BinOp::And => 3,
+            BinOp::Shl | BinOp::Shr => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 6,
+        }
+    }
+}
+
+//constants may refer to each other, this stops `a equ b` / `b equ a` loops
+const MAX_EVAL_DEPTH: usize = 64;
+
+fn eval(
+    expr: &Expr,
+    symbols: &HashMap<String, Symbol>,
+    here: i64,
+    line: usize,
+    depth: usize,
+) -> Result<i64, AsmError> {
+    let error = |column: usize, message: String| AsmError {
+        line,
+        column,
+        message,
+    };
+    Ok(match expr {
+        Expr::Num(value) => *value,
+        Expr::Here => here,
+        Expr::Sym(name, column) => match symbols.get(name) {
+            Some(Symbol::Value(value)) => *value,
+            Some(Symbol::Expr(expr, defined_at)) => {
+                if depth >= MAX_EVAL_DEPTH {
+                    return Err(error(*column, format!("`{}` refers to itself", name)));
+                }
+                eval(expr, symbols, *defined_at, line, depth + 1)?
+            }
+            None => return Err(error(*column, format!("unknown symbol `{}`", name))),
+        },
+        Expr::Neg(inner) => eval(inner, symbols, here, line, depth)?.wrapping_neg(),
+        Expr::Not(inner) => !eval(inner, symbols, here, line, depth)?,
+        Expr::Bin(op, lhs, rhs, column) => {
+            let lhs = eval(lhs, symbols, here, line, depth)?;
+            let rhs = eval(rhs, symbols, here, line, depth)?;
+            match op {
+                BinOp::Add => lhs.wrapping_add(rhs),
+                BinOp::Sub => lhs.wrapping_sub(rhs),
+                BinOp::Mul => lhs.wrapping_mul(rhs),
+                BinOp::Div | BinOp::Rem if rhs == 0 => {
+                    return Err(error(*column, "division by zero".to_string()))
+                }
+                BinOp::Div => lhs.wrapping_div(rhs),
+                BinOp::Rem => lhs.wrapping_rem(rhs),
+                BinOp::And => lhs & rhs,
+                BinOp::Or => lhs | rhs,
+                BinOp::Xor => lhs ^ rhs,
+                BinOp::Shl => lhs.wrapping_shl(rhs as u32),
+                BinOp::Shr => lhs.wrapping_shr(rhs as u32),
+            }
+        }
+    })
+}
+
+//recursive descent over one operand, columns are tracked for error messages
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    //column of chars[0] in the source line
+    column: usize,
+    line: usize,
+    text: &'a str,
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(text: &'a str, column: usize, line: usize) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+            column,
+            line,
+            text,
+        }
+    }
+
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column + self.pos,
+            message,
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn parse(mut self) -> Result<Expr, AsmError> {
+        if self.peek().is_none() {
+            return Err(self.error("missing operand".to_string()));
+        }
+        let expr = self.binary(0)?;
+        if self.peek().is_some() {
+            return Err(self.error(format!(
+                "unexpected `{}` in `{}`",
+                self.chars[self.pos], self.text
+            )));
+        }
+        Ok(expr)
+    }
+
+    fn binary_op(&mut self) -> Option<(BinOp, usize)> {
+        let c = self.peek()?;
+        let next = self.chars.get(self.pos + 1).copied();
+        Some(match (c, next) {
+            ('<', Some('<')) => (BinOp::Shl, 2),
+            ('>', Some('>')) => (BinOp::Shr, 2),
+            ('+', _) => (BinOp::Add, 1),
+            ('-', _) => (BinOp::Sub, 1),
+            ('*', _) => (BinOp::Mul, 1),
+            ('/', _) => (BinOp::Div, 1),
+            ('%', _) => (BinOp::Rem, 1),
+            ('&', _) => (BinOp::And, 1),
+            ('|', _) => (BinOp::Or, 1),
+            ('^', _) => (BinOp::Xor, 1),
+            _ => return None,
+        })
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, AsmError> {
+        let mut lhs = self.unary()?;
+        while let Some((op, len)) = self.binary_op() {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            let column = self.column + self.pos;
+            self.pos += len;
+            let rhs = self.binary(op.precedence())?;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(rhs), column);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, AsmError> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            }
+            Some('~') => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some('+') => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some('(') => {
+                self.pos += 1;
+                let inner = self.binary(0)?;
+                if self.peek() != Some(')') {
+                    return Err(self.error("missing `)`".to_string()));
+                }
+                self.pos += 1;
+                Ok(inner)
+            }
+            Some(_) => self.atom(),
+            None => Err(self.error("expression ends early".to_string())),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, AsmError> {
+        let start = self.pos;
+        let c = self.chars[self.pos];
+        let word_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        //$ on its own is the current address, $1F is hex; % is binary only before a digit
+        if c == '$'
+            || (c == '%'
+                && self
+                    .chars
+                    .get(self.pos + 1)
+                    .is_some_and(|c| *c == '0' || *c == '1'))
+        {
+            self.pos += 1;
+            let digits_start = self.pos;
+            while self.pos < self.chars.len() && word_char(self.chars[self.pos]) {
+                self.pos += 1;
+            }
+            let digits: String = self.chars[digits_start..self.pos].iter().collect();
+            if c == '$' && digits.is_empty() {
+                return Ok(Expr::Here);
+            }
+            let radix = if c == '$' { 16 } else { 2 };
+            return self.number(&digits, radix, start);
+        }
+        if !word_char(c) {
+            return Err(self.error(format!("unexpected `{}`", c)));
+        }
+        while self.pos < self.chars.len() && word_char(self.chars[self.pos]) {
+            self.pos += 1;
+        }
+        let word: String = self.chars[start..self.pos].iter().collect();
+        if c.is_ascii_digit() {
+            let lower = word.to_ascii_lowercase();
+            if let Some(hex) = lower.strip_prefix("0x") {
+                return self.number(hex, 16, start);
+            }
+            if let Some(bin) = lower.strip_prefix("0b") {
+                return self.number(bin, 2, start);
+            }
+            return self.number(&word, 10, start);
+        }
+        Ok(Expr::Sym(word, self.column + start))
+    }
+
+    fn number(&self, digits: &str, radix: u32, start: usize) -> Result<Expr, AsmError> {
+        let cleaned: String = digits.chars().filter(|&c| c != '_').collect();
+        i64::from_str_radix(&cleaned, radix)
+            .map(Expr::Num)
+            .map_err(|_| AsmError {
+                line: self.line,
+                column: self.column + start,
+                message: format!("invalid number `{}`", digits),
+            })
+    }
+}
+
+//=== statements ===
+
+//an operand as written, with the column it starts at
+#[derive(Clone, Debug)]
+struct Operand {
+    text: String,
+    column: usize,
+}
+
+#[derive(Clone, Debug)]
+enum StmtKind {
+    Instruction(String, Vec<Operand>),
+    Db(Vec<DataItem>),
+    Dw(Vec<Operand>),
+    Org(Expr),
+    Equ(String, Expr),
+}
+
+#[derive(Clone, Debug)]
+enum DataItem {
+    Expr(Operand),
+    Str(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+struct Stmt {
+    kind: StmtKind,
+    line: usize,
+    column: usize,
+}
+
+struct ParsedLine {
+    labels: Vec<(String, usize)>,
+    stmt: Option<Stmt>,
+}
+
+impl Stmt {
+    fn size(&self) -> usize {
+        match &self.kind {
+            StmtKind::Instruction(mnemonic, operands) => {
+                let long = mnemonic == "LD"
+                    && operands.len() == 2
+                    && first_word(&operands[1].text).eq_ignore_ascii_case("LONG");
+                if long {
+                    4
+                } else {
+                    2
+                }
+            }
+            StmtKind::Db(items) => items
+                .iter()
+                .map(|item| match item {
+                    DataItem::Expr(_) => 1,
+                    DataItem::Str(bytes) => bytes.len(),
+                })
+                .sum(),
+            StmtKind::Dw(items) => items.len() * 2,
+            StmtKind::Org(_) | StmtKind::Equ(..) => 0,
+        }
+    }
+}
+
+fn first_word(text: &str) -> &str {
+    text.split_whitespace().next().unwrap_or_default()
+}
+
+fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+//cuts the line at a `;` that is not inside a string
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..idx],
+            _ => (),
+        }
+    }
+    text
+}
+
+//splits operands at top level commas, keeping the column each one starts at
+//columns count characters, not bytes
+fn split_operands(text: &str, column: usize, line: usize) -> Result<Vec<Operand>, AsmError> {
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    //byte and character offset of the current piece
+    let mut start = (0, 0);
+    let mut pieces = Vec::new();
+    for (count, (idx, c)) in text.char_indices().enumerate() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                pieces.push((start.1, &text[start.0..idx]));
+                start = (idx + 1, count + 1);
+            }
+            _ => (),
+        }
+    }
+    pieces.push((start.1, &text[start.0..]));
+    for (offset, piece) in pieces {
+        let leading = piece.chars().take_while(|c| c.is_whitespace()).count();
+        let trimmed = piece.trim();
+        if trimmed.is_empty() {
+            return Err(AsmError {
+                line,
+                column: column + offset + leading,
+                message: "empty operand".to_string(),
+            });
+        }
+        operands.push(Operand {
+            text: trimmed.to_string(),
+            column: column + offset + leading,
+        });
+    }
+    Ok(operands)
+}
+
+fn parse_line(text: &str, line: usize) -> Result<ParsedLine, AsmError> {
+    let code = strip_comment(text);
+    let mut labels = Vec::new();
+    let mut rest = code;
+    //columns are counted in characters from the start of the line
+    let column_of = |part: &str| code[..code.len() - part.len()].chars().count() + 1;
+
+    //any number of `label:` prefixes
+    loop {
+        let trimmed = rest.trim_start();
+        let word_end = trimmed
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(trimmed.len());
+        let word = &trimmed[..word_end];
+        if word.is_empty() || !trimmed[word_end..].starts_with(':') {
+            break;
+        }
+        if !is_identifier(word) {
+            return Err(AsmError {
+                line,
+                column: column_of(trimmed),
+                message: format!("invalid label `{}`", word),
+            });
+        }
+        labels.push((word.to_string(), column_of(trimmed)));
+        rest = &trimmed[word_end + 1..];
+    }
+
+    let trimmed = rest.trim_start();
+    if trimmed.trim_end().is_empty() {
+        return Ok(ParsedLine { labels, stmt: None });
+    }
+    let column = column_of(trimmed);
+    let word_end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let word = &trimmed[..word_end];
+    let args = &trimmed[word_end..];
+    let args_column = column_of(args);
+
+    //NAME equ expr
+    let after = args.trim_start();
+    let second_end = after.find(char::is_whitespace).unwrap_or(after.len());
+    if after[..second_end].eq_ignore_ascii_case("equ") {
+        if !is_identifier(word) {
+            return Err(AsmError {
+                line,
+                column,
+                message: format!("invalid constant name `{}`", word),
+            });
+        }
+        let value_text = &after[second_end..];
+        let value_column = column_of(value_text);
+        let expr = ExprParser::new(value_text, value_column, line).parse()?;
+        return Ok(ParsedLine {
+            labels,
+            stmt: Some(Stmt {
+                kind: StmtKind::Equ(word.to_string(), expr),
+                line,
+                column,
+            }),
+        });
+    }
+
+    let mnemonic = word.to_ascii_uppercase();
+    let operands = if args.trim().is_empty() {
+        Vec::new()
+    } else {
+        split_operands(args, args_column, line)?
+    };
+    let kind = match mnemonic.as_str() {
+        "DB" => {
+            let mut items = Vec::new();
+            for operand in operands {
+                if let Some(inner) = operand
+                    .text
+                    .strip_prefix('"')
+                    .and_then(|s| s.strip_suffix('"'))
+                {
+                    items.push(DataItem::Str(inner.as_bytes().to_vec()));
+                } else {
+                    items.push(DataItem::Expr(operand));
+                }
+            }
+            if items.is_empty() {
+                return Err(AsmError {
+                    line,
+                    column,
+                    message: "db needs at least one value".to_string(),
+                });
+            }
+            StmtKind::Db(items)
+        }
+        "DW" => {
+            if operands.is_empty() {
+                return Err(AsmError {
+                    line,
+                    column,
+                    message: "dw needs at least one value".to_string(),
+                });
+            }
+            StmtKind::Dw(operands)
+        }
+        "ORG" => match operands.as_slice() {
+            [target] => StmtKind::Org(ExprParser::new(&target.text, target.column, line).parse()?),
+            _ => {
+                return Err(AsmError {
+                    line,
+                    column,
+                    message: "org takes one address".to_string(),
+                })
+            }
+        },
+        _ => StmtKind::Instruction(mnemonic, operands),
+    };
+    Ok(ParsedLine {
+        labels,
+        stmt: Some(Stmt { kind, line, column }),
+    })
+}
+
+//=== encoding ===
+
+//what an operand turned out to be
+enum Arg {
+    V(u16),
+    VRange(u16, u16),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Value(Expr),
+}
+
+fn register(text: &str) -> Option<u16> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V') | Some('v'), Some(digit), None) => digit.to_digit(16).map(|d| d as u16),
+        _ => None,
+    }
+}
+
+fn classify(operand: &Operand, line: usize) -> Result<Arg, AsmError> {
+    let text = operand.text.as_str();
+    if let Some(x) = register(text) {
+        return Ok(Arg::V(x));
+    }
+    if let Some((from, to)) = text.split_once('-') {
+        if let (Some(x), Some(y)) = (register(from.trim()), register(to.trim())) {
+            return Ok(Arg::VRange(x, y));
+        }
+    }
+    let upper = text.to_ascii_uppercase();
+    let compact: String = upper.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(match compact.as_str() {
+        "I" => Arg::I,
+        "[I]" => Arg::IndirectI,
+        "DT" => Arg::Dt,
+        "ST" => Arg::St,
+        "K" => Arg::K,
+        "F" => Arg::F,
+        "HF" => Arg::Hf,
+        "B" => Arg::B,
+        "R" => Arg::R,
+        _ if first_word(text).eq_ignore_ascii_case("LONG") => {
+            let value = text[4..].trim_start();
+            let column = operand.column + text[..text.len() - value.len()].chars().count();
+            Arg::Long(ExprParser::new(value, column, line).parse()?)
+        }
+        _ => Arg::Value(ExprParser::new(text, operand.column, line).parse()?),
+    })
+}
+
+fn encode(
+    stmt: &Stmt,
+    symbols: &HashMap<String, Symbol>,
+    here: i64,
+    out: &mut Vec<u8>,
+) -> Result<(), AsmError> {
+    let line = stmt.line;
+    //evaluates an operand and checks that it fits into the given range
+    let value = |expr: &Expr, column: usize, min: i64, max: i64, what: &str| {
+        let v = eval(expr, symbols, here, line, 0)?;
+        if v < min || v > max {
+            return Err(AsmError {
+                line,
+                column,
+                message: format!(
+                    "{} {} does not fit into {:#X}..={:#X}",
+                    what,
+                    v,
+                    min.max(0),
+                    max
+                ),
+            });
+        }
+        Ok(v)
+    };
+
+    match &stmt.kind {
+        StmtKind::Org(_) | StmtKind::Equ(..) => Ok(()),
+        StmtKind::Db(items) => {
+            for item in items {
+                match item {
+                    DataItem::Str(bytes) => out.extend_from_slice(bytes),
+                    DataItem::Expr(operand) => {
+                        let expr = ExprParser::new(&operand.text, operand.column, line).parse()?;
+                        let v = value(&expr, operand.column, -128, 0xFF, "byte")?;
+                        out.push(v as u8);
+                    }
+                }
+            }
+            Ok(())
+        }
+        StmtKind::Dw(items) => {
+            for operand in items {
+                let expr = ExprParser::new(&operand.text, operand.column, line).parse()?;
+                let v = value(&expr, operand.column, -0x8000, 0xFFFF, "word")?;
+                out.extend_from_slice(&(v as u16).to_be_bytes());
+            }
+            Ok(())
+        }
+        StmtKind::Instruction(mnemonic, operands) => {
+            let args = operands
+                .iter()
+                .map(|operand| classify(operand, line))
+                .collect::<Result<Vec<_>, _>>()?;
+            let col = |idx: usize| operands.get(idx).map_or(stmt.column, |o| o.column);
+            let byte = |expr: &Expr, idx: usize| {
+                value(expr, col(idx), -128, 0xFF, "byte").map(|v| v as u16 & 0xFF)
+            };
+            let addr = |expr: &Expr, idx: usize| {
+                value(expr, col(idx), 0, 0xFFF, "address").map(|v| v as u16)
+            };
+            let nibble =
+                |expr: &Expr, idx: usize| value(expr, col(idx), 0, 0xF, "nibble").map(|v| v as u16);
+
+            use Arg::*;
+            let op: u16 = match (mnemonic.as_str(), args.as_slice()) {
+                ("CLS", []) => 0x00E0,
+                ("RET", []) => 0x00EE,
+                ("SCR", []) => 0x00FB,
+                ("SCL", []) => 0x00FC,
+                ("EXIT", []) => 0x00FD,
+                ("LOW", []) => 0x00FE,
+                ("HIGH", []) => 0x00FF,
+                ("AUDIO", []) => 0xF002,
+                ("SCD", [Value(n)]) => 0x00C0 | nibble(n, 0)?,
+                ("SCU", [Value(n)]) => 0x00D0 | nibble(n, 0)?,
+                ("SYS", [Value(a)]) => addr(a, 0)?,
+                ("JP", [Value(a)]) => 0x1000 | addr(a, 0)?,
+                ("JP", [V(0), Value(a)]) => 0xB000 | addr(a, 1)?,
+                ("CALL", [Value(a)]) => 0x2000 | addr(a, 0)?,
+                ("SE", [V(x), Value(b)]) => 0x3000 | x << 8 | byte(b, 1)?,
+                ("SNE", [V(x), Value(b)]) => 0x4000 | x << 8 | byte(b, 1)?,
+                ("SE", [V(x), V(y)]) => 0x5000 | x << 8 | y << 4,
+                ("SNE", [V(x), V(y)]) => 0x9000 | x << 8 | y << 4,
+                ("LD", [IndirectI, VRange(x, y)]) => 0x5002 | x << 8 | y << 4,
+                ("LD", [VRange(x, y), IndirectI]) => 0x5003 | x << 8 | y << 4,
+                ("LD", [V(x), Value(b)]) => 0x6000 | x << 8 | byte(b, 1)?,
+                ("ADD", [V(x), Value(b)]) => 0x7000 | x << 8 | byte(b, 1)?,
+                ("LD", [V(x), V(y)]) => 0x8000 | x << 8 | y << 4,
+                ("OR", [V(x), V(y)]) => 0x8001 | x << 8 | y << 4,
+                ("AND", [V(x), V(y)]) => 0x8002 | x << 8 | y << 4,
+                ("XOR", [V(x), V(y)]) => 0x8003 | x << 8 | y << 4,
+                ("ADD", [V(x), V(y)]) => 0x8004 | x << 8 | y << 4,
+                ("SUB", [V(x), V(y)]) => 0x8005 | x << 8 | y << 4,
+                ("SHR", [V(x)]) => 0x8006 | x << 8 | x << 4,
+                ("SHR", [V(x), V(y)]) => 0x8006 | x << 8 | y << 4,
+                ("SUBN", [V(x), V(y)]) => 0x8007 | x << 8 | y << 4,
+                ("SHL", [V(x)]) => 0x800E | x << 8 | x << 4,
+                ("SHL", [V(x), V(y)]) => 0x800E | x << 8 | y << 4,
+                ("LD", [I, Long(a)]) => {
+                    let a = value(a, col(1), 0, 0xFFFF, "address")? as u16;
+                    out.extend_from_slice(&0xF000u16.to_be_bytes());
+                    out.extend_from_slice(&a.to_be_bytes());
+                    return Ok(());
+                }
+                ("LD", [I, Value(a)]) => 0xA000 | addr(a, 1)?,
+                ("RND", [V(x), Value(b)]) => 0xC000 | x << 8 | byte(b, 1)?,
+                ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | x << 8 | y << 4 | nibble(n, 2)?,
+                ("SKP", [V(x)]) => 0xE09E | x << 8,
+                ("SKNP", [V(x)]) => 0xE0A1 | x << 8,
+                ("PLANE", [Value(n)]) => 0xF001 | nibble(n, 0)? << 8,
+                ("LD", [V(x), Dt]) => 0xF007 | x << 8,
+                ("LD", [V(x), K]) => 0xF00A | x << 8,
+                ("LD", [Dt, V(x)]) => 0xF015 | x << 8,
+                ("LD", [St, V(x)]) => 0xF018 | x << 8,
+                ("ADD", [I, V(x)]) => 0xF01E | x << 8,
+                ("LD", [F, V(x)]) => 0xF029 | x << 8,
+                ("LD", [Hf, V(x)]) => 0xF030 | x << 8,
+                ("LD", [B, V(x)]) => 0xF033 | x << 8,
+                ("PITCH", [V(x)]) => 0xF03A | x << 8,
+                ("LD", [IndirectI, V(x)]) => 0xF055 | x << 8,
+                ("LD", [V(x), IndirectI]) => 0xF065 | x << 8,
+                ("LD", [R, V(x)]) => 0xF075 | x << 8,
+                ("LD", [V(x), R]) => 0xF085 | x << 8,
+                _ => {
+                    return Err(AsmError {
+                        line,
+                        column: stmt.column,
+                        message: format!("invalid instruction `{}` with these operands", mnemonic),
+                    })
+                }
+            };
+            out.extend_from_slice(&op.to_be_bytes());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{self, Syntax};
+    use crate::START_ADDR;
+
+    //disassembles, assembles the listing again and expects the same bytes
+    fn round_trip(rom: &[u8]) {
+        let lines = disasm::disassemble(rom, START_ADDR, Syntax::Cowgod);
+        let source = disasm::to_source(&lines);
+        let assembled =
+            assemble(&source, START_ADDR).unwrap_or_else(|err| panic!("{} in\n{}", err, source));
+        assert_eq!(assembled, rom, "in\n{}", source);
+    }
+
+    #[test]
+    fn every_opcode_round_trips() {
+        //the entry point is always decoded as code, valid or not
+        for op in 0..=0xFFFFu16 {
+            let mut rom = op.to_be_bytes().to_vec();
+            if op == 0xF000 {
+                rom.extend_from_slice(&[0x12, 0x34]);
+            }
+            round_trip(&rom);
+        }
+    }
+
+    #[test]
+    fn mixed_code_and_data_round_trips() {
+        #[rustfmt::skip]
+        let rom = [
+            0x00, 0xE0, //CLS
+            0xF0, 0x00, 0x02, 0x10, //LD I, LONG 0x0210
+            0x22, 0x0E, //CALL 0x20E
+            0x3A, 0x01, //SE VA, 0x01
+            0xF0, 0x00, 0xFF, 0xFE, //skipped long load
+            0x12, 0x00, //JP 0x200
+            0x00, 0xEE, //RET
+            0xF0, 0x90, 0x90, 0xF0, //data after the return
+            0xF0, 0x00, //trailing F000 without an operand
+        ];
+        round_trip(&rom);
+    }
+
+    #[test]
+    fn lone_long_load_is_listed_as_a_word() {
+        let lines = disasm::disassemble(&[0xF0, 0x00], START_ADDR, Syntax::Cowgod);
+        assert_eq!(lines[0].text, "DW 0xF000");
+        round_trip(&[0xF0, 0x00]);
+    }
+
+    #[test]
+    fn labels_and_constants_resolve_in_both_directions() {
+        let source = "
+            SPEED equ 2
+            start: LD V0, SPEED * 2
+                   LD I, sprite
+                   DRW V0, V1, sprite_end - sprite
+                   JP start
+            sprite: db 0xF0, %1001_0000
+            sprite_end:
+        ";
+        assert_eq!(
+            assemble(source, 0x200).unwrap(),
+            [0x60, 0x04, 0xA2, 0x08, 0xD0, 0x12, 0x12, 0x00, 0xF0, 0x90]
+        );
+    }
+
+    #[test]
+    fn org_pads_with_zeros() {
+        let mut expected = vec![0x00, 0xE0];
+        expected.resize(0x10, 0);
+        assert_eq!(assemble("CLS\norg 0x210\n", 0x200).unwrap(), expected);
+        expected.extend_from_slice(&[0x00, 0xE0]);
+        assert_eq!(assemble("CLS\norg 0x210\nCLS", 0x200).unwrap(), expected);
+        assert_eq!(error("CLS\norg 0x10001").2, "program does not fit into 64K");
+    }
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = assemble(source, START_ADDR).unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        let (line, column, message) = error("CLS\n  JP nowhere\n");
+        assert_eq!((line, column), (2, 6));
+        assert_eq!(message, "unknown symbol `nowhere`");
+
+        let (line, column, _) = error("  LD V0, 0x100");
+        assert_eq!((line, column), (1, 10));
+
+        let (line, column, _) = error("LD V0, 1 +");
+        assert_eq!((line, column), (1, 11));
+
+        let (line, column, _) = error("db 1,, 2");
+        assert_eq!((line, column), (1, 6));
+
+        let (line, column, _) = error("\n\n    FOO V0");
+        assert_eq!((line, column), (3, 5));
+    }
+
+    #[test]
+    fn columns_count_characters_on_non_ascii_lines() {
+        let (_, column, _) = error("db \"é\", 0x100");
+        assert_eq!(column, 9);
+        let (_, column, _) = error("db \"ü\", \"ö\", (1 +)");
+        assert_eq!(column, 18);
+    }
+
+    #[test]
+    fn reserved_names_cannot_be_labels() {
+        for name in [
+            "B", "f", "K", "R", "I", "dt", "ST", "HF", "long", "VA", "v0",
+        ] {
+            let (line, column, message) = error(&format!("CLS\n  {}: CLS", name));
+            assert_eq!((line, column), (2, 3));
+            assert_eq!(message, format!("`{}` is a reserved name", name));
+        }
+        let (_, _, message) = error("B equ 3");
+        assert_eq!(message, "`B` is a reserved name");
+        //names that only start like a keyword are fine
+        assert!(assemble("bb: JP bb", START_ADDR).is_ok());
+    }
+
+    #[test]
+    fn duplicate_labels_are_errors() {
+        let (line, column, message) = error("a: CLS\n   a: CLS");
+        assert_eq!((line, column), (2, 4));
+        assert_eq!(message, "`a` is already defined");
+    }
+}
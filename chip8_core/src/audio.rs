@@ -0,0 +1,161 @@
+use crate::{Emu, AUDIO_PATTERN_SIZE, DEFAULT_PITCH};
+
+//frames per second the renderer is fed at, the rate tick_timers is called at
+const FRAME_RATE: u32 = 60;
+//bits per second the XO-CHIP pattern plays at with the default pitch
+const PATTERN_RATE: f64 = 4000.0;
+const PATTERN_BITS: f64 = (AUDIO_PATTERN_SIZE * 8) as f64;
+//a quarter of full scale leaves headroom for mixing in an editor
+const AMPLITUDE: i16 = i16::MAX / 4;
+
+//turns the buzzer into 16 bit mono PCM without a sound card, for tests and recordings
+//
+//call render once per frame, after the frame's tick_timers: the buzzer sounded during the frame
+//if the sound timer was running when it ended. An XO-CHIP pattern loaded with F002 plays at the
+//rate set by FX3A, other programs get a square wave
+pub struct AudioRenderer {
+    sample_rate: u32,
+    tone: f64,
+    //where in the pattern (in bits) or the square wave (in periods) playback is
+    position: f64,
+    //sample_rate * frames not yet turned into samples, keeps rates like 44100 exact
+    carry: u64,
+}
+
+impl AudioRenderer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            tone: 440.0,
+            position: 0.0,
+            carry: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    //frequency of the square wave for programs without a pattern
+    pub fn set_tone(&mut self, hz: f64) {
+        self.tone = hz;
+    }
+
+    //samples for the given number of frames of the machine's current sound state
+    pub fn render(&mut self, emu: &Emu, frames: u32) -> Vec<i16> {
+        //widened so long renders at high rates cannot overflow
+        self.carry += self.sample_rate as u64 * frames as u64;
+        let count = (self.carry / FRAME_RATE as u64) as usize;
+        self.carry %= FRAME_RATE as u64;
+        if !emu.buzzed {
+            //every beep starts at the beginning of the wave
+            self.position = 0.0;
+            return vec![0; count];
+        }
+        let mut samples = Vec::with_capacity(count);
+        match emu.audio_pattern() {
+            Some(pattern) => {
+                let exponent = (emu.pitch() as f64 - DEFAULT_PITCH as f64) / 48.0;
+                let step = PATTERN_RATE * exponent.exp2() / self.sample_rate as f64;
+                for _ in 0..count {
+                    let bit = self.position as usize;
+                    let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                    samples.push(if high { AMPLITUDE } else { -AMPLITUDE });
+                    self.position = (self.position + step) % PATTERN_BITS;
+                }
+            }
+            None => {
+                let step = self.tone / self.sample_rate as f64;
+                for _ in 0..count {
+                    samples.push(if self.position < 0.5 {
+                        AMPLITUDE
+                    } else {
+                        -AMPLITUDE
+                    });
+                    self.position = (self.position + step) % 1.0;
+                }
+            }
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a machine that sounded the buzzer for the frame that just ended
+    fn buzzing(emu: &mut Emu) {
+        emu.set_sound_timer(2);
+        emu.tick_timers();
+    }
+
+    #[test]
+    fn frames_get_their_share_of_samples() {
+        let emu = Emu::new();
+        let mut renderer = AudioRenderer::new(44100);
+        assert_eq!(renderer.render(&emu, 1).len(), 735);
+        assert_eq!(renderer.render(&emu, 60).len(), 44100);
+        //half samples are carried over to the next frame
+        let mut renderer = AudioRenderer::new(22050);
+        let counts: Vec<usize> = (0..4).map(|_| renderer.render(&emu, 1).len()).collect();
+        assert_eq!(counts, [367, 368, 367, 368]);
+    }
+
+    #[test]
+    fn silent_without_the_sound_timer() {
+        let mut emu = Emu::new();
+        emu.tick_timers();
+        let samples = AudioRenderer::new(44100).render(&emu, 2);
+        assert_eq!(samples.len(), 1470);
+        assert!(samples.iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn square_wave_without_a_pattern() {
+        let mut emu = Emu::new();
+        buzzing(&mut emu);
+        let mut renderer = AudioRenderer::new(4000);
+        renderer.set_tone(500.0);
+        let samples = renderer.render(&emu, 1);
+        //8 samples a period, half of them high
+        assert_eq!(
+            samples[..8],
+            [
+                AMPLITUDE, AMPLITUDE, AMPLITUDE, AMPLITUDE, -AMPLITUDE, -AMPLITUDE, -AMPLITUDE,
+                -AMPLITUDE
+            ]
+        );
+        assert_eq!(samples[8..16], samples[..8]);
+    }
+
+    #[test]
+    fn pattern_plays_bit_by_bit() {
+        #[rustfmt::skip]
+        let mut program = vec![
+            0xA2, 0x06, //LD I, 0x206
+            0xF0, 0x02, //AUDIO
+            0x12, 0x04, //JP 0x204
+        ];
+        let pattern: Vec<u8> = (0..AUDIO_PATTERN_SIZE as u8).map(|i| 0xF0 ^ i).collect();
+        program.extend_from_slice(&pattern);
+        let mut emu = Emu::with_quirks(crate::Quirks::xo_chip());
+        emu.load(&program).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        buzzing(&mut emu);
+        //at the default pitch the pattern plays a bit per sample at 4000 Hz
+        let mut renderer = AudioRenderer::new(4000);
+        let mut samples = renderer.render(&emu, 1);
+        samples.extend(renderer.render(&emu, 1));
+        let bits: Vec<i16> = pattern
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| byte & (0x80 >> bit)))
+            .map(|bit| if bit != 0 { AMPLITUDE } else { -AMPLITUDE })
+            .collect();
+        assert_eq!(samples.len(), 133);
+        assert_eq!(samples[..bits.len()], bits[..]);
+        //and starts over after the last bit
+        assert_eq!(samples[bits.len()..], bits[..133 - bits.len()]);
+    }
+}
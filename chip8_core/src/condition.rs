@@ -0,0 +1,510 @@
+//expressions for conditional breakpoints, e.g. `V3 == 0x10 && I > 0x300`
+//
+//operands are V0-VF, I, DT, ST, PC, SP, numbers (decimal or 0x hex) and [addr] for a byte of RAM
+//operators from loosest to tightest: || && == != < <= > >= | ^ & << >> + - * / % and unary ! - ~
+//comparisons give 1 or 0 and a condition holds when it evaluates to anything but 0
+
+use crate::NUM_REGS;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConditionError {
+    //1-based position in the expression
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+//the machine state a condition can look at
+pub(crate) struct Machine<'a> {
+    pub(crate) v_reg: &'a [u8; NUM_REGS],
+    pub(crate) i_reg: u16,
+    pub(crate) dt: u8,
+    pub(crate) st: u8,
+    pub(crate) pc: u16,
+    pub(crate) sp: u16,
+    pub(crate) memory: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    V(usize),
+    I,
+    Dt,
+    St,
+    Pc,
+    Sp,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum UnaryOp {
+    Not,
+    Neg,
+    Invert,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    fn from_token(token: &str) -> Option<(BinaryOp, u8)> {
+        Some(match token {
+            "||" => (BinaryOp::Or, 1),
+            "&&" => (BinaryOp::And, 2),
+            "==" => (BinaryOp::Eq, 3),
+            "!=" => (BinaryOp::Ne, 3),
+            "<" => (BinaryOp::Lt, 4),
+            "<=" => (BinaryOp::Le, 4),
+            ">" => (BinaryOp::Gt, 4),
+            ">=" => (BinaryOp::Ge, 4),
+            "|" => (BinaryOp::BitOr, 5),
+            "^" => (BinaryOp::BitXor, 6),
+            "&" => (BinaryOp::BitAnd, 7),
+            "<<" => (BinaryOp::Shl, 8),
+            ">>" => (BinaryOp::Shr, 8),
+            "+" => (BinaryOp::Add, 9),
+            "-" => (BinaryOp::Sub, 9),
+            "*" => (BinaryOp::Mul, 10),
+            "/" => (BinaryOp::Div, 10),
+            "%" => (BinaryOp::Rem, 10),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Num(i64),
+    Reg(Operand),
+    //a byte of RAM, 0 outside of the address space
+    Mem(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, machine: &Machine) -> i64 {
+        match self {
+            Node::Num(value) => *value,
+            Node::Reg(operand) => match *operand {
+                Operand::V(x) => machine.v_reg[x] as i64,
+                Operand::I => machine.i_reg as i64,
+                Operand::Dt => machine.dt as i64,
+                Operand::St => machine.st as i64,
+                Operand::Pc => machine.pc as i64,
+                Operand::Sp => machine.sp as i64,
+            },
+            Node::Mem(addr) => usize::try_from(addr.eval(machine))
+                .ok()
+                .and_then(|addr| machine.memory.get(addr))
+                .map_or(0, |&byte| byte as i64),
+            Node::Unary(op, inner) => {
+                let value = inner.eval(machine);
+                match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Invert => !value,
+                }
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(machine);
+                //|| and && only look at the right side when they have to
+                match op {
+                    BinaryOp::Or if lhs != 0 => return 1,
+                    BinaryOp::And if lhs == 0 => return 0,
+                    _ => (),
+                }
+                let rhs = rhs.eval(machine);
+                match op {
+                    BinaryOp::Or | BinaryOp::And => (rhs != 0) as i64,
+                    BinaryOp::Eq => (lhs == rhs) as i64,
+                    BinaryOp::Ne => (lhs != rhs) as i64,
+                    BinaryOp::Lt => (lhs < rhs) as i64,
+                    BinaryOp::Le => (lhs <= rhs) as i64,
+                    BinaryOp::Gt => (lhs > rhs) as i64,
+                    BinaryOp::Ge => (lhs >= rhs) as i64,
+                    BinaryOp::BitOr => lhs | rhs,
+                    BinaryOp::BitXor => lhs ^ rhs,
+                    BinaryOp::BitAnd => lhs & rhs,
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    //a breakpoint cannot fail at runtime, dividing by zero gives 0
+                    BinaryOp::Div => lhs.checked_div(rhs).unwrap_or(0),
+                    BinaryOp::Rem => lhs.checked_rem(rhs).unwrap_or(0),
+                }
+            }
+        }
+    }
+}
+
+//a parsed expression, keeps its source text for display
+#[derive(Clone, Debug)]
+pub struct Condition {
+    text: String,
+    root: Node,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, ConditionError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            end: text.len() + 1,
+        };
+        let root = parser.binary(0)?;
+        if let Some((token, column)) = parser.tokens.get(parser.pos) {
+            return Err(ConditionError {
+                column: *column,
+                message: format!("unexpected `{}`", token),
+            });
+        }
+        Ok(Self {
+            text: text.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub(crate) fn eval(&self, machine: &Machine) -> i64 {
+        self.root.eval(machine)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+//splits into words, numbers and operators with their 1-based columns
+fn tokenize(text: &str) -> Result<Vec<(String, usize)>, ConditionError> {
+    const TWO_CHAR: [&str; 8] = ["||", "&&", "==", "!=", "<=", ">=", "<<", ">>"];
+    let mut tokens = Vec::new();
+    let bytes = text.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos] as char;
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+        let start = pos;
+        if c.is_ascii_alphanumeric() || c == '_' {
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                pos += 1;
+            }
+        } else if text
+            .get(pos..pos + 2)
+            .is_some_and(|pair| TWO_CHAR.contains(&pair))
+        {
+            pos += 2;
+        } else if "<>|^&+-*/%!~()[]".contains(c) {
+            pos += 1;
+        } else {
+            return Err(ConditionError {
+                column: start + 1,
+                message: format!("unexpected `{}`", c),
+            });
+        }
+        tokens.push((text[start..pos].to_string(), start + 1));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(String, usize)>,
+    pos: usize,
+    //column reported for errors at the end of the text
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|(token, _)| token.as_str())
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |(_, column)| *column)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, ConditionError> {
+        Err(ConditionError {
+            column: self.column(),
+            message,
+        })
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, ConditionError> {
+        let mut lhs = self.unary()?;
+        while let Some((op, precedence)) = self.peek().and_then(BinaryOp::from_token) {
+            if precedence <= min_precedence {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(precedence)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, ConditionError> {
+        let op = match self.peek() {
+            Some("!") => UnaryOp::Not,
+            Some("-") => UnaryOp::Neg,
+            Some("~") => UnaryOp::Invert,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn expect(&mut self, close: &str) -> Result<(), ConditionError> {
+        if self.peek() != Some(close) {
+            return self.error(format!("missing `{}`", close));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn primary(&mut self) -> Result<Node, ConditionError> {
+        let Some(token) = self.peek().map(str::to_string) else {
+            return self.error("expression ends early".to_string());
+        };
+        match token.as_str() {
+            "(" => {
+                self.pos += 1;
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                return Ok(inner);
+            }
+            "[" => {
+                self.pos += 1;
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                return Ok(Node::Mem(Box::new(addr)));
+            }
+            _ => (),
+        }
+        let upper = token.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Some(Operand::I),
+            "DT" => Some(Operand::Dt),
+            "ST" => Some(Operand::St),
+            "PC" => Some(Operand::Pc),
+            "SP" => Some(Operand::Sp),
+            _ => upper
+                .strip_prefix('V')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+                .map(Operand::V),
+        };
+        let node = match operand {
+            Some(operand) => Node::Reg(operand),
+            None => {
+                let value = match upper.strip_prefix("0X") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => token.parse(),
+                };
+                match value {
+                    Ok(value) => Node::Num(value),
+                    Err(_) => return self.error(format!("unknown operand `{}`", token)),
+                }
+            }
+        };
+        self.pos += 1;
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Access, Emu, StopReason, Watchpoint};
+
+    //V0-VF hold their own index, RAM at 0x300 holds 0xAB
+    fn eval(text: &str) -> i64 {
+        let v_reg: [u8; NUM_REGS] = std::array::from_fn(|x| x as u8);
+        let mut memory = vec![0; 0x1000];
+        memory[0x300] = 0xAB;
+        let machine = Machine {
+            v_reg: &v_reg,
+            i_reg: 0x300,
+            dt: 5,
+            st: 6,
+            pc: 0x204,
+            sp: 2,
+            memory: &memory,
+        };
+        let condition = Condition::parse(text).unwrap_or_else(|err| panic!("{}: {}", text, err));
+        condition.eval(&machine)
+    }
+
+    fn error(text: &str) -> (usize, String) {
+        let err = Condition::parse(text).unwrap_err();
+        (err.column, err.message)
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        let cases = [
+            //comparisons are looser than the bitwise operators
+            ("6 & 3 == 2", 1),
+            ("1 | 2 == 3", 1),
+            ("1 < 2 == 1", 1),
+            ("1 || 0 && 0", 1),
+            ("1 | 2 ^ 3 & 1", 3),
+            ("1 << 2 + 1", 8),
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("7 - 2 - 1", 4),
+            ("-2 * 3", -6),
+            ("!0 + !5", 1),
+            ("~0", -1),
+            ("7 / 0 + 7 % 0", 0),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(eval(text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn literals_are_decimal_or_hex() {
+        assert_eq!(eval("31"), 31);
+        assert_eq!(eval("0x1F"), 31);
+        assert_eq!(eval("0X1f"), 31);
+        assert_eq!(eval("0x10 == 16"), 1);
+    }
+
+    #[test]
+    fn operands_read_the_machine() {
+        let cases = [
+            ("V3", 3),
+            ("vf", 15),
+            ("I", 0x300),
+            ("dt", 5),
+            ("ST", 6),
+            ("PC", 0x204),
+            ("sp", 2),
+            ("[I]", 0xAB),
+            ("[0x300 + V1 - 1]", 0xAB),
+            ("[0x301]", 0),
+            ("[0x10000]", 0),
+            ("[-1]", 0),
+            ("V3 == 3 && [I] > 0x80", 1),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(eval(text), expected, "{}", text);
+        }
+        assert_eq!(Condition::parse("  V0 == 1 ").unwrap().text(), "V0 == 1");
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_column() {
+        let cases = [
+            ("V0 $ 1", 4, "unexpected `$`"),
+            ("V0 ==", 6, "expression ends early"),
+            ("", 1, "expression ends early"),
+            ("(V0 + 1", 8, "missing `)`"),
+            ("[I", 3, "missing `]`"),
+            ("VG == 1", 1, "unknown operand `VG`"),
+            ("0x", 1, "unknown operand `0x`"),
+            (
+                "99999999999999999999",
+                1,
+                "unknown operand `99999999999999999999`",
+            ),
+            ("V0 V1", 4, "unexpected `V1`"),
+        ];
+        for (text, column, message) in cases {
+            assert_eq!(error(text), (column, message.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access() {
+        #[rustfmt::skip]
+        let program = [
+            0xA3, 0x00, //LD I, 0x300
+            0xF0, 0x65, //LD V0, [I]
+            0xF0, 0x55, //LD [I], V0
+        ];
+        let mut emu = Emu::new();
+        emu.load(&program).unwrap();
+        emu.add_watchpoint(Watchpoint::new(0x300..=0x300, true, false));
+        emu.add_watchpoint(Watchpoint::new(0x2FF..=0x300, false, true));
+        emu.tick().unwrap();
+        assert!(!emu.is_stopped());
+        emu.tick().unwrap();
+        assert_eq!(
+            emu.stop_reason(),
+            Some(StopReason::Watchpoint {
+                pc: 0x202,
+                addr: 0x300,
+                access: Access::Read
+            })
+        );
+        assert_eq!(emu.pc(), 0x204);
+        emu.resume();
+        emu.tick().unwrap();
+        assert_eq!(
+            emu.stop_reason(),
+            Some(StopReason::Watchpoint {
+                pc: 0x204,
+                addr: 0x300,
+                access: Access::Write
+            })
+        );
+        assert_eq!(emu.pc(), 0x206);
+    }
+
+    #[test]
+    fn conditional_breakpoints_stop_when_the_condition_holds() {
+        #[rustfmt::skip]
+        let program = [
+            0x70, 0x01, //ADD V0, 1
+            0x12, 0x00, //JP 0x200
+        ];
+        let mut emu = Emu::new();
+        emu.load(&program).unwrap();
+        emu.add_conditional_breakpoint(0x202, Condition::parse("V0 == 3").unwrap());
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.stop_reason(), Some(StopReason::Breakpoint(0x202)));
+        assert_eq!(emu.v_reg()[0], 3);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::condition::{Condition, Machine};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+//kind of RAM access that hit a watchpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+//stops the machine when the program reads or writes an address in the range
+//instruction fetches are not reads, only data accesses like FX65, DXYN or FX33 are
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn new(range: RangeInclusive<u16>, read: bool, write: bool) -> Self {
+        Self { range, read, write }
+    }
+
+    fn matches(&self, addr: u16, access: Access) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        kind && self.range.contains(&addr)
+    }
+}
+
+//why the debugger is holding the machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    //Emu::pause was called
+    Paused,
+    //execution reached a breakpoint, the instruction at the address has not run yet
+    Breakpoint(u16),
+    //a step or run_to finished
+    Step,
+    //the instruction at pc accessed addr, it has already finished
+    Watchpoint { pc: u16, addr: u16, access: Access },
+}
+
+//what tick does when it is called
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RunMode {
+    Running,
+    Stopped(StopReason),
+    //run until the call returns, sp tells the return from a recursive call apart
+    StepOver { addr: u16, sp: u16 },
+    //run until a RET takes sp below this
+    StepOut { sp: u16 },
+    RunTo(u16),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Debugger {
+    //breakpoints only stop when their condition, if any, holds
+    pub(crate) breakpoints: BTreeMap<u16, Option<Condition>>,
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    pub(crate) mode: RunMode,
+    //execution resumes at this address, its breakpoint was already reported
+    pub(crate) resume_at: Option<u16>,
+    //address of the instruction that is running, for watchpoint hits
+    instruction: u16,
+    //first watchpoint hit by the running instruction, reported once it finishes
+    hit: Option<StopReason>,
+}
+
+impl Debugger {
+    pub(crate) fn new() -> Self {
+        Self {
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            mode: RunMode::Running,
+            resume_at: None,
+            instruction: 0,
+            hit: None,
+        }
+    }
+
+    //starts running from pc without stopping at a breakpoint there right away
+    pub(crate) fn resume(&mut self, mode: RunMode, pc: u16) {
+        self.mode = mode;
+        self.resume_at = Some(pc);
+    }
+
+    //called before every instruction, true when the machine has to hold at pc
+    pub(crate) fn check_stop(&mut self, machine: &Machine) -> bool {
+        let (pc, sp) = (machine.pc, machine.sp);
+        if let RunMode::Stopped(_) = self.mode {
+            return true;
+        }
+        self.instruction = pc;
+        //the first instruction after resuming always runs, even for run_to(pc)
+        if self.resume_at.take() == Some(pc) {
+            return false;
+        }
+        let reached = match self.mode {
+            RunMode::StepOver { addr, sp: call_sp } => pc == addr && sp == call_sp,
+            RunMode::StepOut { sp: call_sp } => sp < call_sp,
+            RunMode::RunTo(addr) => pc == addr,
+            _ => false,
+        };
+        let breakpoint = match self.breakpoints.get(&pc) {
+            Some(Some(condition)) => condition.eval(machine) != 0,
+            Some(None) => true,
+            None => false,
+        };
+        if reached {
+            self.mode = RunMode::Stopped(StopReason::Step);
+        } else if breakpoint {
+            self.mode = RunMode::Stopped(StopReason::Breakpoint(pc));
+        }
+        matches!(self.mode, RunMode::Stopped(_))
+    }
+
+    //called by the RAM accessors for every data access
+    pub(crate) fn check_access(&mut self, addr: usize, access: Access) {
+        if self.watchpoints.is_empty() || self.hit.is_some() {
+            return;
+        }
+        let addr = addr as u16;
+        if self
+            .watchpoints
+            .iter()
+            .any(|watch| watch.matches(addr, access))
+        {
+            self.hit = Some(StopReason::Watchpoint {
+                pc: self.instruction,
+                addr,
+                access,
+            });
+        }
+    }
+
+    //called after every instruction, stops on the watchpoint it hit
+    pub(crate) fn finish_instruction(&mut self) {
+        if let Some(hit) = self.hit.take() {
+            self.mode = RunMode::Stopped(hit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Emu, StopReason};
+
+    fn emu(program: &[u8]) -> Emu {
+        let mut emu = Emu::new();
+        emu.load(program).unwrap();
+        emu
+    }
+
+    //a subroutine at 0x206 that is called from 0x200
+    #[rustfmt::skip]
+    const CALL: [u8; 12] = [
+        0x22, 0x06, //CALL 0x206
+        0x61, 0x01, //LD V1, 1
+        0x12, 0x04, //JP 0x204
+        0x60, 0x05, //LD V0, 5
+        0x70, 0x01, //ADD V0, 1
+        0x00, 0xEE, //RET
+    ];
+
+    #[test]
+    fn breakpoint_stops_before_the_instruction() {
+        #[rustfmt::skip]
+        let mut emu = emu(&[
+            0x60, 0x05, //LD V0, 5
+            0x61, 0x07, //LD V1, 7
+        ]);
+        emu.add_breakpoint(0x202);
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.stop_reason(), Some(StopReason::Breakpoint(0x202)));
+        assert_eq!(emu.pc(), 0x202);
+        assert_eq!(emu.v_reg()[..2], [5, 0]);
+        emu.step_into().unwrap();
+        assert_eq!(emu.stop_reason(), Some(StopReason::Step));
+        assert_eq!(emu.v_reg()[1], 7);
+    }
+
+    #[test]
+    fn resuming_runs_the_instruction_at_the_breakpoint() {
+        #[rustfmt::skip]
+        let mut emu = emu(&[
+            0x70, 0x01, //ADD V0, 1
+            0x12, 0x00, //JP 0x200
+        ]);
+        emu.add_breakpoint(0x200);
+        emu.tick().unwrap();
+        assert_eq!(emu.stop_reason(), Some(StopReason::Breakpoint(0x200)));
+        assert_eq!(emu.v_reg()[0], 0);
+        emu.resume();
+        emu.tick().unwrap();
+        assert!(!emu.is_stopped());
+        assert_eq!(emu.v_reg()[0], 1);
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        assert_eq!(emu.stop_reason(), Some(StopReason::Breakpoint(0x200)));
+        assert_eq!(emu.v_reg()[0], 1);
+    }
+
+    #[test]
+    fn step_over_runs_a_whole_call() {
+        let mut emu = emu(&CALL);
+        emu.pause();
+        emu.step_over().unwrap();
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.stop_reason(), Some(StopReason::Step));
+        assert_eq!(emu.pc(), 0x202);
+        assert_eq!(emu.sp(), 0);
+        assert_eq!(emu.v_reg()[..2], [6, 0]);
+    }
+
+    #[test]
+    fn step_into_enters_a_call() {
+        let mut emu = emu(&CALL);
+        emu.pause();
+        emu.step_into().unwrap();
+        assert_eq!(emu.stop_reason(), Some(StopReason::Step));
+        assert_eq!(emu.pc(), 0x206);
+        assert_eq!(emu.sp(), 1);
+    }
+
+    #[test]
+    fn breakpoint_inside_a_call_stops_step_over() {
+        let mut emu = emu(&CALL);
+        emu.add_breakpoint(0x208);
+        emu.pause();
+        emu.step_over().unwrap();
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.stop_reason(), Some(StopReason::Breakpoint(0x208)));
+        assert_eq!(emu.v_reg()[0], 5);
+    }
+
+    #[test]
+    fn pause_holds_until_resumed() {
+        let mut emu = emu(&CALL);
+        emu.pause();
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.stop_reason(), Some(StopReason::Paused));
+        assert_eq!(emu.pc(), 0x200);
+        emu.resume();
+        emu.tick().unwrap();
+        assert!(!emu.is_stopped());
+        assert_eq!(emu.pc(), 0x206);
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+
+//mnemonic style of the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    //Cowgod's technical reference, e.g. `LD V3, 0x1F` and `DRW V0, V1, 5`
+    Cowgod,
+    //Octo assembly language, e.g. `v3 := 0x1F` and `sprite v0 v1 5`
+    Octo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    Code,
+    Data,
+}
+
+//one line of a listing: an instruction or a run of bytes that is never executed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: String = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:04X}: {:<16} {}", self.addr, hex, self.text)
+    }
+}
+
+//bytes per data line
+const DATA_PER_LINE: usize = 8;
+
+//how control leaves an instruction, used to tell code from data
+enum Flow {
+    //falls through to the next instruction
+    Next,
+    //may or may not skip the next instruction
+    Skip,
+    //1NNN, never falls through
+    Jump(u16),
+    //2NNN, comes back to the next instruction
+    Call(u16),
+    //RET, EXIT, BNNN and invalid opcodes: the next instruction is not reached from here
+    Stop,
+}
+
+fn flow(op: u16) -> Flow {
+    let digit1 = (op & 0xF000) >> 12;
+    let digit4 = op & 0x000F;
+    match (digit1, op, digit4) {
+        (0, 0x00EE, _) | (0, 0x00FD, _) | (0xB, _, _) => Flow::Stop,
+        (1, _, _) => Flow::Jump(op & 0xFFF),
+        (2, _, _) => Flow::Call(op & 0xFFF),
+        (3, _, _) | (4, _, _) | (5, _, 0) | (9, _, 0) => Flow::Skip,
+        (0xE, _, _) if op & 0xFF == 0x9E || op & 0xFF == 0xA1 => Flow::Skip,
+        _ if decode(op, Syntax::Cowgod).is_some() => Flow::Next,
+        _ => Flow::Stop,
+    }
+}
+
+//length of the instruction starting with op, F000 NNNN carries its operand in the next word
+fn instruction_len(op: u16) -> usize {
+    if op == 0xF000 {
+        4
+    } else {
+        2
+    }
+}
+
+fn word_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(((*data.get(offset)? as u16) << 8) | *data.get(offset + 1)? as u16)
+}
+
+//turns a single opcode into a mnemonic, None if it is not an instruction
+//F000 needs the word after it, on its own it is printed as data, see decode_at for the full form
+pub fn decode(op: u16, syntax: Syntax) -> Option<String> {
+    let digit1 = (op & 0xF000) >> 12;
+    let digit2 = (op & 0x0F00) >> 8;
+    let digit3 = (op & 0x00F0) >> 4;
+    let digit4 = op & 0x000F;
+    let nnn = op & 0xFFF;
+    let nn = op & 0xFF;
+    let (x, y) = (digit2, digit3);
+
+    let text = match syntax {
+        Syntax::Cowgod => match (digit1, digit2, digit3, digit4) {
+            (0, 0, 0xC, n) => format!("SCD {}", n),
+            (0, 0, 0xD, n) => format!("SCU {}", n),
+            (0, 0, 0xE, 0) => "CLS".to_string(),
+            (0, 0, 0xE, 0xE) => "RET".to_string(),
+            (0, 0, 0xF, 0xB) => "SCR".to_string(),
+            (0, 0, 0xF, 0xC) => "SCL".to_string(),
+            (0, 0, 0xF, 0xD) => "EXIT".to_string(),
+            (0, 0, 0xF, 0xE) => "LOW".to_string(),
+            (0, 0, 0xF, 0xF) => "HIGH".to_string(),
+            (0, _, _, _) => format!("SYS {:#05X}", nnn),
+            (1, _, _, _) => format!("JP {:#05X}", nnn),
+            (2, _, _, _) => format!("CALL {:#05X}", nnn),
+            (3, _, _, _) => format!("SE V{:X}, {:#04X}", x, nn),
+            (4, _, _, _) => format!("SNE V{:X}, {:#04X}", x, nn),
+            (5, _, _, 0) => format!("SE V{:X}, V{:X}", x, y),
+            (5, _, _, 2) => format!("LD [I], V{:X} - V{:X}", x, y),
+            (5, _, _, 3) => format!("LD V{:X} - V{:X}, [I]", x, y),
+            (6, _, _, _) => format!("LD V{:X}, {:#04X}", x, nn),
+            (7, _, _, _) => format!("ADD V{:X}, {:#04X}", x, nn),
+            (8, _, _, 0) => format!("LD V{:X}, V{:X}", x, y),
+            (8, _, _, 1) => format!("OR V{:X}, V{:X}", x, y),
+            (8, _, _, 2) => format!("AND V{:X}, V{:X}", x, y),
+            (8, _, _, 3) => format!("XOR V{:X}, V{:X}", x, y),
+            (8, _, _, 4) => format!("ADD V{:X}, V{:X}", x, y),
+            (8, _, _, 5) => format!("SUB V{:X}, V{:X}", x, y),
+            (8, _, _, 6) => format!("SHR V{:X}, V{:X}", x, y),
+            (8, _, _, 7) => format!("SUBN V{:X}, V{:X}", x, y),
+            (8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+            (9, _, _, 0) => format!("SNE V{:X}, V{:X}", x, y),
+            (0xA, _, _, _) => format!("LD I, {:#05X}", nnn),
+            (0xB, _, _, _) => format!("JP V0, {:#05X}", nnn),
+            (0xC, _, _, _) => format!("RND V{:X}, {:#04X}", x, nn),
+            (0xD, _, _, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            (0xE, _, 9, 0xE) => format!("SKP V{:X}", x),
+            (0xE, _, 0xA, 1) => format!("SKNP V{:X}", x),
+            (0xF, 0, 0, 0) => "DW 0xF000".to_string(),
+            (0xF, n, 0, 1) => format!("PLANE {}", n),
+            (0xF, 0, 0, 2) => "AUDIO".to_string(),
+            (0xF, _, 0, 7) => format!("LD V{:X}, DT", x),
+            (0xF, _, 0, 0xA) => format!("LD V{:X}, K", x),
+            (0xF, _, 1, 5) => format!("LD DT, V{:X}", x),
+            (0xF, _, 1, 8) => format!("LD ST, V{:X}", x),
+            (0xF, _, 1, 0xE) => format!("ADD I, V{:X}", x),
+            (0xF, _, 2, 9) => format!("LD F, V{:X}", x),
+            (0xF, _, 3, 0) => format!("LD HF, V{:X}", x),
+            (0xF, _, 3, 3) => format!("LD B, V{:X}", x),
+            (0xF, _, 3, 0xA) => format!("PITCH V{:X}", x),
+            (0xF, _, 5, 5) => format!("LD [I], V{:X}", x),
+            (0xF, _, 6, 5) => format!("LD V{:X}, [I]", x),
+            (0xF, _, 7, 5) => format!("LD R, V{:X}", x),
+            (0xF, _, 8, 5) => format!("LD V{:X}, R", x),
+            _ => return None,
+        },
+        Syntax::Octo => match (digit1, digit2, digit3, digit4) {
+            (0, 0, 0xC, n) => format!("scroll-down {}", n),
+            (0, 0, 0xD, n) => format!("scroll-up {}", n),
+            (0, 0, 0xE, 0) => "clear".to_string(),
+            (0, 0, 0xE, 0xE) => "return".to_string(),
+            (0, 0, 0xF, 0xB) => "scroll-right".to_string(),
+            (0, 0, 0xF, 0xC) => "scroll-left".to_string(),
+            (0, 0, 0xF, 0xD) => "exit".to_string(),
+            (0, 0, 0xF, 0xE) => "lores".to_string(),
+            (0, 0, 0xF, 0xF) => "hires".to_string(),
+            (0, _, _, _) => format!("native {:#05X}", nnn),
+            (1, _, _, _) => format!("jump {:#05X}", nnn),
+            (2, _, _, _) => format!(":call {:#05X}", nnn),
+            //Octo conditions say when the next instruction runs, the opposite of the skip
+            (3, _, _, _) => format!("if v{:x} != {:#04X} then", x, nn),
+            (4, _, _, _) => format!("if v{:x} == {:#04X} then", x, nn),
+            (5, _, _, 0) => format!("if v{:x} != v{:x} then", x, y),
+            (5, _, _, 2) => format!("save v{:x} - v{:x}", x, y),
+            (5, _, _, 3) => format!("load v{:x} - v{:x}", x, y),
+            (6, _, _, _) => format!("v{:x} := {:#04X}", x, nn),
+            (7, _, _, _) => format!("v{:x} += {:#04X}", x, nn),
+            (8, _, _, 0) => format!("v{:x} := v{:x}", x, y),
+            (8, _, _, 1) => format!("v{:x} |= v{:x}", x, y),
+            (8, _, _, 2) => format!("v{:x} &= v{:x}", x, y),
+            (8, _, _, 3) => format!("v{:x} ^= v{:x}", x, y),
+            (8, _, _, 4) => format!("v{:x} += v{:x}", x, y),
+            (8, _, _, 5) => format!("v{:x} -= v{:x}", x, y),
+            (8, _, _, 6) => format!("v{:x} >>= v{:x}", x, y),
+            (8, _, _, 7) => format!("v{:x} =- v{:x}", x, y),
+            (8, _, _, 0xE) => format!("v{:x} <<= v{:x}", x, y),
+            (9, _, _, 0) => format!("if v{:x} == v{:x} then", x, y),
+            (0xA, _, _, _) => format!("i := {:#05X}", nnn),
+            (0xB, _, _, _) => format!("jump0 {:#05X}", nnn),
+            (0xC, _, _, _) => format!("v{:x} := random {:#04X}", x, nn),
+            (0xD, _, _, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            (0xE, _, 9, 0xE) => format!("if v{:x} -key then", x),
+            (0xE, _, 0xA, 1) => format!("if v{:x} key then", x),
+            (0xF, 0, 0, 0) => "0xF0 0x00".to_string(),
+            (0xF, n, 0, 1) => format!("plane {}", n),
+            (0xF, 0, 0, 2) => "audio".to_string(),
+            (0xF, _, 0, 7) => format!("v{:x} := delay", x),
+            (0xF, _, 0, 0xA) => format!("v{:x} := key", x),
+            (0xF, _, 1, 5) => format!("delay := v{:x}", x),
+            (0xF, _, 1, 8) => format!("buzzer := v{:x}", x),
+            (0xF, _, 1, 0xE) => format!("i += v{:x}", x),
+            (0xF, _, 2, 9) => format!("i := hex v{:x}", x),
+            (0xF, _, 3, 0) => format!("i := bighex v{:x}", x),
+            (0xF, _, 3, 3) => format!("bcd v{:x}", x),
+            (0xF, _, 3, 0xA) => format!("pitch := v{:x}", x),
+            (0xF, _, 5, 5) => format!("save v{:x}", x),
+            (0xF, _, 6, 5) => format!("load v{:x}", x),
+            (0xF, _, 7, 5) => format!("saveflags v{:x}", x),
+            (0xF, _, 8, 5) => format!("loadflags v{:x}", x),
+            _ => return None,
+        },
+    };
+    Some(text)
+}
+
+//full mnemonic of the instruction at offset, including the operand of F000 NNNN
+pub(crate) fn decode_at(data: &[u8], offset: usize, syntax: Syntax) -> Option<(String, usize)> {
+    let op = word_at(data, offset)?;
+    let len = instruction_len(op);
+    //an F000 at the very end has no operand and comes out as data through decode
+    if let Some(long) = word_at(data, offset + 2).filter(|_| len == 4) {
+        let text = match syntax {
+            Syntax::Cowgod => format!("LD I, LONG {:#06X}", long),
+            Syntax::Octo => format!("i := long {:#06X}", long),
+        };
+        return Some((text, len));
+    }
+    decode(op, syntax).map(|text| (text, 2))
+}
+
+//marks every offset reachable from the entry point as the start of an instruction
+//self-modifying code and computed jumps (BNNN) cannot be followed, what they reach shows up as data
+fn trace_code(data: &[u8], base: u16, entry: u16) -> Vec<bool> {
+    let mut code = vec![false; data.len()];
+    let mut pending = vec![entry];
+    while let Some(addr) = pending.pop() {
+        let Some(offset) = (addr as usize).checked_sub(base as usize) else {
+            continue;
+        };
+        if offset >= data.len() || code[offset] {
+            continue;
+        }
+        let Some((_, len)) = decode_at(data, offset, Syntax::Cowgod) else {
+            continue;
+        };
+        code[offset] = true;
+        let op = word_at(data, offset).unwrap_or_default();
+        let next = addr.wrapping_add(len as u16);
+        match flow(op) {
+            Flow::Next => pending.push(next),
+            Flow::Skip => {
+                pending.push(next);
+                //the skipped instruction may itself be 4 bytes long
+                let skipped_len = word_at(data, offset + len).map_or(2, instruction_len);
+                pending.push(next.wrapping_add(skipped_len as u16));
+            }
+            Flow::Jump(target) => pending.push(target),
+            Flow::Call(target) => {
+                pending.push(target);
+                pending.push(next);
+            }
+            Flow::Stop => (),
+        }
+    }
+    code
+}
+
+//disassembles a ROM loaded at base, starting execution at base as Emu::load_at does
+pub fn disassemble(data: &[u8], base: u16, syntax: Syntax) -> Vec<Line> {
+    let code = trace_code(data, base, base);
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let addr = base.wrapping_add(offset as u16);
+        if code[offset] {
+            if let Some((text, len)) = decode_at(data, offset, syntax) {
+                lines.push(Line {
+                    addr,
+                    bytes: data[offset..offset + len].to_vec(),
+                    kind: LineKind::Code,
+                    text,
+                });
+                offset += len;
+                continue;
+            }
+        }
+        //collect data up to the next instruction
+        let start = offset;
+        while offset < data.len() && !code[offset] && offset - start < DATA_PER_LINE {
+            offset += 1;
+        }
+        let bytes = data[start..offset].to_vec();
+        let values: Vec<_> = bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+        let text = match syntax {
+            Syntax::Cowgod => format!("DB {}", values.join(", ")),
+            Syntax::Octo => values.join(" "),
+        };
+        lines.push(Line {
+            addr,
+            bytes,
+            kind: LineKind::Data,
+            text,
+        });
+    }
+    lines
+}
+
+//listing in a form the assembler accepts, addresses and bytes move into comments
+//assembling the result of a Cowgod disassembly at the same base gives back the ROM
+pub fn to_source(lines: &[Line]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        let hex: String = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        out.push_str(&format!(
+            "    {:<40} ; {:04X}: {}\n",
+            line.text, line.addr, hex
+        ));
+    }
+    out
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+
+//everything that can go wrong while the emulator runs a program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmuError {
+    //opcode at pc is not part of the instruction set
+    InvalidOpcode { pc: u16, op: u16 },
+    //CALL with all stack slots in use
+    StackOverflow,
+    //RET with an empty stack
+    StackUnderflow,
+    //instruction read or wrote past the end of RAM
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::InvalidOpcode { pc, op } => {
+                write!(f, "invalid opcode {:#06X} at {:#05X}", op, pc)
+            }
+            EmuError::StackOverflow => write!(f, "stack overflow"),
+            EmuError::StackUnderflow => write!(f, "stack underflow"),
+            EmuError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#X}", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
+//reasons a ROM can be rejected by Emu::load
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    //ROM file has no bytes in it
+    EmptyRom,
+    //ROM does not fit between the load address and the end of RAM
+    RomTooLarge { size: usize, max: usize },
+    //load address is outside of RAM
+    InvalidAddress { addr: u16 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::EmptyRom => write!(f, "ROM is empty"),
+            LoadError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, at most {} bytes fit", size, max)
+            }
+            LoadError::InvalidAddress { addr } => {
+                write!(f, "load address {:#X} is outside of RAM", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
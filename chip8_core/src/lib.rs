@@ -1,15 +1,64 @@
+use condition::Machine;
+use debug::{Debugger, RunMode};
 use rand::random;
+use trace::TraceState;
 
-const RAM_SIZE: usize = 4096;
+pub mod asm;
+mod audio;
+mod condition;
+mod debug;
+pub mod disasm;
+mod error;
+mod movie;
+pub mod octo;
+mod quirks;
+mod rng;
+mod snapshot;
+mod timing;
+mod trace;
+pub use audio::AudioRenderer;
+pub use condition::{Condition, ConditionError};
+pub use debug::{Access, StopReason, Watchpoint};
+pub use error::{EmuError, LoadError};
+pub use movie::{rom_hash, Movie, MovieError, MOVIE_VERSION};
+pub use quirks::{IndexIncrement, Quirks};
+pub use rng::{Rng, RngKind};
+pub use snapshot::{Snapshot, SnapshotError, SNAPSHOT_VERSION};
+pub use timing::{Timing, VIP_FRAME_CYCLES};
+pub use trace::Tracer;
+
+//XO-CHIP address space, classic programs only see the first CLASSIC_RAM_SIZE bytes of it
+const RAM_SIZE: usize = 0x10000;
+const CLASSIC_RAM_SIZE: usize = 0x1000;
+//lores (CHIP-8) resolution
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
-const NUM_REGS: usize = 16;
+//hires (SUPER-CHIP) resolution, use Emu::screen_width/screen_height for the current one
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+const SCREEN_SIZE: usize = HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT;
+pub const NUM_REGS: usize = 16;
 const STACK_SIZE: usize = 16;
-const START_ADDR: u16 = 0x200;
+//where programs are loaded and start running
+pub const START_ADDR: u16 = 0x200;
+//ETI-660 programs start here instead
+pub const ETI_660_START_ADDR: u16 = 0x600;
 const NUM_KEYS: usize = 16;
-const FONTSET_SIZE: usize = 80;
+pub const FONTSET_SIZE: usize = 80;
+//SUPER-CHIP 8x10 font lives right after the small one
+const BIG_FONTSET_ADDR: usize = FONTSET_SIZE;
+const BIG_FONTSET_SIZE: usize = 160;
+//HP-48 RPL user flags saved by FX75 and restored by FX85
+const NUM_FLAGS: usize = 16;
+//XO-CHIP audio pattern buffer, 128 one bit samples
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+//XO-CHIP pitch register value that plays the pattern at 4000 Hz
+pub const DEFAULT_PITCH: u8 = 64;
+//XO-CHIP bitplanes, a pixel holds one bit per plane
+const NUM_PLANES: u8 = 2;
 
-const FONTSET: [u8; FONTSET_SIZE] = [
+//4x5 sprites of the hex digits, FX29 points I at them
+pub const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -28,25 +77,76 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+//SUPER-CHIP 1.1 only ships 0-9, A-F follow the common extension
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 pub struct Emu {
-    pc: u16,                                      //program counter
-    ram: [u8; RAM_SIZE],                          //memory
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT], //screen size
-    v_reg: [u8; NUM_REGS],                        //v - registers
-    i_reg: u16,                                   //i - register
-    sp: u16,                                      // stack pointer
-    stack: [u16; STACK_SIZE],                     // stack
-    keys: [bool; NUM_KEYS],                       // key presses on the chip8
-    dt: u8,                                       // delay timer
-    st: u8,                                       // sound timer
+    pc: u16,                                         //program counter
+    ram: [u8; RAM_SIZE],                             //memory
+    screen: [u8; SCREEN_SIZE], //screen, only the first width * height pixels are in use
+    planes: u8,                //XO-CHIP bitplanes selected by FN01
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>, //XO-CHIP pattern loaded by F002
+    pitch: u8,                 //XO-CHIP playback rate set by FX3A
+    hires: bool,               //128x64 SUPER-CHIP mode
+    flags: [u8; NUM_FLAGS],    //RPL user flags
+    exited: bool,              //00FD was executed
+    v_reg: [u8; NUM_REGS],     //v - registers
+    i_reg: u16,                //i - register
+    sp: u16,                   // stack pointer
+    stack: [u16; STACK_SIZE],  // stack
+    keys: [bool; NUM_KEYS],    // key presses on the chip8
+    dt: u8,                    // delay timer
+    st: u8,                    // sound timer
+    quirks: Quirks,            // interpreter behaviour switches
+    vblank: bool,              // vertical blank reached (DXYN display wait)
+    buzzed: bool,              // the sound timer ran during the frame tick_timers ended
+    rng: Rng,                  // CXNN random number source
+    timing: Timing,            // what run_frame executes per frame
+    cycle_debt: u32,           // VIP cycles the last frame's final instruction ran over, not saved
+    debugger: Debugger,        // breakpoints and stepping, not part of save states
+    tracer: Option<Tracer>,    // opt-in instruction log
+}
+
+impl Default for Emu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Emu {
     pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    //creates an emulator that follows the given interpreter quirks
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut new_emu = Self {
             pc: START_ADDR,
             ram: [0; RAM_SIZE],
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            screen: [0; SCREEN_SIZE],
+            planes: 1,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            hires: false,
+            flags: [0; NUM_FLAGS],
+            exited: false,
             v_reg: [0; NUM_REGS],
             i_reg: 0,
             sp: 0,
@@ -54,9 +154,17 @@ impl Emu {
             keys: [false; NUM_KEYS],
             dt: 0,
             st: 0,
+            quirks,
+            vblank: false,
+            buzzed: false,
+            rng: Rng::new(RngKind::Xorshift, random()),
+            timing: Timing::default(),
+            cycle_debt: 0,
+            debugger: Debugger::new(),
+            tracer: None,
         };
 
-        new_emu.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        new_emu.load_fonts();
 
         new_emu
     }
@@ -64,7 +172,12 @@ impl Emu {
     pub fn reset(&mut self) {
         self.pc = START_ADDR;
         self.ram = [0; RAM_SIZE];
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        self.screen = [0; SCREEN_SIZE];
+        self.planes = 1;
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.hires = false;
+        self.exited = false;
         self.v_reg = [0; NUM_REGS];
         self.i_reg = 0;
         self.sp = 0;
@@ -72,30 +185,186 @@ impl Emu {
         self.keys = [false; NUM_KEYS];
         self.dt = 0;
         self.st = 0;
+        self.vblank = false;
+        self.buzzed = false;
+        self.cycle_debt = 0;
+        //RPL flags survive a reset, just like they survive between programs on the HP-48
+        self.load_fonts();
+    }
+
+    fn load_fonts(&mut self) {
         self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        self.ram[BIG_FONTSET_ADDR..BIG_FONTSET_ADDR + BIG_FONTSET_SIZE]
+            .copy_from_slice(&BIG_FONTSET);
     }
 
-    fn push(&mut self, val: u16) {
+    fn push(&mut self, val: u16) -> Result<(), EmuError> {
         //push method for stack
+        if self.sp as usize >= STACK_SIZE {
+            return Err(EmuError::StackOverflow);
+        }
         self.stack[self.sp as usize] = val;
         self.sp += 1;
+        Ok(())
     }
 
-    // game code error when empty stack popped, so not handling the complete panic.
-    fn pop(&mut self) -> u16 {
+    // popping an empty stack is a game code error, reported instead of underflowing sp
+    fn pop(&mut self) -> Result<u16, EmuError> {
         //pop method for stack
+        if self.sp == 0 {
+            return Err(EmuError::StackUnderflow);
+        }
         self.sp -= 1;
-        self.stack[self.sp as usize]
+        Ok(self.stack[self.sp as usize])
+    }
+
+    //RAM accessors, every instruction touching memory goes through these so bad addresses become errors
+    //data accesses are also where watchpoints trigger, instruction fetches use peek_byte instead
+    fn read_byte(&mut self, addr: usize) -> Result<u8, EmuError> {
+        let val = self.peek_byte(addr)?;
+        self.debugger.check_access(addr, Access::Read);
+        Ok(val)
+    }
+
+    fn write_byte(&mut self, addr: usize, val: u8) -> Result<(), EmuError> {
+        let size = self.memory_size();
+        let byte = self.ram[..size]
+            .get_mut(addr)
+            .ok_or(EmuError::MemoryOutOfBounds { addr })?;
+        *byte = val;
+        self.debugger.check_access(addr, Access::Write);
+        Ok(())
+    }
+
+    fn peek_byte(&self, addr: usize) -> Result<u8, EmuError> {
+        self.ram[..self.memory_size()]
+            .get(addr)
+            .copied()
+            .ok_or(EmuError::MemoryOutOfBounds { addr })
+    }
+
+    //bytes of RAM the program can address, 64K for XO-CHIP and 4K otherwise
+    pub fn memory_size(&self) -> usize {
+        if self.quirks.extended_memory {
+            RAM_SIZE
+        } else {
+            CLASSIC_RAM_SIZE
+        }
+    }
+
+    //skips the next instruction, which is 4 bytes long when it is XO-CHIP's F000 NNNN
+    fn skip(&mut self) {
+        let next = self.peek_byte(self.pc as usize).ok() == Some(0xF0)
+            && self.peek_byte(self.pc as usize + 1).ok() == Some(0x00);
+        self.pc = self.pc.wrapping_add(if next { 4 } else { 2 });
+    }
+
+    //runs a single instruction and reports faults instead of panicking
+    pub fn tick(&mut self) -> Result<(), EmuError> {
+        self.step().map(|_| ())
+    }
+
+    //runs one frame according to the timing model, then counts the timers down
+    pub fn run_frame(&mut self) -> Result<(), EmuError> {
+        match self.timing {
+            Timing::Instructions(count) => {
+                for _ in 0..count {
+                    self.tick()?;
+                }
+            }
+            Timing::Vip => self.run_vip_frame()?,
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    //spends the interpreter's cycles of a frame, an instruction that runs past the end
+    //takes its extra cycles out of the next frame
+    fn run_vip_frame(&mut self) -> Result<(), EmuError> {
+        let mut spent = self.cycle_debt;
+        self.cycle_debt = 0;
+        while spent < VIP_FRAME_CYCLES {
+            let pc = self.pc;
+            let op = u16::from_be_bytes([
+                self.peek_byte(pc as usize)?,
+                self.peek_byte(pc as usize + 1)?,
+            ]);
+            let cycles = timing::vip_cycles(self, op);
+            //stopped by the debugger or exited
+            if !self.step()? {
+                return Ok(());
+            }
+            spent += cycles;
+            //DXYN waiting for vertical blank idles until the interrupt ends the frame
+            if op & 0xF000 == 0xD000 && self.quirks.display_wait && self.pc == pc {
+                return Ok(());
+            }
+        }
+        self.cycle_debt = spent - VIP_FRAME_CYCLES;
+        Ok(())
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
     }
 
-    pub fn tick(&mut self) {
-        //Fetch
-        let op = self.fetch();
-        //decode and execute
-        self.execute(op);
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.cycle_debt = 0;
     }
 
-    fn execute(&mut self, op: u16) {
+    //true when an instruction ran, false when the machine is halted or held by the debugger
+    fn step(&mut self) -> Result<bool, EmuError> {
+        //an exited program stays halted until reset
+        if self.exited {
+            return Ok(false);
+        }
+        //a stopped debugger holds the machine before the instruction at pc
+        //built from the fields so the debugger can be borrowed mutably at the same time
+        let machine = Machine {
+            v_reg: &self.v_reg,
+            i_reg: self.i_reg,
+            dt: self.dt,
+            st: self.st,
+            pc: self.pc,
+            sp: self.sp,
+            memory: &self.ram[..self.memory_size()],
+        };
+        if self.debugger.check_stop(&machine) {
+            return Ok(false);
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(&TraceState {
+                pc: self.pc,
+                memory: machine.memory,
+                v_reg: &self.v_reg,
+                i_reg: self.i_reg,
+                sp: self.sp,
+                dt: self.dt,
+                st: self.st,
+            });
+        }
+        self.rng.step();
+        //Fetch, decode and execute
+        let result = self.fetch().and_then(|op| self.execute(op));
+        self.debugger.finish_instruction();
+        result.map(|()| true)
+    }
+
+    //what conditional breakpoints can look at
+    fn machine(&self) -> Machine<'_> {
+        Machine {
+            v_reg: &self.v_reg,
+            i_reg: self.i_reg,
+            dt: self.dt,
+            st: self.st,
+            pc: self.pc,
+            sp: self.sp,
+            memory: &self.ram[..self.memory_size()],
+        }
+    }
+
+    fn execute(&mut self, op: u16) -> Result<(), EmuError> {
         //digit separation
         let digit1 = (op & 0xF000) >> 12;
         let digit2 = (op & 0x0F00) >> 8;
@@ -105,21 +374,72 @@ impl Emu {
         match (digit1, digit2, digit3, digit4) {
             //NOP
             //(Do nothing)
-            (0, 0, 0, 0) => return,
+            (0, 0, 0, 0) => (),
+
+            //00CN
+            //SCROLL DOWN N PIXELS (SUPER-CHIP)
+            (0, 0, 0xC, _) => {
+                self.scroll(0, digit4 as isize);
+            }
+
+            //00DN
+            //SCROLL UP N PIXELS (XO-CHIP)
+            (0, 0, 0xD, _) => {
+                self.scroll(0, -(digit4 as isize));
+            }
 
             //CLS
-            //(clears the screen)
+            //(clears the screen, only the selected planes on XO-CHIP)
             (0, 0, 0xE, 0) => {
-                self.screen = [false; SCREEN_HEIGHT * SCREEN_WIDTH];
+                let mask = !self.planes;
+                for pixel in self.screen.iter_mut() {
+                    *pixel &= mask;
+                }
             }
 
             //RET
             //(returns from a subroutine)
             (0, 0, 0xE, 0xE) => {
-                let return_address = self.pop();
+                let return_address = self.pop()?;
                 self.pc = return_address;
             }
 
+            //00FB
+            //SCROLL RIGHT 4 PIXELS (SUPER-CHIP)
+            (0, 0, 0xF, 0xB) => {
+                self.scroll(4, 0);
+            }
+
+            //00FC
+            //SCROLL LEFT 4 PIXELS (SUPER-CHIP)
+            (0, 0, 0xF, 0xC) => {
+                self.scroll(-4, 0);
+            }
+
+            //00FD
+            //EXIT THE INTERPRETER (SUPER-CHIP)
+            (0, 0, 0xF, 0xD) => {
+                self.exited = true;
+            }
+
+            //00FE
+            //LORES, 64x32 (SUPER-CHIP)
+            (0, 0, 0xF, 0xE) => {
+                self.hires = false;
+                if self.quirks.mode_switch_clears {
+                    self.screen = [0; SCREEN_SIZE];
+                }
+            }
+
+            //00FF
+            //HIRES, 128x64 (SUPER-CHIP)
+            (0, 0, 0xF, 0xF) => {
+                self.hires = true;
+                if self.quirks.mode_switch_clears {
+                    self.screen = [0; SCREEN_SIZE];
+                }
+            }
+
             //JMP NNN
             //(jump to the given address)
             (1, _, _, _) => {
@@ -131,7 +451,7 @@ impl Emu {
             //(calling a function)
             (2, _, _, _) => {
                 let nnn = op & 0xFFF;
-                self.push(self.pc);
+                self.push(self.pc)?;
                 self.pc = nnn;
             }
 
@@ -141,7 +461,7 @@ impl Emu {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
                 if self.v_reg[x] == nn {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
@@ -151,7 +471,7 @@ impl Emu {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
                 if self.v_reg[x] != nn {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
@@ -161,7 +481,25 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
                 if self.v_reg[x] == self.v_reg[y] {
-                    self.pc += 2;
+                    self.skip();
+                }
+            }
+
+            //5XY2
+            //SAVE VX to VY to RAM at I, in either order (XO-CHIP)
+            (5, _, _, 2) => {
+                let i = self.i_reg as usize;
+                for (offset, reg) in Self::reg_range(digit2, digit3) {
+                    self.write_byte(i + offset, self.v_reg[reg])?;
+                }
+            }
+
+            //5XY3
+            //LOAD VX to VY from RAM at I, in either order (XO-CHIP)
+            (5, _, _, 3) => {
+                let i = self.i_reg as usize;
+                for (offset, reg) in Self::reg_range(digit2, digit3) {
+                    self.v_reg[reg] = self.read_byte(i + offset)?;
                 }
             }
 
@@ -195,6 +533,9 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
                 self.v_reg[x] |= self.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
             }
 
             //8XY2
@@ -203,6 +544,9 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
                 self.v_reg[x] &= self.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
             }
 
             //8XY3
@@ -211,6 +555,9 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
                 self.v_reg[x] ^= self.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
             }
 
             //8XY4
@@ -242,12 +589,13 @@ impl Emu {
             }
 
             //8XY6
-            //VX >>= VY
+            //VX >>= 1 (VX = VY >> 1 with the shift quirk)
             // VF CARRY FLAG STORES THE DROPPED OFF VALUE
             (8, _, _, 6) => {
                 let x = digit2 as usize;
-                let lsb = self.v_reg[x] & 1;
-                self.v_reg[x] >>= 1;
+                let src = self.shift_source(x, digit3 as usize);
+                let lsb = src & 1;
+                self.v_reg[x] = src >> 1;
                 self.v_reg[0xF] = lsb;
             }
 
@@ -266,12 +614,13 @@ impl Emu {
             }
 
             //8XYE
-            // VX <<= 1
+            // VX <<= 1 (VX = VY << 1 with the shift quirk)
             // OVERFLOW STORED IN VF
-            (8, _, _, _) => {
+            (8, _, _, 0xE) => {
                 let x = digit2 as usize;
-                let msb = (self.v_reg[x] >> 7) & 1;
-                self.v_reg[x] <<= 1;
+                let src = self.shift_source(x, digit3 as usize);
+                let msb = (src >> 7) & 1;
+                self.v_reg[x] = src << 1;
                 self.v_reg[0xF] = msb;
             }
 
@@ -281,7 +630,7 @@ impl Emu {
                 let x = digit2 as usize;
                 let y = digit3 as usize;
                 if self.v_reg[x] != self.v_reg[y] {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
@@ -294,9 +643,15 @@ impl Emu {
 
             //BNNN
             //JMP TO V0 + NNN
+            //(BXNN, JMP TO VX + XNN with the jump quirk)
             (0xB, _, _, _) => {
-                let nnn = (op & 0xFFF) as u16;
-                self.sp = (self.v_reg[0] as u16) + nnn;
+                let nnn = op & 0xFFF;
+                let offset = if self.quirks.jump_uses_vx {
+                    self.v_reg[digit2 as usize]
+                } else {
+                    self.v_reg[0]
+                };
+                self.pc = (offset as u16 + nnn) & 0xFFF;
             }
 
             //CXNN
@@ -305,38 +660,72 @@ impl Emu {
             (0xC, _, _, _) => {
                 let x = digit2 as usize;
                 let nn = (op & 0xFF) as u8;
-                let rng: u8 = random();
+                let start = START_ADDR as usize;
+                let rng = self.rng.next_byte(&self.ram[start..start + 0x100]);
                 self.v_reg[x] = rng & nn;
             }
 
             //DRAW SPRITES
             //DXYN
             //X AND Y ARE COORDINATES INTO V_REG AND N IS THE NUMBER OF ROWS. NUMBER OF COLUMNS PER ROW IS ALWAYS 8
+            //DXY0 DRAWS A 16x16 SPRITE INSTEAD, TWO BYTES PER ROW (SUPER-CHIP)
+            //IN LORES ONLY 8x16 WITHOUT THE BIG SPRITE QUIRK, LIKE SUPER-CHIP 1.1
+            //WITH BOTH XO-CHIP PLANES SELECTED THE SECOND PLANE'S SPRITE FOLLOWS THE FIRST ONE IN RAM
             (0xD, _, _, _) => {
-                //gets x and y coords
-                let x_coord = self.v_reg[digit2 as usize] as u16;
-                let y_coord = self.v_reg[digit3 as usize] as u16;
-                //number of rows
-                let num_rows = digit4;
+                //the VIP only draws during vertical blank, so redo the opcode until it comes
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        self.redo();
+                        return Ok(());
+                    }
+                    self.vblank = false;
+                }
+                let width = self.screen_width();
+                let height = self.screen_height();
+                //gets x and y coords, the starting position always wraps
+                let x_coord = self.v_reg[digit2 as usize] as usize % width;
+                let y_coord = self.v_reg[digit3 as usize] as usize % height;
+                //number of rows and columns
+                let (num_rows, num_cols) = match digit4 {
+                    0 if self.hires || self.quirks.lores_big_sprites => (16, 16),
+                    0 => (16, 8),
+                    _ => (digit4 as usize, 8),
+                };
+                let bytes_per_row = num_cols / 8;
                 //checks if pixels were flipped
                 let mut flipped = false;
-                //iterate over each row of sprite
-                for y_line in 0..num_rows {
-                    // row data stored at I register
-                    let addr = self.i_reg + y_line as u16;
-                    let pixels = self.ram[addr as usize];
-                    //Iterate over each column in the row
-                    for x_line in 0..8 {
-                        // Sprites to wrap around screen
-                        // Use a mask to fetch current pixel's bit. Only flip if a 1
-                        if (pixels & (0b1000_0000 >> x_line)) != 0 {
-                            let x = (x_coord + x_line) as usize % SCREEN_WIDTH;
-                            let y = (y_coord + y_line) as usize % SCREEN_HEIGHT;
-                            //get pixel index for 1d screen array
-                            let idx = x + SCREEN_WIDTH * y;
-                            // check for flipping
-                            flipped |= self.screen[idx];
-                            self.screen[idx] ^= true;
+                let mut addr = self.i_reg as usize;
+                for plane in 0..NUM_PLANES {
+                    let plane_bit = 1 << plane;
+                    if self.planes & plane_bit == 0 {
+                        continue;
+                    }
+                    //iterate over each row of sprite
+                    for y_line in 0..num_rows {
+                        // row data stored at I register
+                        let mut pixels = 0u16;
+                        for _ in 0..bytes_per_row {
+                            pixels = (pixels << 8) | self.read_byte(addr)? as u16;
+                            addr += 1;
+                        }
+                        //Iterate over each column in the row
+                        for x_line in 0..num_cols {
+                            // Use a mask to fetch current pixel's bit. Only flip if a 1
+                            if (pixels & (1 << (num_cols - 1 - x_line))) != 0 {
+                                let x = x_coord + x_line;
+                                let y = y_coord + y_line;
+                                //pixels past the edge are either cut off or wrapped
+                                if self.quirks.clip_sprites && (x >= width || y >= height) {
+                                    continue;
+                                }
+                                let x = x % width;
+                                let y = y % height;
+                                //get pixel index for 1d screen array
+                                let idx = x + width * y;
+                                // check for flipping
+                                flipped |= self.screen[idx] & plane_bit != 0;
+                                self.screen[idx] ^= plane_bit;
+                            }
                         }
                     }
                 }
@@ -351,12 +740,13 @@ impl Emu {
             //KEY PRESS SKIP
             //EX9E
             //if index stored in VX is pressed, then we have a SKIP
+            //only the low nibble of VX picks the key, like on the VIP
             (0xE, _, 9, 0xE) => {
                 let x = digit2 as usize;
                 let vx = self.v_reg[x];
-                let key = self.keys[vx as usize];
+                let key = self.keys[(vx & 0xF) as usize];
                 if key {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
@@ -366,12 +756,37 @@ impl Emu {
             (0xE, _, 0xA, 1) => {
                 let x = digit2 as usize;
                 let vx = self.v_reg[x];
-                let key = self.keys[vx as usize];
+                let key = self.keys[(vx & 0xF) as usize];
                 if !key {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
 
+            //F000 NNNN
+            //I = NNNN, the address is the word after the opcode (XO-CHIP)
+            (0xF, 0, 0, 0) => {
+                let high = self.peek_byte(self.pc as usize)? as u16;
+                let low = self.peek_byte(self.pc as usize + 1)? as u16;
+                self.i_reg = (high << 8) | low;
+                self.pc = self.pc.wrapping_add(2);
+            }
+
+            //FN01
+            //SELECT BITPLANES N FOR DRAWING, CLEARING AND SCROLLING (XO-CHIP)
+            (0xF, _, 0, 1) => {
+                self.planes = digit2 as u8 & 0b11;
+            }
+
+            //F002
+            //LOAD THE 16 BYTE AUDIO PATTERN FROM RAM AT I (XO-CHIP)
+            (0xF, 0, 0, 2) => {
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (idx, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_byte(self.i_reg as usize + idx)?;
+                }
+                self.audio_pattern = Some(pattern);
+            }
+
             //FX07
             //VX = DT
             // stores delay timer value into VX
@@ -396,7 +811,7 @@ impl Emu {
                 }
                 if !pressed {
                     //REDO OPCODE
-                    self.pc -= 2;
+                    self.redo();
                 }
             }
 
@@ -434,6 +849,14 @@ impl Emu {
                 self.i_reg = c * 5;
             }
 
+            //FX30
+            //big font at I (SUPER-CHIP), 10 bytes per character
+            (0xF, _, 3, 0) => {
+                let x = digit2 as usize;
+                let c = (self.v_reg[x] & 0xF) as u16;
+                self.i_reg = BIG_FONTSET_ADDR as u16 + c * 10;
+            }
+
             //FX33
             //CONVERTS TO BINARY CODED DECIMAL FORMAT OF THE NUMBER STORED IN VX
             //TODO: find more fast and efficient BCD algorithms so I dont have to do floating point arithmetics
@@ -445,9 +868,10 @@ impl Emu {
                 let tens = ((vx / 10.0) % 10.0).floor() as u8;
                 let ones = (vx % 10.0) as u8;
 
-                self.ram[self.i_reg as usize] = hundreds;
-                self.ram[(self.i_reg + 1) as usize] = tens;
-                self.ram[(self.i_reg + 2) as usize] = ones;
+                let i = self.i_reg as usize;
+                self.write_byte(i, hundreds)?;
+                self.write_byte(i + 1, tens)?;
+                self.write_byte(i + 2, ones)?;
             }
 
             //FX55
@@ -457,8 +881,9 @@ impl Emu {
                 let x = digit2 as usize;
                 let i = self.i_reg as usize;
                 for idx in 0..=x {
-                    self.ram[i + idx] = self.v_reg[idx];
+                    self.write_byte(i + idx, self.v_reg[idx])?;
                 }
+                self.increment_index(x);
             }
 
             //FX65
@@ -468,37 +893,364 @@ impl Emu {
                 let x = digit2 as usize;
                 let i = self.i_reg as usize;
                 for idx in 0..=x {
-                    self.v_reg[idx] = self.ram[i + idx];
+                    self.v_reg[idx] = self.read_byte(i + idx)?;
                 }
+                self.increment_index(x);
+            }
+
+            //FX3A
+            //PITCH = VX (XO-CHIP)
+            (0xF, _, 3, 0xA) => {
+                self.pitch = self.v_reg[digit2 as usize];
+            }
+
+            //FX75
+            //SAVE V0 to VX to the RPL flags (SUPER-CHIP)
+            (0xF, _, 7, 5) => {
+                let x = digit2 as usize;
+                self.flags[..=x].copy_from_slice(&self.v_reg[..=x]);
+            }
+
+            //FX85
+            //LOAD V0 to VX from the RPL flags (SUPER-CHIP)
+            (0xF, _, 8, 5) => {
+                let x = digit2 as usize;
+                self.v_reg[..=x].copy_from_slice(&self.flags[..=x]);
             }
 
-            (_, _, _, _) => unimplemented!("Unimplemented opcode: {}", op),
+            (_, _, _, _) => {
+                return Err(EmuError::InvalidOpcode {
+                    pc: self.previous_pc(),
+                    op,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    //moves the selected planes by dx, dy pixels of the current resolution, pixels scrolled in are blank
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.screen_width() as isize;
+        let height = self.screen_height() as isize;
+        let old = self.screen;
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let inside = (0..width).contains(&src_x) && (0..height).contains(&src_y);
+                let moved = if inside {
+                    old[(src_x + src_y * width) as usize] & self.planes
+                } else {
+                    0
+                };
+                let idx = (x + y * width) as usize;
+                self.screen[idx] = (self.screen[idx] & !self.planes) | moved;
+            }
+        }
+    }
+
+    //registers touched by 5XY2 / 5XY3 paired with their offset from I, counting down when X > Y
+    fn reg_range(x: u16, y: u16) -> impl Iterator<Item = (usize, usize)> {
+        let (x, y) = (x as usize, y as usize);
+        (0..=x.abs_diff(y))
+            .map(move |offset| (offset, if x <= y { x + offset } else { x - offset }))
+    }
+
+    //value that 8XY6 / 8XYE shift, depending on the shift quirk
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v_reg[y]
+        } else {
+            self.v_reg[x]
         }
     }
 
-    fn fetch(&mut self) -> u16 {
-        let higher_byte = self.ram[self.pc as usize] as u16;
-        let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
+    //moves I past the registers FX55 / FX65 touched, depending on the load/store quirk
+    fn increment_index(&mut self, x: usize) {
+        let step = match self.quirks.load_store {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => x as u16,
+            IndexIncrement::XPlusOne => x as u16 + 1,
+        };
+        self.i_reg = self.i_reg.wrapping_add(step);
+    }
+
+    //address of the instruction fetch just moved past, pc wraps like it does when fetching
+    fn previous_pc(&self) -> u16 {
+        self.pc.wrapping_sub(2) & (self.memory_size() - 1) as u16
+    }
+
+    //runs the current instruction again next time, for opcodes that wait
+    fn redo(&mut self) {
+        self.pc = self.previous_pc();
+    }
+
+    fn fetch(&mut self) -> Result<u16, EmuError> {
+        let higher_byte = self.peek_byte(self.pc as usize)? as u16;
+        let lower_byte = self.peek_byte(self.pc as usize + 1)? as u16;
         let op = (higher_byte << 8) | lower_byte; //convert to Big Endian
-        self.pc += 2; //move ahead
-        op
+        self.pc = self.pc.wrapping_add(2); //move ahead
+        Ok(op)
     }
 
     pub fn tick_timers(&mut self) {
+        //tick_timers is called once per displayed frame, which is when vertical blank happens
+        self.vblank = true;
+        self.buzzed = self.st > 0;
         if self.dt > 0 {
             self.dt -= 1;
         }
         if self.st > 0 {
-            if self.st == 0 {
-                //BEEP after this ( implement later )
-            }
             self.st -= 1;
         }
     }
 
-    //returns a pointer to screen buffer array
-    pub fn get_display(&self) -> &[bool] {
-        &self.screen
+    //the buzzer sounds for as long as the sound timer is counting down
+    pub fn sound_active(&self) -> bool {
+        self.st > 0
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    //changes interpreter behaviour on the fly, e.g. when the frontend switches profiles
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    //returns a pointer to screen buffer array, screen_width() * screen_height() pixels long
+    //each pixel is a colour index 0-3: bit 0 is set by plane 1, bit 1 by XO-CHIP plane 2
+    pub fn get_display(&self) -> &[u8] {
+        &self.screen[..self.screen_width() * self.screen_height()]
+    }
+
+    //current resolution, 64x32 normally and 128x64 in SUPER-CHIP hires mode
+    pub fn screen_width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    pub fn screen_height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    //register inspection for frontends and tools
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn v_reg(&self) -> &[u8; NUM_REGS] {
+        &self.v_reg
+    }
+
+    pub fn i_reg(&self) -> u16 {
+        self.i_reg
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    //register writes for debuggers
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_v_reg(&mut self, idx: usize, val: u8) {
+        self.v_reg[idx] = val;
+    }
+
+    pub fn set_i_reg(&mut self, val: u16) {
+        self.i_reg = val;
+    }
+
+    pub fn set_delay_timer(&mut self, val: u8) {
+        self.dt = val;
+    }
+
+    pub fn set_sound_timer(&mut self, val: u8) {
+        self.st = val;
+    }
+
+    //writes into RAM for debuggers, watchpoints are not triggered
+    pub fn write_memory(&mut self, addr: usize, data: &[u8]) -> Result<(), EmuError> {
+        let size = self.memory_size();
+        match self.ram[..size].get_mut(addr..addr + data.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(data);
+                Ok(())
+            }
+            None => Err(EmuError::MemoryOutOfBounds {
+                addr: addr + data.len() - 1,
+            }),
+        }
+    }
+
+    //return addresses of the active calls, innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    //the addressable RAM, for debuggers and memory viewers
+    pub fn memory(&self) -> &[u8] {
+        &self.ram[..self.memory_size()]
+    }
+
+    //debugger: tick does nothing while stopped, the frontend keeps calling it as usual
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.debugger.breakpoints.insert(addr, None);
+    }
+
+    //stops at addr only when the condition holds, replaces any breakpoint already there
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: Condition) {
+        self.debugger.breakpoints.insert(addr, Some(condition));
+    }
+
+    //false if there was no breakpoint at addr
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.debugger.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u16, Option<&Condition>)> + '_ {
+        self.debugger
+            .breakpoints
+            .iter()
+            .map(|(&addr, condition)| (addr, condition.as_ref()))
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.debugger.watchpoints.push(watchpoint);
+    }
+
+    //false if no such watchpoint was set
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let count = self.debugger.watchpoints.len();
+        self.debugger
+            .watchpoints
+            .retain(|watch| watch != watchpoint);
+        self.debugger.watchpoints.len() != count
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.debugger.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.debugger.watchpoints
+    }
+
+    //logs every instruction from now on, replaces the current tracer
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    //stops tracing, call Tracer::finish on the result to flush it
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    //evaluates an expression against the current state, e.g. for a watch window
+    pub fn evaluate(&self, condition: &Condition) -> i64 {
+        condition.eval(&self.machine())
+    }
+
+    //None while running or stepping over/out
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        match self.debugger.mode {
+            RunMode::Stopped(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop_reason().is_some()
+    }
+
+    pub fn pause(&mut self) {
+        if !self.is_stopped() {
+            self.debugger.mode = RunMode::Stopped(StopReason::Paused);
+        }
+    }
+
+    //continues until the next breakpoint
+    pub fn resume(&mut self) {
+        self.debugger.resume(RunMode::Running, self.pc);
+    }
+
+    //runs exactly one instruction and stops again
+    pub fn step_into(&mut self) -> Result<(), EmuError> {
+        self.debugger.resume(RunMode::Running, self.pc);
+        let result = self.tick();
+        //a watchpoint hit by the instruction is the more useful reason
+        if !self.is_stopped() {
+            self.debugger.mode = RunMode::Stopped(StopReason::Step);
+        }
+        result
+    }
+
+    //like step_into, but a CALL (2NNN) runs until the subroutine returns
+    pub fn step_over(&mut self) -> Result<(), EmuError> {
+        let op = self.memory().get(self.pc as usize..self.pc as usize + 2);
+        match op {
+            Some(&[high, _]) if high & 0xF0 == 0x20 => {
+                let mode = RunMode::StepOver {
+                    addr: self.pc.wrapping_add(2),
+                    sp: self.sp,
+                };
+                self.debugger.resume(mode, self.pc);
+                Ok(())
+            }
+            _ => self.step_into(),
+        }
+    }
+
+    //runs until the current subroutine returns (00EE), outside of a subroutine this is resume
+    pub fn step_out(&mut self) {
+        let mode = if self.sp == 0 {
+            RunMode::Running
+        } else {
+            RunMode::StepOut { sp: self.sp }
+        };
+        self.debugger.resume(mode, self.pc);
+    }
+
+    //run to cursor, breakpoints on the way still stop the machine
+    pub fn run_to(&mut self, addr: u16) {
+        self.debugger.resume(RunMode::RunTo(addr), self.pc);
+    }
+
+    //XO-CHIP audio pattern, None until the program loads one with F002
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    //XO-CHIP pitch register, the pattern plays at 4000 * 2^((pitch - 64) / 48) Hz
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    //true once the program ran 00FD, the frontend can close the game then
+    pub fn has_exited(&self) -> bool {
+        self.exited
     }
 
     //key buffer array frontend API manipulator
@@ -507,13 +1259,443 @@ impl Emu {
         self.keys[idx] = pressed;
     }
 
+    //all 16 keys as a bitmask, bit N set while key N is held
+    pub fn key_state(&self) -> u16 {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (idx, &down)| acc | ((down as u16) << idx))
+    }
+
+    //sets all 16 keys at once from a key_state bitmask, used for movie playback
+    pub fn set_key_state(&mut self, state: u16) {
+        for (idx, key) in self.keys.iter_mut().enumerate() {
+            *key = state & (1 << idx) != 0;
+        }
+    }
+
+    //makes CXNN deterministic: the same seed and inputs always give the same run
+    //new emulators start with a random seed
+    pub fn seed_rng(&mut self, kind: RngKind, seed: u64) {
+        self.rng = Rng::new(kind, seed);
+    }
+
+    pub fn rng(&self) -> Rng {
+        self.rng
+    }
+
+    //captures everything needed to resume the machine later
+    pub fn save_state(&self) -> Snapshot {
+        Snapshot {
+            quirks: self.quirks,
+            pc: self.pc,
+            ram: self.ram[..self.memory_size()].to_vec(),
+            screen: self.screen.to_vec(),
+            planes: self.planes,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            hires: self.hires,
+            flags: self.flags,
+            exited: self.exited,
+            v_reg: self.v_reg,
+            i_reg: self.i_reg,
+            sp: self.sp,
+            stack: self.stack,
+            keys: self.keys,
+            dt: self.dt,
+            st: self.st,
+            vblank: self.vblank,
+            rng: self.rng,
+        }
+    }
+
+    //puts the machine back into the state of a snapshot, quirks included
+    pub fn load_state(&mut self, snapshot: &Snapshot) {
+        self.quirks = snapshot.quirks;
+        self.pc = snapshot.pc;
+        self.ram = [0; RAM_SIZE];
+        self.ram[..snapshot.ram.len()].copy_from_slice(&snapshot.ram);
+        self.screen.copy_from_slice(&snapshot.screen);
+        self.planes = snapshot.planes;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        self.hires = snapshot.hires;
+        self.flags = snapshot.flags;
+        self.exited = snapshot.exited;
+        self.v_reg = snapshot.v_reg;
+        self.i_reg = snapshot.i_reg;
+        self.sp = snapshot.sp;
+        self.stack = snapshot.stack;
+        self.keys = snapshot.keys;
+        self.dt = snapshot.dt;
+        self.st = snapshot.st;
+        self.vblank = snapshot.vblank;
+        self.rng = snapshot.rng;
+        self.buzzed = false;
+        self.cycle_debt = 0;
+    }
+
     //loads game code from file to RAM so it can be executed
     //TAKE A LIST OF BYTES AND WRITE TO RAM
-    pub fn load(&mut self, data: &[u8]) {
-        let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
-        self.ram[start..end].copy_from_slice(data);
+    pub fn load(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        self.load_at(START_ADDR, data)
     }
 
-    //TODO: completed to section 6.2 
+    //same as load, but at a caller chosen address which also becomes the entry point
+    pub fn load_at(&mut self, addr: u16, data: &[u8]) -> Result<(), LoadError> {
+        let start = addr as usize;
+        if start >= self.memory_size() {
+            return Err(LoadError::InvalidAddress { addr });
+        }
+        if data.is_empty() {
+            return Err(LoadError::EmptyRom);
+        }
+        let max = self.memory_size() - start;
+        if data.len() > max {
+            return Err(LoadError::RomTooLarge {
+                size: data.len(),
+                max,
+            });
+        }
+        self.ram[start..start + data.len()].copy_from_slice(data);
+        self.pc = addr;
+        Ok(())
+    }
+
+    //TODO: completed to section 6.2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //an XO-CHIP machine with the program at the very top of its 64K
+    fn top_of_memory(quirks: Quirks, program: [u8; 2]) -> Emu {
+        let mut emu = Emu::with_quirks(quirks);
+        emu.load_at(0xFFFE, &program).unwrap();
+        emu
+    }
+
+    //runs the instructions of a program, the rest of it is data
+    fn run(quirks: Quirks, program: &[u8], instructions: usize) -> Emu {
+        let mut emu = Emu::with_quirks(quirks);
+        emu.load(program).unwrap();
+        for _ in 0..instructions {
+            emu.tick().unwrap();
+        }
+        emu
+    }
+
+    fn lit_pixels(emu: &Emu) -> usize {
+        emu.get_display()
+            .iter()
+            .filter(|&&pixel| pixel != 0)
+            .count()
+    }
+
+    #[test]
+    fn mode_switch_keeps_the_display_on_schip() {
+        #[rustfmt::skip]
+        let program = [
+            0xA2, 0x08, //LD I, 0x208
+            0xD0, 0x01, //DRW V0, V0, 1
+            0x00, 0xFF, //HIGH
+            0x00, 0xFE, //LOW
+            0xFF,
+        ];
+        let emu = run(Quirks::schip(), &program, 3);
+        assert_eq!(lit_pixels(&emu), 8);
+        let emu = run(Quirks::schip(), &program, 4);
+        assert_eq!(lit_pixels(&emu), 8);
+        let emu = run(Quirks::xo_chip(), &program, 3);
+        assert_eq!(lit_pixels(&emu), 0);
+    }
+
+    #[test]
+    fn big_sprites_in_lores_depend_on_the_quirk() {
+        let mut program = vec![
+            0xA2, 0x06, //LD I, 0x206
+            0xD0, 0x00, //DRW V0, V0, 0
+            0x00, 0x00,
+        ];
+        program.extend_from_slice(&[0xFF; 32]);
+        //SUPER-CHIP 1.1 only draws a byte of each of the 16 rows
+        assert_eq!(lit_pixels(&run(Quirks::schip(), &program, 2)), 8 * 16);
+        assert_eq!(lit_pixels(&run(Quirks::xo_chip(), &program, 2)), 16 * 16);
+        //hires always draws 16x16
+        program[4..6].copy_from_slice(&[0xD0, 0x00]);
+        program[2..4].copy_from_slice(&[0x00, 0xFF]);
+        assert_eq!(lit_pixels(&run(Quirks::schip(), &program, 3)), 16 * 16);
+    }
+
+    //the presets pinned by the quirk tests below
+    fn presets() -> [(&'static str, Quirks); 4] {
+        [
+            ("vip", Quirks::vip()),
+            ("chip48", Quirks::chip48()),
+            ("schip", Quirks::schip()),
+            ("xochip", Quirks::xo_chip()),
+        ]
+    }
+
+    //like run, but one instruction a frame so the VIP's display wait never holds up a draw
+    fn run_frames(quirks: Quirks, program: &[u8], instructions: usize) -> Emu {
+        let mut emu = Emu::with_quirks(quirks);
+        emu.load(program).unwrap();
+        for _ in 0..instructions {
+            emu.tick_timers();
+            emu.tick().unwrap();
+        }
+        emu
+    }
+
+    #[test]
+    fn shifts_per_preset() {
+        #[rustfmt::skip]
+        let program = [
+            0x61, 0x03, //LD V1, 0x03
+            0x62, 0x80, //LD V2, 0x80
+            0x81, 0x26, //SHR V1, V2
+            0x63, 0x03, //LD V3, 0x03
+            0x64, 0x80, //LD V4, 0x80
+            0x83, 0x4E, //SHL V3, V4
+        ];
+        for (name, quirks) in presets() {
+            let emu = run(quirks, &program, 6);
+            let v = emu.v_reg();
+            let expected = match name {
+                //VY is shifted into VX
+                "vip" | "xochip" => [0x40, 0x00],
+                //VX is shifted in place
+                _ => [0x01, 0x06],
+            };
+            assert_eq!([v[1], v[3]], expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn load_store_index_per_preset() {
+        #[rustfmt::skip]
+        let program = [
+            0xA3, 0x00, //LD I, 0x300
+            0xF2, 0x55, //LD [I], V2
+            0xF2, 0x65, //LD V2, [I]
+        ];
+        for (name, quirks) in presets() {
+            let after_store = run(quirks, &program, 2).i_reg();
+            let after_load = run(quirks, &program, 3).i_reg();
+            let expected = match name {
+                "vip" | "xochip" => (0x303, 0x306),
+                "chip48" => (0x302, 0x304),
+                _ => (0x300, 0x300),
+            };
+            assert_eq!((after_store, after_load), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_per_preset() {
+        for logic in [0x21, 0x22, 0x23] {
+            #[rustfmt::skip]
+            let program = [
+                0x6F, 0x05, //LD VF, 5
+                0x61, 0x0F, //LD V1, 0x0F
+                0x62, 0xF0, //LD V2, 0xF0
+                0x81, logic, //OR / AND / XOR V1, V2
+            ];
+            for (name, quirks) in presets() {
+                let emu = run(quirks, &program, 4);
+                let expected = if name == "vip" { 0 } else { 5 };
+                assert_eq!(emu.v_reg()[0xF], expected, "{name} 81{logic:02X}");
+            }
+        }
+    }
+
+    #[test]
+    fn jump_with_offset_per_preset() {
+        #[rustfmt::skip]
+        let program = [
+            0x60, 0x10, //LD V0, 0x10
+            0x62, 0x20, //LD V2, 0x20
+            0xB2, 0x40, //JP V0, 0x240
+        ];
+        for (name, quirks) in presets() {
+            let emu = run(quirks, &program, 3);
+            let expected = match name {
+                //BXNN jumps to XNN + VX
+                "chip48" | "schip" => 0x260,
+                _ => 0x250,
+            };
+            assert_eq!(emu.pc(), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn sprites_at_the_edge_per_preset() {
+        #[rustfmt::skip]
+        let program = [
+            0x60, 0x3C, //LD V0, 60
+            0xA2, 0x06, //LD I, 0x206
+            0xD0, 0x11, //DRW V0, V1, 1
+            0xFF,
+        ];
+        for (name, quirks) in presets() {
+            let emu = run_frames(quirks, &program, 3);
+            let expected = if name == "xochip" { 8 } else { 4 };
+            assert_eq!(lit_pixels(&emu), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn display_wait_per_preset() {
+        #[rustfmt::skip]
+        let program = [
+            0xD0, 0x01, //DRW V0, V0, 1
+            0xD0, 0x01, //DRW V0, V0, 1
+        ];
+        for (name, quirks) in presets() {
+            let mut emu = Emu::with_quirks(quirks);
+            emu.load(&program).unwrap();
+            emu.tick_timers();
+            emu.tick().unwrap();
+            emu.tick().unwrap();
+            let expected = if name == "vip" { 0x202 } else { 0x204 };
+            assert_eq!(emu.pc(), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn memory_size_per_preset() {
+        for (name, quirks) in presets() {
+            let mut emu = Emu::with_quirks(quirks);
+            let expected = if name == "xochip" {
+                RAM_SIZE
+            } else {
+                CLASSIC_RAM_SIZE
+            };
+            assert_eq!(emu.memory_size(), expected, "{name}");
+            assert_eq!(
+                emu.load_at(0x1000, &[0]).is_ok(),
+                name == "xochip",
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn mode_switch_clears_per_preset() {
+        #[rustfmt::skip]
+        let program = [
+            0xA2, 0x08, //LD I, 0x208
+            0xD0, 0x01, //DRW V0, V0, 1
+            0x00, 0xFF, //HIGH
+            0x00, 0xFE, //LOW
+            0xFF,
+        ];
+        for (name, quirks) in presets() {
+            let emu = run_frames(quirks, &program, 4);
+            let expected = if name == "xochip" { 0 } else { 8 };
+            assert_eq!(lit_pixels(&emu), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn big_sprites_in_lores_per_preset() {
+        let mut program = vec![
+            0xA2, 0x06, //LD I, 0x206
+            0xD0, 0x00, //DRW V0, V0, 0
+            0x00, 0x00,
+        ];
+        program.extend_from_slice(&[0xFF; 32]);
+        for (name, quirks) in presets() {
+            let emu = run_frames(quirks, &program, 2);
+            let expected = if name == "xochip" { 16 * 16 } else { 8 * 16 };
+            assert_eq!(lit_pixels(&emu), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn register_ranges_follow_the_order_of_x_and_y() {
+        #[rustfmt::skip]
+        let program = [
+            0x61, 0x01, //LD V1, 1
+            0x62, 0x02, //LD V2, 2
+            0x63, 0x03, //LD V3, 3
+            0xA3, 0x00, //LD I, 0x300
+            0x51, 0x32, //SAVE V1 - V3
+            0xA3, 0x10, //LD I, 0x310
+            0x53, 0x12, //SAVE V3 - V1
+            0x54, 0x63, //LOAD V4 - V6
+            0x59, 0x73, //LOAD V9 - V7
+        ];
+        let emu = run(Quirks::xo_chip(), &program, 9);
+        assert_eq!(&emu.memory()[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(&emu.memory()[0x310..0x313], &[3, 2, 1]);
+        assert_eq!(&emu.v_reg()[4..7], &[3, 2, 1]);
+        assert_eq!(&emu.v_reg()[7..10], &[1, 2, 3]);
+        assert_eq!(emu.i_reg(), 0x310);
+    }
+
+    #[test]
+    fn key_wait_at_top_of_memory_stays_put() {
+        let mut emu = top_of_memory(Quirks::xo_chip(), [0xF0, 0x0A]);
+        emu.tick().unwrap();
+        assert_eq!(emu.pc(), 0xFFFE);
+        emu.keypress(0x5, true);
+        emu.tick().unwrap();
+        assert_eq!(emu.v_reg()[0], 0x5);
+        assert_eq!(emu.pc(), 0x0000);
+    }
+
+    #[test]
+    fn display_wait_at_top_of_memory_stays_put() {
+        let quirks = Quirks {
+            display_wait: true,
+            ..Quirks::xo_chip()
+        };
+        let mut emu = top_of_memory(quirks, [0xD0, 0x01]);
+        emu.tick().unwrap();
+        assert_eq!(emu.pc(), 0xFFFE);
+        emu.tick_timers();
+        emu.tick().unwrap();
+        assert_eq!(emu.pc(), 0x0000);
+    }
+
+    #[test]
+    fn long_load_at_top_of_memory_wraps() {
+        let mut emu = Emu::with_quirks(Quirks::xo_chip());
+        emu.load_at(0xFFFC, &[0xF0, 0x00, 0x12, 0x34]).unwrap();
+        emu.set_pc(0xFFFC);
+        emu.tick().unwrap();
+        assert_eq!(emu.i_reg(), 0x1234);
+        assert_eq!(emu.pc(), 0x0000);
+    }
+
+    #[test]
+    fn undefined_arithmetic_opcodes_are_invalid() {
+        for n in 0x8..=0xDu16 {
+            let op = 0x8120 | n;
+            let mut emu = Emu::new();
+            emu.load(&op.to_be_bytes()).unwrap();
+            emu.set_v_reg(1, 0x42);
+            assert_eq!(
+                emu.tick(),
+                Err(EmuError::InvalidOpcode { pc: 0x200, op }),
+                "{op:04X}"
+            );
+            assert_eq!(emu.v_reg()[1], 0x42, "{op:04X}");
+        }
+    }
+
+    #[test]
+    fn invalid_opcode_at_top_of_memory_reports_its_address() {
+        let mut emu = top_of_memory(Quirks::xo_chip(), [0xE0, 0xFF]);
+        assert_eq!(
+            emu.tick(),
+            Err(EmuError::InvalidOpcode {
+                pc: 0xFFFE,
+                op: 0xE0FF
+            })
+        );
+    }
 }
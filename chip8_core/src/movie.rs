@@ -0,0 +1,414 @@
+use std::fmt;
+
+use crate::quirks::QUIRKS_LEN;
+use crate::snapshot::crc32;
+use crate::{Quirks, RngKind, Timing};
+
+//file layout: magic, version, rng kind, seed, ROM hash, quirks, timing model, instructions per
+//frame, load address, frame count, key states, CRC-32
+const MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u16 = 3;
+
+//input recording: the RNG seed, ROM and machine settings a run started with plus the 16-key
+//state of every frame, one bit per key, which is all it takes to replay the run exactly
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rng_kind: RngKind,
+    pub seed: u64,
+    pub rom_hash: u32,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub load_addr: u16,
+    frames: Vec<u16>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    //does not start with the movie magic
+    BadMagic,
+    //written by an incompatible version
+    UnsupportedVersion(u16),
+    //stored checksum does not match the contents
+    ChecksumMismatch,
+    //buffer ends before the movie does
+    Truncated,
+    //decoded fine but contains values that make no sense
+    Invalid,
+    //movie was recorded with a different ROM, playing it would desync
+    RomMismatch { expected: u32, found: u32 },
+    //the settings asked for are not the ones the movie was recorded with
+    QuirksMismatch { expected: Quirks, found: Quirks },
+    TimingMismatch { expected: Timing, found: Timing },
+    LoadAddrMismatch { expected: u16, found: u16 },
+}
+
+fn describe_quirks(quirks: &Quirks) -> String {
+    match quirks.name() {
+        Some(name) => format!("the {} quirks", name),
+        None => format!("custom quirks ({:?})", quirks),
+    }
+}
+
+fn describe_timing(timing: &Timing) -> String {
+    match timing {
+        Timing::Instructions(count) => format!("{} instructions per frame", count),
+        Timing::Vip => "COSMAC VIP timing".to_string(),
+    }
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(v) => write!(f, "unsupported movie version {}", v),
+            MovieError::ChecksumMismatch => write!(f, "movie file is corrupted"),
+            MovieError::Truncated => write!(f, "movie file is truncated"),
+            MovieError::Invalid => write!(f, "movie file contains invalid values"),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "desync: movie was recorded with ROM {:08X}, this ROM is {:08X}",
+                expected, found
+            ),
+            MovieError::QuirksMismatch { expected, found } => write!(
+                f,
+                "desync: movie was recorded with {}, not {}",
+                describe_quirks(expected),
+                describe_quirks(found)
+            ),
+            MovieError::TimingMismatch { expected, found } => write!(
+                f,
+                "desync: movie was recorded with {}, not {}",
+                describe_timing(expected),
+                describe_timing(found)
+            ),
+            MovieError::LoadAddrMismatch { expected, found } => write!(
+                f,
+                "desync: movie was recorded with the ROM at {:#05X}, not {:#05X}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+//identifies a ROM in movie files
+pub fn rom_hash(rom: &[u8]) -> u32 {
+    crc32(rom)
+}
+
+impl Movie {
+    pub fn new(
+        rng_kind: RngKind,
+        seed: u64,
+        rom: &[u8],
+        quirks: Quirks,
+        timing: Timing,
+        load_addr: u16,
+    ) -> Self {
+        Self {
+            rng_kind,
+            seed,
+            rom_hash: rom_hash(rom),
+            quirks,
+            timing,
+            load_addr,
+            frames: Vec::new(),
+        }
+    }
+
+    //appends the key state used for the next frame, see Emu::key_state
+    pub fn record_frame(&mut self, keys: u16) {
+        self.frames.push(keys);
+    }
+
+    //key state of a frame, None past the end of the recording
+    pub fn frame(&self, idx: usize) -> Option<u16> {
+        self.frames.get(idx).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    //drops everything after the first len frames, used when the player rewinds mid-recording
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+    }
+
+    //reports a desync before playback starts instead of halfway through it
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let found = rom_hash(rom);
+        if found != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_hash,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    //settings asked for on the command line, None where the movie's own are used
+    pub fn check_settings(
+        &self,
+        quirks: Option<Quirks>,
+        timing: Option<Timing>,
+        load_addr: Option<u16>,
+    ) -> Result<(), MovieError> {
+        if let Some(found) = quirks.filter(|&found| found != self.quirks) {
+            return Err(MovieError::QuirksMismatch {
+                expected: self.quirks,
+                found,
+            });
+        }
+        if let Some(found) = timing.filter(|&found| found != self.timing) {
+            return Err(MovieError::TimingMismatch {
+                expected: self.timing,
+                found,
+            });
+        }
+        if let Some(found) = load_addr.filter(|&found| found != self.load_addr) {
+            return Err(MovieError::LoadAddrMismatch {
+                expected: self.load_addr,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.frames.len() * 2 + 32);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        out.push(match self.rng_kind {
+            RngKind::Xorshift => 0,
+            RngKind::Timed => 1,
+        });
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        out.extend_from_slice(&self.quirks.to_bytes());
+        let (model, count) = match self.timing {
+            Timing::Instructions(count) => (0, count),
+            Timing::Vip => (1, 0),
+        };
+        out.push(model);
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&self.load_addr.to_le_bytes());
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for keys in self.frames.iter() {
+            out.extend_from_slice(&keys.to_le_bytes());
+        }
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        //magic, version, rng kind, seed, ROM hash, quirks, timing, load address, frame count
+        //and checksum
+        const FIXED_LEN: usize = 4 + 2 + 1 + 8 + 4 + QUIRKS_LEN + 1 + 4 + 2 + 4 + 4;
+        if data.len() < 6 {
+            return Err(MovieError::Truncated);
+        }
+        if &data[..4] != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        if data.len() < FIXED_LEN {
+            return Err(MovieError::Truncated);
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+            return Err(MovieError::ChecksumMismatch);
+        }
+
+        let rng_kind = match body[6] {
+            0 => RngKind::Xorshift,
+            1 => RngKind::Timed,
+            _ => return Err(MovieError::Invalid),
+        };
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&body[7..15]);
+        let rom_hash = u32::from_le_bytes([body[15], body[16], body[17], body[18]]);
+        let (quirks, rest) = body[19..].split_at(QUIRKS_LEN);
+        let mut quirk_bytes = [0; QUIRKS_LEN];
+        quirk_bytes.copy_from_slice(quirks);
+        let quirks = Quirks::from_bytes(quirk_bytes).ok_or(MovieError::Invalid)?;
+        let instructions = u32::from_le_bytes([rest[1], rest[2], rest[3], rest[4]]);
+        let timing = match rest[0] {
+            0 if instructions > 0 => Timing::Instructions(instructions),
+            1 => Timing::Vip,
+            _ => return Err(MovieError::Invalid),
+        };
+        let load_addr = u16::from_le_bytes([rest[5], rest[6]]);
+        let count = u32::from_le_bytes([rest[7], rest[8], rest[9], rest[10]]) as usize;
+        let keys = &rest[11..];
+        if keys.len() != count * 2 {
+            return Err(MovieError::Truncated);
+        }
+        let frames = keys
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+
+        Ok(Self {
+            rng_kind,
+            seed: u64::from_le_bytes(seed),
+            rom_hash,
+            quirks,
+            timing,
+            load_addr,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 4] = [0x12, 0x00, 0xF0, 0x0A];
+
+    fn movie(quirks: Quirks, timing: Timing, load_addr: u16) -> Movie {
+        let mut movie = Movie::new(
+            RngKind::Timed,
+            0xDEAD_BEEF_0123,
+            &ROM,
+            quirks,
+            timing,
+            load_addr,
+        );
+        for keys in [0, 0x0001, 0x8010, 0xFFFF, 0] {
+            movie.record_frame(keys);
+        }
+        movie
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut custom = Quirks::vip();
+        custom.clip_sprites = false;
+        for quirks in [Quirks::default(), Quirks::vip(), Quirks::xo_chip(), custom] {
+            for timing in [
+                Timing::Instructions(10),
+                Timing::Instructions(1000),
+                Timing::Vip,
+            ] {
+                for load_addr in [0x200, 0x600] {
+                    let movie = movie(quirks, timing, load_addr);
+                    assert_eq!(Movie::from_bytes(&movie.to_bytes()), Ok(movie));
+                }
+            }
+        }
+        let empty = Movie::new(
+            RngKind::Xorshift,
+            0,
+            &ROM,
+            Quirks::default(),
+            Timing::Vip,
+            0x200,
+        );
+        assert_eq!(Movie::from_bytes(&empty.to_bytes()), Ok(empty));
+    }
+
+    #[test]
+    fn damaged_files() {
+        let bytes = movie(Quirks::schip(), Timing::Instructions(10), 0x200).to_bytes();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(Movie::from_bytes(&bad_magic), Err(MovieError::BadMagic));
+
+        //movies from before the settings were stored
+        let mut old = bytes.clone();
+        old[4..6].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(
+            Movie::from_bytes(&old),
+            Err(MovieError::UnsupportedVersion(1))
+        );
+
+        let mut corrupted = bytes.clone();
+        corrupted[20] ^= 1;
+        assert_eq!(
+            Movie::from_bytes(&corrupted),
+            Err(MovieError::ChecksumMismatch)
+        );
+
+        for len in 0..6 {
+            assert_eq!(Movie::from_bytes(&bytes[..len]), Err(MovieError::Truncated));
+        }
+    }
+
+    #[test]
+    fn impossible_settings_are_invalid() {
+        let bytes = movie(Quirks::schip(), Timing::Instructions(10), 0x200).to_bytes();
+        let reseal = |bytes: &mut Vec<u8>| {
+            let end = bytes.len() - 4;
+            let checksum = crc32(&bytes[..end]);
+            bytes[end..].copy_from_slice(&checksum.to_le_bytes());
+        };
+        //a quirk flag that is not a bool, an unknown timing model, no instructions per frame
+        let timing = 19 + QUIRKS_LEN;
+        for (pos, value) in [(19, 2), (timing, 2), (timing + 1, 0)] {
+            let mut edited = bytes.clone();
+            edited[pos] = value;
+            reseal(&mut edited);
+            assert_eq!(
+                Movie::from_bytes(&edited),
+                Err(MovieError::Invalid),
+                "byte {}",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn settings_on_the_command_line_have_to_match() {
+        let movie = movie(Quirks::schip(), Timing::Instructions(10), 0x600);
+        assert_eq!(movie.check_settings(None, None, None), Ok(()));
+        assert_eq!(
+            movie.check_settings(
+                Some(Quirks::schip()),
+                Some(Timing::Instructions(10)),
+                Some(0x600)
+            ),
+            Ok(())
+        );
+
+        let err = movie
+            .check_settings(Some(Quirks::vip()), None, None)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            MovieError::QuirksMismatch {
+                expected: Quirks::schip(),
+                found: Quirks::vip()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "desync: movie was recorded with the schip quirks, not the vip quirks"
+        );
+
+        let err = movie
+            .check_settings(None, Some(Timing::Vip), None)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "desync: movie was recorded with 10 instructions per frame, not COSMAC VIP timing"
+        );
+
+        let err = movie.check_settings(None, None, Some(0x200)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "desync: movie was recorded with the ROM at 0x600, not 0x200"
+        );
+    }
+}
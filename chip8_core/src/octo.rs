@@ -0,0 +1,1373 @@
+//compiler for Octo, the assembly language most community CHIP-8 programs are written in
+//
+//    :alias x v1
+//    :const SPEED 2
+//    : main
+//        x := 0
+//        loop
+//            i := sprite
+//            sprite x v2 5
+//            x += SPEED
+//            if x == 64 then x := 0
+//        again
+//    : sprite
+//        0xF0 0x90 0xF0
+//
+//tokens are separated by whitespace and `#` starts a comment, everything below 0x200 is reserved
+//for the interpreter so programs start at START_ADDR with a jump to `main` unless it comes first
+
+use crate::START_ADDR;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+//an error together with where in the source it happened, both 1-based
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+//a compiled program together with what a debugger needs to map addresses back to the source
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    //bytes to load at START_ADDR
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+    //address of every emitted instruction to the source line it came from
+    pub lines: BTreeMap<u16, usize>,
+    //`:breakpoint name` directives
+    pub breakpoints: Vec<(String, u16)>,
+}
+
+pub fn compile(source: &str) -> Result<Program, CompileError> {
+    let mut compiler = Compiler::new(tokenize(source));
+    compiler.run()?;
+    compiler.finish()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    //line for the line map, macro expansions count as the line that invoked them
+    origin: usize,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (idx, line) in source.lines().enumerate() {
+        //byte offset and column in characters of the word being read
+        let mut start = None;
+        //a trailing space makes sure the last word is pushed
+        for (column, (pos, c)) in line
+            .char_indices()
+            .chain(std::iter::once((line.len(), ' ')))
+            .enumerate()
+        {
+            match (c.is_whitespace(), start) {
+                (false, None) => {
+                    if c == '#' {
+                        break;
+                    }
+                    start = Some((pos, column));
+                }
+                (true, Some((begin, begin_column))) => {
+                    tokens.push_back(Token {
+                        text: line[begin..pos].to_string(),
+                        line: idx + 1,
+                        column: begin_column + 1,
+                        origin: idx + 1,
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(bin) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(bin, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+//`:unpack n` puts n into the high nibble, `:unpack long` keeps the whole high byte
+fn unpack_high(nibble: Option<u8>, addr: u16) -> u8 {
+    match nibble {
+        Some(n) => n << 4 | (addr >> 8) as u8 & 0xF,
+        None => (addr >> 8) as u8,
+    }
+}
+
+//how a forward reference is written once the label is known
+#[derive(Clone, Copy, Debug)]
+enum FixupKind {
+    //low 12 bits of the instruction at the address
+    Addr,
+    //the 16 bit word at the address, F000 NNNN
+    Long,
+    //the byte at the address
+    Byte,
+    //:unpack, the high or low byte of the address, UnpackHigh ORs in a nibble
+    UnpackHigh(Option<u8>),
+    UnpackLow,
+}
+
+struct Fixup {
+    at: usize,
+    kind: FixupKind,
+    name: Token,
+}
+
+//open loop or if ... begin blocks
+enum Block {
+    Loop {
+        start: usize,
+        //`while` jumps out of the loop, patched at `again`
+        exits: Vec<usize>,
+        token: Token,
+    },
+    If {
+        //jump over the current branch, patched at `else` or `end`
+        jump: usize,
+        token: Token,
+    },
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+//right hand side of a comparison or assignment
+enum Rhs {
+    Reg(u8),
+    Value(Token),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CondOp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Key,
+    NotKey,
+}
+
+impl CondOp {
+    fn negate(self) -> CondOp {
+        match self {
+            CondOp::Eq => CondOp::Ne,
+            CondOp::Ne => CondOp::Eq,
+            CondOp::Lt => CondOp::Ge,
+            CondOp::Ge => CondOp::Lt,
+            CondOp::Gt => CondOp::Le,
+            CondOp::Le => CondOp::Gt,
+            CondOp::Key => CondOp::NotKey,
+            CondOp::NotKey => CondOp::Key,
+        }
+    }
+}
+
+struct Cond {
+    x: u8,
+    op: CondOp,
+    rhs: Option<Rhs>,
+}
+
+//macros may invoke other macros, this stops one that invokes itself
+const MAX_EXPANSIONS: usize = 10_000;
+const MEMORY_SIZE: usize = 0x10000;
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    memory: Vec<u8>,
+    here: usize,
+    //one past the highest byte written
+    end: usize,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    lines: BTreeMap<u16, usize>,
+    breakpoints: Vec<(String, u16)>,
+    expansions: usize,
+    //line of the statement being compiled, for the line map
+    line: usize,
+    //for errors at the end of the source
+    last: Token,
+    //whether 0x200 holds a jump to main, not needed when main comes first
+    main_jump: bool,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        let last = tokens.back().cloned().unwrap_or(Token {
+            text: String::new(),
+            line: 1,
+            column: 1,
+            origin: 1,
+        });
+        let main_first = tokens.len() >= 2 && tokens[0].text == ":" && tokens[1].text == "main";
+        let start = START_ADDR as usize;
+        Self {
+            tokens,
+            memory: vec![0; MEMORY_SIZE],
+            here: if main_first { start } else { start + 2 },
+            end: if main_first { start } else { start + 2 },
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            lines: BTreeMap::new(),
+            breakpoints: Vec::new(),
+            expansions: 0,
+            line: 1,
+            last,
+            main_jump: !main_first,
+        }
+    }
+
+    fn error<T>(token: &Token, message: String) -> Result<T, CompileError> {
+        Err(CompileError {
+            line: token.line,
+            column: token.column,
+            message,
+        })
+    }
+
+    fn next(&mut self) -> Result<Token, CompileError> {
+        match self.tokens.pop_front() {
+            Some(token) => Ok(token),
+            None => Self::error(&self.last, "unexpected end of source".to_string()),
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, CompileError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Self::error(
+                &token,
+                format!("expected `{}`, found `{}`", text, token.text),
+            );
+        }
+        Ok(token)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    //=== names and values ===
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        if let Some(&reg) = self.aliases.get(&token.text) {
+            return Some(reg);
+        }
+        let mut chars = token.text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v') | Some('V'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u8, CompileError> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(reg) => Ok(reg),
+            None => Self::error(
+                &token,
+                format!("expected a register, found `{}`", token.text),
+            ),
+        }
+    }
+
+    //number, constant or a label that is already defined
+    fn known_value(&self, token: &Token) -> Option<f64> {
+        parse_number(&token.text)
+            .or_else(|| self.consts.get(&token.text).copied())
+            .or_else(|| self.labels.get(&token.text).map(|&addr| addr as f64))
+    }
+
+    fn check_name(&self, token: &Token) -> Result<(), CompileError> {
+        let valid = token
+            .text
+            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && token
+                .text
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid || self.register(token).is_some() {
+            return Self::error(token, format!("`{}` cannot be used as a name", token.text));
+        }
+        if self.labels.contains_key(&token.text)
+            || self.consts.contains_key(&token.text)
+            || self.aliases.contains_key(&token.text)
+            || self.macros.contains_key(&token.text)
+        {
+            return Self::error(token, format!("`{}` is already defined", token.text));
+        }
+        Ok(())
+    }
+
+    //a value that has to be known right away, e.g. a sprite height
+    fn constant(&self, token: &Token, max: i64) -> Result<u16, CompileError> {
+        let Some(value) = self.known_value(token) else {
+            return Self::error(
+                token,
+                format!("`{}` is not a number or constant", token.text),
+            );
+        };
+        let value = value.floor() as i64;
+        if value < 0 || value > max {
+            return Self::error(token, format!("{} does not fit into 0..={:#X}", value, max));
+        }
+        Ok(value as u16)
+    }
+
+    fn byte_value(token: &Token, value: f64) -> Result<u8, CompileError> {
+        let value = value.floor() as i64;
+        if !(-128..=0xFF).contains(&value) {
+            return Self::error(token, format!("{} does not fit into a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    //a byte operand, labels that are not defined yet are filled in at the end
+    fn byte_field(&mut self, token: Token, at: usize) -> Result<u8, CompileError> {
+        match self.known_value(&token) {
+            Some(value) => Self::byte_value(&token, value),
+            None => {
+                self.fixups.push(Fixup {
+                    at,
+                    kind: FixupKind::Byte,
+                    name: token,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    //a 12 bit address operand of the instruction at `at`
+    fn addr_field(&mut self, token: Token, at: usize) -> Result<u16, CompileError> {
+        match self.known_value(&token) {
+            Some(value) => {
+                let value = value.floor() as i64;
+                if !(0..=0xFFF).contains(&value) {
+                    return Self::error(
+                        &token,
+                        format!("address {:#X} does not fit into 12 bits", value),
+                    );
+                }
+                Ok(value as u16)
+            }
+            None => {
+                self.fixups.push(Fixup {
+                    at,
+                    kind: FixupKind::Addr,
+                    name: token,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    //=== output ===
+
+    fn emit_byte(&mut self, byte: u8, token: &Token) -> Result<(), CompileError> {
+        if self.here >= MEMORY_SIZE {
+            return Self::error(token, "program does not fit into 64K".to_string());
+        }
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn emit(&mut self, op: u16, token: &Token) -> Result<(), CompileError> {
+        self.lines.insert(self.here as u16, self.line);
+        let [high, low] = op.to_be_bytes();
+        self.emit_byte(high, token)?;
+        self.emit_byte(low, token)
+    }
+
+    //ORs an address into the jump at `at`
+    fn patch_addr(&mut self, at: usize, addr: usize, token: &Token) -> Result<(), CompileError> {
+        if addr > 0xFFF {
+            return Self::error(
+                token,
+                format!("address {:#X} does not fit into 12 bits", addr),
+            );
+        }
+        self.memory[at] = (self.memory[at] & 0xF0) | (addr >> 8) as u8;
+        self.memory[at + 1] = addr as u8;
+        Ok(())
+    }
+
+    //=== statements ===
+
+    fn run(&mut self) -> Result<(), CompileError> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), CompileError> {
+        let token = self.next()?;
+        self.line = token.origin;
+
+        if let Some(x) = self.register(&token) {
+            return self.register_op(x, token);
+        }
+        if self.macros.contains_key(&token.text) {
+            return self.expand(token);
+        }
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.labels.insert(name.text, self.here as u16);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let reg = self.expect_register()?;
+                self.aliases.insert(name.text, reg);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.next()?;
+                let Some(value) = self.known_value(&value) else {
+                    return Self::error(
+                        &value,
+                        format!("`{}` is not a number or constant", value.text),
+                    );
+                };
+                self.consts.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value = self.calc_block()?;
+                self.consts.insert(name.text, value);
+            }
+            ":byte" => {
+                let byte = if self.peek_is("{") {
+                    let value = self.calc_block()?;
+                    Self::byte_value(&token, value)?
+                } else {
+                    let value = self.next()?;
+                    self.byte_field(value, self.here)?
+                };
+                self.emit_byte(byte, &token)?;
+            }
+            ":org" => {
+                let value = if self.peek_is("{") {
+                    self.calc_block()?
+                } else {
+                    let value = self.next()?;
+                    self.constant(&value, 0xFFFF)? as f64
+                };
+                let addr = value.floor() as i64;
+                if addr < START_ADDR as i64 || addr >= MEMORY_SIZE as i64 {
+                    return Self::error(
+                        &token,
+                        format!(":org {:#X} is outside of the program area", addr),
+                    );
+                }
+                self.here = addr as usize;
+            }
+            ":macro" => self.define_macro()?,
+            ":next" => {
+                //the label points at the operand of the next instruction, for self-modifying code
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.labels.insert(name.text, self.here as u16 + 1);
+            }
+            ":unpack" => self.unpack()?,
+            ":breakpoint" => {
+                let name = self.next()?;
+                self.breakpoints.push((name.text, self.here as u16));
+            }
+            ":call" => {
+                let target = self.next()?;
+                let addr = self.addr_field(target, self.here)?;
+                self.emit(0x2000 | addr, &token)?;
+            }
+            ";" | "return" => self.emit(0x00EE, &token)?,
+            "clear" => self.emit(0x00E0, &token)?,
+            "scroll-right" => self.emit(0x00FB, &token)?,
+            "scroll-left" => self.emit(0x00FC, &token)?,
+            "exit" => self.emit(0x00FD, &token)?,
+            "lores" => self.emit(0x00FE, &token)?,
+            "hires" => self.emit(0x00FF, &token)?,
+            "audio" => self.emit(0xF002, &token)?,
+            "scroll-down" | "scroll-up" => {
+                let value = self.next()?;
+                let n = self.constant(&value, 0xF)?;
+                let base = if token.text == "scroll-down" {
+                    0x00C0
+                } else {
+                    0x00D0
+                };
+                self.emit(base | n, &token)?;
+            }
+            "plane" => {
+                let value = self.next()?;
+                let n = self.constant(&value, 0xF)?;
+                self.emit(0xF001 | n << 8, &token)?;
+            }
+            "bcd" => {
+                let x = self.expect_register()? as u16;
+                self.emit(0xF033 | x << 8, &token)?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register()? as u16;
+                let op = if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.expect_register()? as u16;
+                    let base = if token.text == "save" { 0x5002 } else { 0x5003 };
+                    base | x << 8 | y << 4
+                } else {
+                    let base = if token.text == "save" { 0xF055 } else { 0xF065 };
+                    base | x << 8
+                };
+                self.emit(op, &token)?;
+            }
+            "saveflags" => {
+                let x = self.expect_register()? as u16;
+                self.emit(0xF075 | x << 8, &token)?;
+            }
+            "loadflags" => {
+                let x = self.expect_register()? as u16;
+                self.emit(0xF085 | x << 8, &token)?;
+            }
+            "sprite" => {
+                let x = self.expect_register()? as u16;
+                let y = self.expect_register()? as u16;
+                let height = self.next()?;
+                let n = self.constant(&height, 0xF)?;
+                self.emit(0xD000 | x << 8 | y << 4 | n, &token)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.next()?;
+                let addr = self.addr_field(target, self.here)?;
+                let base = match token.text.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                self.emit(base | addr, &token)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.expect_register()? as u16;
+                let base = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(base | x << 8, &token)?;
+            }
+            "i" => self.index_op(token)?,
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                exits: Vec::new(),
+                token,
+            }),
+            "while" => {
+                let cond = self.condition()?;
+                //skip the exit jump while the condition holds
+                self.emit_skip_unless(&cond, true, &token)?;
+                let at = self.here;
+                self.emit(0x1000, &token)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { exits, .. }) => exits.push(at),
+                    _ => return Self::error(&token, "`while` outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    let at = self.here;
+                    self.emit(0x1000, &token)?;
+                    self.patch_addr(at, start, &token)?;
+                    for exit in exits {
+                        self.patch_addr(exit, self.here, &token)?;
+                    }
+                }
+                _ => return Self::error(&token, "`again` without `loop`".to_string()),
+            },
+            "if" => self.if_statement(token)?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If {
+                    jump,
+                    token: opened,
+                }) => {
+                    let at = self.here;
+                    self.emit(0x1000, &token)?;
+                    self.patch_addr(jump, self.here, &token)?;
+                    self.blocks.push(Block::If {
+                        jump: at,
+                        token: opened,
+                    });
+                }
+                _ => return Self::error(&token, "`else` without `if ... begin`".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => self.patch_addr(jump, self.here, &token)?,
+                _ => return Self::error(&token, "`end` without `if ... begin`".to_string()),
+            },
+            _ => {
+                //bare numbers and constants are data, anything else calls a subroutine
+                if let Some(value) =
+                    parse_number(&token.text).or_else(|| self.consts.get(&token.text).copied())
+                {
+                    let byte = Self::byte_value(&token, value)?;
+                    return self.emit_byte(byte, &token);
+                }
+                if token.text.starts_with(':') {
+                    return Self::error(&token, format!("unknown directive `{}`", token.text));
+                }
+                let addr = self.addr_field(token.clone(), self.here)?;
+                self.emit(0x2000 | addr, &token)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn register_op(&mut self, x: u8, token: Token) -> Result<(), CompileError> {
+        let x16 = x as u16;
+        let op_token = self.next()?;
+        let source = self.next()?;
+        let op = match op_token.text.as_str() {
+            ":=" => match source.text.as_str() {
+                "key" => 0xF00A | x16 << 8,
+                "delay" => 0xF007 | x16 << 8,
+                "random" => {
+                    let mask = self.next()?;
+                    let at = self.here + 1;
+                    0xC000 | x16 << 8 | self.byte_field(mask, at)? as u16
+                }
+                _ => match self.register(&source) {
+                    Some(y) => 0x8000 | x16 << 8 | (y as u16) << 4,
+                    None => {
+                        let at = self.here + 1;
+                        0x6000 | x16 << 8 | self.byte_field(source, at)? as u16
+                    }
+                },
+            },
+            "+=" => match self.register(&source) {
+                Some(y) => 0x8004 | x16 << 8 | (y as u16) << 4,
+                None => {
+                    let at = self.here + 1;
+                    0x7000 | x16 << 8 | self.byte_field(source, at)? as u16
+                }
+            },
+            "-=" => match self.register(&source) {
+                Some(y) => 0x8005 | x16 << 8 | (y as u16) << 4,
+                None => {
+                    //there is no subtract immediate, add the two's complement instead
+                    let Some(value) = self.known_value(&source) else {
+                        return Self::error(
+                            &source,
+                            format!("`{}` is not a number or constant", source.text),
+                        );
+                    };
+                    let byte = Self::byte_value(&source, value)?;
+                    0x7000 | x16 << 8 | byte.wrapping_neg() as u16
+                }
+            },
+            "|=" | "&=" | "^=" | "=-" | ">>=" | "<<=" => {
+                let Some(y) = self.register(&source) else {
+                    return Self::error(&source, format!("`{}` needs a register", op_token.text));
+                };
+                let low = match op_token.text.as_str() {
+                    "|=" => 1,
+                    "&=" => 2,
+                    "^=" => 3,
+                    "=-" => 7,
+                    ">>=" => 6,
+                    _ => 0xE,
+                };
+                0x8000 | x16 << 8 | (y as u16) << 4 | low
+            }
+            _ => return Self::error(&op_token, format!("unknown operator `{}`", op_token.text)),
+        };
+        self.emit(op, &token)
+    }
+
+    fn index_op(&mut self, token: Token) -> Result<(), CompileError> {
+        let op_token = self.next()?;
+        match op_token.text.as_str() {
+            "+=" => {
+                let x = self.expect_register()? as u16;
+                self.emit(0xF01E | x << 8, &token)
+            }
+            ":=" => {
+                let source = self.next()?;
+                match source.text.as_str() {
+                    "hex" => {
+                        let x = self.expect_register()? as u16;
+                        self.emit(0xF029 | x << 8, &token)
+                    }
+                    "bighex" => {
+                        let x = self.expect_register()? as u16;
+                        self.emit(0xF030 | x << 8, &token)
+                    }
+                    "long" => {
+                        let target = self.next()?;
+                        self.emit(0xF000, &token)?;
+                        let at = self.here;
+                        let addr = match self.known_value(&target) {
+                            Some(value) => {
+                                let value = value.floor() as i64;
+                                if !(0..=0xFFFF).contains(&value) {
+                                    return Self::error(
+                                        &target,
+                                        format!("address {:#X} does not fit into 16 bits", value),
+                                    );
+                                }
+                                value as u16
+                            }
+                            None => {
+                                self.fixups.push(Fixup {
+                                    at,
+                                    kind: FixupKind::Long,
+                                    name: target.clone(),
+                                });
+                                0
+                            }
+                        };
+                        let [high, low] = addr.to_be_bytes();
+                        self.emit_byte(high, &target)?;
+                        self.emit_byte(low, &target)
+                    }
+                    _ => {
+                        let addr = self.addr_field(source, self.here)?;
+                        self.emit(0xA000 | addr, &token)
+                    }
+                }
+            }
+            _ => Self::error(&op_token, format!("unknown operator `{}`", op_token.text)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Cond, CompileError> {
+        let x = self.expect_register()?;
+        let op_token = self.next()?;
+        let op = match op_token.text.as_str() {
+            "==" => CondOp::Eq,
+            "!=" => CondOp::Ne,
+            "<" => CondOp::Lt,
+            ">" => CondOp::Gt,
+            "<=" => CondOp::Le,
+            ">=" => CondOp::Ge,
+            "key" => {
+                return Ok(Cond {
+                    x,
+                    op: CondOp::Key,
+                    rhs: None,
+                })
+            }
+            "-key" => {
+                return Ok(Cond {
+                    x,
+                    op: CondOp::NotKey,
+                    rhs: None,
+                })
+            }
+            _ => return Self::error(&op_token, format!("unknown comparison `{}`", op_token.text)),
+        };
+        let rhs = self.next()?;
+        let rhs = match self.register(&rhs) {
+            Some(y) => Rhs::Reg(y),
+            None => Rhs::Value(rhs),
+        };
+        Ok(Cond {
+            x,
+            op,
+            rhs: Some(rhs),
+        })
+    }
+
+    //emits instructions that skip the next one unless the condition holds,
+    //or skip it when the condition holds if `negate` is set
+    fn emit_skip_unless(
+        &mut self,
+        cond: &Cond,
+        negate: bool,
+        token: &Token,
+    ) -> Result<(), CompileError> {
+        let op = if negate { cond.op.negate() } else { cond.op };
+        let x = cond.x as u16;
+        match (op, &cond.rhs) {
+            (CondOp::Key, _) => self.emit(0xE0A1 | x << 8, token),
+            (CondOp::NotKey, _) => self.emit(0xE09E | x << 8, token),
+            (CondOp::Eq, Some(Rhs::Reg(y))) => self.emit(0x9000 | x << 8 | (*y as u16) << 4, token),
+            (CondOp::Ne, Some(Rhs::Reg(y))) => self.emit(0x5000 | x << 8 | (*y as u16) << 4, token),
+            (CondOp::Eq | CondOp::Ne, Some(Rhs::Value(value))) => {
+                let base = if op == CondOp::Eq { 0x4000 } else { 0x3000 };
+                let at = self.here + 1;
+                let byte = self.byte_field(value.clone(), at)? as u16;
+                self.emit(base | x << 8 | byte, token)
+            }
+            //the rest compares through vF: vF := rhs, then subtract and test the carry
+            (_, Some(rhs)) => {
+                match rhs {
+                    Rhs::Reg(y) => self.emit(0x8F00 | (*y as u16) << 4, token)?,
+                    Rhs::Value(value) => {
+                        let at = self.here + 1;
+                        let byte = self.byte_field(value.clone(), at)? as u16;
+                        self.emit(0x6F00 | byte, token)?;
+                    }
+                }
+                let (subtract, skip) = match op {
+                    //vF := rhs - vX, no borrow means vX <= rhs
+                    CondOp::Gt => (0x8F05, 0x3F01),
+                    CondOp::Le => (0x8F05, 0x3F00),
+                    //vF := vX - rhs, no borrow means vX >= rhs
+                    CondOp::Lt => (0x8F07, 0x3F01),
+                    _ => (0x8F07, 0x3F00),
+                };
+                self.emit(subtract | x << 4, token)?;
+                self.emit(skip, token)
+            }
+            (_, None) => unreachable!("comparisons always have a right hand side"),
+        }
+    }
+
+    fn if_statement(&mut self, token: Token) -> Result<(), CompileError> {
+        let cond = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            "then" => {
+                self.emit_skip_unless(&cond, false, &token)?;
+                if self.tokens.is_empty() {
+                    return Self::error(&keyword, "`then` needs a statement".to_string());
+                }
+                self.statement()
+            }
+            "begin" => {
+                //jump over the block unless the condition holds
+                self.emit_skip_unless(&cond, true, &token)?;
+                let jump = self.here;
+                self.emit(0x1000, &token)?;
+                self.blocks.push(Block::If { jump, token });
+                Ok(())
+            }
+            _ => Self::error(
+                &keyword,
+                format!("expected `then` or `begin`, found `{}`", keyword.text),
+            ),
+        }
+    }
+
+    fn unpack(&mut self) -> Result<(), CompileError> {
+        let high = self.next()?;
+        let nibble = if high.text == "long" {
+            None
+        } else {
+            Some(self.constant(&high, 0xF)? as u8)
+        };
+        let name = self.next()?;
+        let addr = self
+            .known_value(&name)
+            .map(|value| value.floor() as i64 as u16);
+        //v0 := high part, v1 := low byte
+        if addr.is_none() {
+            self.fixups.push(Fixup {
+                at: self.here + 1,
+                kind: FixupKind::UnpackHigh(nibble),
+                name: name.clone(),
+            });
+            self.fixups.push(Fixup {
+                at: self.here + 3,
+                kind: FixupKind::UnpackLow,
+                name: name.clone(),
+            });
+        }
+        let addr = addr.unwrap_or_default();
+        self.emit(0x6000 | unpack_high(nibble, addr) as u16, &name)?;
+        self.emit(0x6100 | addr & 0xFF, &name)
+    }
+
+    //=== macros ===
+
+    fn define_macro(&mut self) -> Result<(), CompileError> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => (),
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, token: Token) -> Result<(), CompileError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Self::error(&token, format!("macro `{}` expands forever", token.text));
+        }
+        let count = self.macros[&token.text].params.len();
+        let mut args = Vec::new();
+        for _ in 0..count {
+            args.push(self.next()?.text);
+        }
+        let definition = &self.macros[&token.text];
+        let expanded: Vec<_> = definition
+            .body
+            .iter()
+            .map(|body_token| {
+                let mut body_token = body_token.clone();
+                body_token.origin = token.origin;
+                if let Some(idx) = definition
+                    .params
+                    .iter()
+                    .position(|param| *param == body_token.text)
+                {
+                    body_token.text = args[idx].clone();
+                }
+                body_token
+            })
+            .collect();
+        for body_token in expanded.into_iter().rev() {
+            self.tokens.push_front(body_token);
+        }
+        Ok(())
+    }
+
+    //=== :calc ===
+
+    //{ expression }, evaluated right to left without precedence as in Octo
+    fn calc_block(&mut self) -> Result<f64, CompileError> {
+        self.expect("{")?;
+        let value = self.calc_expr()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn calc_expr(&mut self) -> Result<f64, CompileError> {
+        let lhs = self.calc_term()?;
+        let Some(op) = self.tokens.front().cloned() else {
+            return Ok(lhs);
+        };
+        let apply: fn(f64, f64) -> f64 = match op.text.as_str() {
+            "+" => |a, b| a + b,
+            "-" => |a, b| a - b,
+            "*" => |a, b| a * b,
+            "/" => |a, b| a / b,
+            "%" => |a, b| a % b,
+            "&" => |a, b| ((a as i64) & (b as i64)) as f64,
+            "|" => |a, b| ((a as i64) | (b as i64)) as f64,
+            "^" => |a, b| ((a as i64) ^ (b as i64)) as f64,
+            "<<" => |a, b| ((a as i64) << (b as i64)) as f64,
+            ">>" => |a, b| ((a as i64) >> (b as i64)) as f64,
+            "pow" => f64::powf,
+            "min" => f64::min,
+            "max" => f64::max,
+            "<" => |a, b| (a < b) as u8 as f64,
+            ">" => |a, b| (a > b) as u8 as f64,
+            "<=" => |a, b| (a <= b) as u8 as f64,
+            ">=" => |a, b| (a >= b) as u8 as f64,
+            "==" => |a, b| (a == b) as u8 as f64,
+            "!=" => |a, b| (a != b) as u8 as f64,
+            _ => return Ok(lhs),
+        };
+        self.next()?;
+        let rhs = self.calc_expr()?;
+        if (op.text == "/" || op.text == "%") && rhs == 0.0 {
+            return Self::error(&op, "division by zero".to_string());
+        }
+        if (op.text == "<<" || op.text == ">>") && !(0.0..64.0).contains(&rhs) {
+            return Self::error(&op, format!("cannot shift by {rhs}"));
+        }
+        Ok(apply(lhs, rhs))
+    }
+
+    fn calc_term(&mut self) -> Result<f64, CompileError> {
+        let token = self.next()?;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|a| -a),
+            "~" => Some(|a| !(a as i64) as f64),
+            "!" => Some(|a| (a == 0.0) as u8 as f64),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "floor" => Some(f64::floor),
+            "ceil" => Some(f64::ceil),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.calc_term()?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expr()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            //byte that was already compiled at an address
+            "@" => {
+                let addr = self.calc_term()?.floor() as i64;
+                if !(0..MEMORY_SIZE as i64).contains(&addr) {
+                    return Self::error(&token, format!("address {:#X} is out of range", addr));
+                }
+                Ok(self.memory[addr as usize] as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => match self.known_value(&token) {
+                Some(value) => Ok(value),
+                None => Self::error(&token, format!("unknown name `{}`", token.text)),
+            },
+        }
+    }
+
+    //=== end of source ===
+
+    fn finish(mut self) -> Result<Program, CompileError> {
+        if let Some(block) = self.blocks.last() {
+            return match block {
+                Block::Loop { token, .. } => {
+                    Self::error(token, "`loop` without `again`".to_string())
+                }
+                Block::If { token, .. } => {
+                    Self::error(token, "`if ... begin` without `end`".to_string())
+                }
+            };
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&addr) = self.labels.get(&fixup.name.text) else {
+                return Self::error(&fixup.name, format!("undefined name `{}`", fixup.name.text));
+            };
+            let at = fixup.at;
+            match fixup.kind {
+                FixupKind::Addr => self.patch_addr(at, addr as usize, &fixup.name)?,
+                FixupKind::Long => {
+                    let [high, low] = addr.to_be_bytes();
+                    self.memory[at] = high;
+                    self.memory[at + 1] = low;
+                }
+                FixupKind::Byte => self.memory[at] = Self::byte_value(&fixup.name, addr as f64)?,
+                FixupKind::UnpackHigh(nibble) => self.memory[at] = unpack_high(nibble, addr),
+                FixupKind::UnpackLow => self.memory[at] = addr as u8,
+            }
+        }
+        let start = START_ADDR as usize;
+        if self.main_jump {
+            let Some(&main) = self.labels.get("main") else {
+                return Self::error(&self.last, "the program has no `main` label".to_string());
+            };
+            self.memory[start] = 0x10;
+            self.patch_addr(start, main as usize, &self.last.clone())?;
+            self.lines.entry(START_ADDR).or_insert(1);
+        }
+        Ok(Program {
+            rom: self.memory[start..self.end].to_vec(),
+            labels: self.labels.into_iter().collect(),
+            lines: self.lines,
+            breakpoints: self.breakpoints,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emu;
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source)
+            .unwrap_or_else(|err| panic!("{} in\n{}", err, source))
+            .rom
+    }
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = compile(source).unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn loop_while_and_again() {
+        let source = "
+            : main
+                loop
+                    v0 += 1
+                    while v0 != 5
+                again
+        ";
+        //SE skips the exit jump while the condition holds, the exit lands after `again`
+        assert_eq!(
+            rom(source),
+            [0x70, 0x01, 0x40, 0x05, 0x12, 0x08, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn if_then_skips_the_statement() {
+        let cases: [(&str, &[u8]); 8] = [
+            ("v4 == v5", &[0x94, 0x50]),
+            ("v4 != 2", &[0x34, 0x02]),
+            ("v0 key", &[0xE0, 0xA1]),
+            ("v0 -key", &[0xE0, 0x9E]),
+            //the rest goes through vF
+            ("v1 < v2", &[0x8F, 0x20, 0x8F, 0x17, 0x3F, 0x01]),
+            ("v1 >= 3", &[0x6F, 0x03, 0x8F, 0x17, 0x3F, 0x00]),
+            ("v1 > v2", &[0x8F, 0x20, 0x8F, 0x15, 0x3F, 0x01]),
+            ("v1 <= 7", &[0x6F, 0x07, 0x8F, 0x15, 0x3F, 0x00]),
+        ];
+        for (cond, skip) in cases {
+            let mut expected = skip.to_vec();
+            expected.extend_from_slice(&[0x63, 0x01]);
+            assert_eq!(
+                rom(&format!(": main if {} then v3 := 1", cond)),
+                expected,
+                "{}",
+                cond
+            );
+        }
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = "
+            : main
+                if v0 == 1 begin
+                    v1 := 2
+                else
+                    v1 := 3
+                end
+        ";
+        assert_eq!(
+            rom(source),
+            [0x30, 0x01, 0x12, 0x08, 0x61, 0x02, 0x12, 0x0A, 0x61, 0x03]
+        );
+        //`begin` negates the comparison to jump over the block
+        assert_eq!(
+            rom(": main if v0 > v1 begin v2 := 1 end"),
+            [0x8F, 0x10, 0x8F, 0x05, 0x3F, 0x00, 0x12, 0x0A, 0x62, 0x01]
+        );
+    }
+
+    //runs `if a OP b then v3 := 1` and returns whether v3 was set
+    fn holds(a: u8, op: &str, b: u8, immediate: bool) -> bool {
+        let rhs = if immediate {
+            b.to_string()
+        } else {
+            "v2".to_string()
+        };
+        let source = format!(
+            ": main v1 := {} v2 := {} if v1 {} {} then v3 := 1 : end jump end",
+            a, b, op, rhs
+        );
+        let mut emu = Emu::new();
+        emu.load(&rom(&source)).unwrap();
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        emu.v_reg()[3] == 1
+    }
+
+    #[test]
+    fn comparisons_through_vf_hold_for_every_operand() {
+        let values = [0, 1, 2, 0x7F, 0x80, 0xFE, 0xFF];
+        for a in values {
+            for b in values {
+                for immediate in [false, true] {
+                    assert_eq!(holds(a, "<", b, immediate), a < b, "{} < {}", a, b);
+                    assert_eq!(holds(a, ">", b, immediate), a > b, "{} > {}", a, b);
+                    assert_eq!(holds(a, "<=", b, immediate), a <= b, "{} <= {}", a, b);
+                    assert_eq!(holds(a, ">=", b, immediate), a >= b, "{} >= {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn next_labels_the_operand_of_the_next_instruction() {
+        let source = "
+            : main
+                :next target
+                v0 := 0
+                i := target
+        ";
+        assert_eq!(rom(source), [0x60, 0x00, 0xA2, 0x01]);
+    }
+
+    #[test]
+    fn unpack_splits_an_address() {
+        let source = "
+            : main
+                :unpack 0xA data
+                :unpack long data
+            : data
+                0x12
+        ";
+        assert_eq!(
+            rom(source),
+            [0x60, 0xA2, 0x61, 0x08, 0x60, 0x02, 0x61, 0x08, 0x12]
+        );
+        //a label that is already known, main after data needs the jump at 0x200
+        assert_eq!(
+            rom(": data 0x12 : main :unpack 0xA data"),
+            [0x12, 0x03, 0x12, 0x60, 0xA2, 0x61, 0x02]
+        );
+    }
+
+    #[test]
+    fn macros_substitute_their_arguments() {
+        let source = "\
+:macro add-to reg amount { reg += amount }
+: main
+    add-to v1 3
+    add-to v2 0x10
+";
+        let program = compile(source).unwrap();
+        //main does not come first, so 0x200 jumps to it
+        assert_eq!(program.rom, [0x12, 0x02, 0x71, 0x03, 0x72, 0x10]);
+        //expansions are mapped to the line that invoked them
+        assert_eq!(program.lines.get(&0x202), Some(&3));
+        assert_eq!(program.lines.get(&0x204), Some(&4));
+    }
+
+    #[test]
+    fn forward_references_are_filled_in() {
+        let source = "
+            : main
+                jump sub
+                :call sub
+                i := sprite
+                i := long sprite
+                sub
+            : sub
+                ;
+            : sprite
+                0xF0 0x90
+        ";
+        let program = compile(source).unwrap();
+        #[rustfmt::skip]
+        assert_eq!(
+            program.rom,
+            [
+                0x12, 0x0C, 0x22, 0x0C, 0xA2, 0x0E, 0xF0, 0x00, 0x02, 0x0E, 0x22, 0x0C,
+                0x00, 0xEE, 0xF0, 0x90,
+            ]
+        );
+        assert_eq!(program.labels.get("sub"), Some(&0x20C));
+        assert_eq!(program.labels.get("sprite"), Some(&0x20E));
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        let cases = [
+            (": main\n  jump nowhere", 2, 8, "undefined name `nowhere`"),
+            (": main\n  loop\n  v0 += 1", 2, 3, "`loop` without `again`"),
+            (": main\n  again", 2, 3, "`again` without `loop`"),
+            (": main\n  while v0 == 1", 2, 3, "`while` outside of a loop"),
+            (
+                ": main\n  if v0 == 1 begin",
+                2,
+                3,
+                "`if ... begin` without `end`",
+            ),
+            (
+                ": main\n  v0 := 0x100",
+                2,
+                9,
+                "256 does not fit into a byte",
+            ),
+            (": main\n  if v0 ~ 1 then", 2, 9, "unknown comparison `~`"),
+            (
+                ": main\n  sprite v0 v1 16",
+                2,
+                16,
+                "16 does not fit into 0..=0xF",
+            ),
+            (": main\n  v0 :=", 2, 6, "unexpected end of source"),
+            ("v0 := 1", 1, 7, "the program has no `main` label"),
+            (": main\n: main", 2, 3, "`main` is already defined"),
+            (": main\n:calc x { 1 << 64 }", 2, 13, "cannot shift by 64"),
+            (": main\n:calc x { 1 >> -1 }", 2, 13, "cannot shift by -1"),
+        ];
+        for (source, line, column, message) in cases {
+            assert_eq!(
+                error(source),
+                (line, column, message.to_string()),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn columns_count_characters_on_non_ascii_lines() {
+        let (line, column, _) = error(":macro m é { } : main jump nowhere");
+        assert_eq!((line, column), (1, 28));
+    }
+}
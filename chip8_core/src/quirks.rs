@@ -0,0 +1,185 @@
+//Behaviour switches for the opcodes that different CHIP-8 interpreters disagree on.
+//The defaults keep the behaviour this emulator always had, the presets follow the
+//platforms most ROMs are written for.
+
+//how FX55 / FX65 leave the I register once they are done
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    //I is left untouched (SUPER-CHIP)
+    Unchanged,
+    //I += X (CHIP-48)
+    X,
+    //I += X + 1 (COSMAC VIP)
+    XPlusOne,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    //8XY6 / 8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    //what FX55 / FX65 do to I
+    pub load_store: IndexIncrement,
+    //8XY1 / 8XY2 / 8XY3 clear VF
+    pub vf_reset: bool,
+    //BNNN becomes BXNN and jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    //sprites are cut off at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    //DXYN waits for the next vertical blank (the next tick_timers call) before drawing
+    pub display_wait: bool,
+    //XO-CHIP 64K address space instead of the classic 4K
+    pub extended_memory: bool,
+    //00FE / 00FF clear the display when switching resolution (XO-CHIP), SUPER-CHIP 1.1 keeps it
+    pub mode_switch_clears: bool,
+    //DXY0 draws a 16x16 sprite in lores as well (XO-CHIP), SUPER-CHIP 1.1 draws 8x16 there
+    pub lores_big_sprites: bool,
+}
+
+//names of the presets, in the order the frontends list them
+const PRESETS: [&str; 5] = ["default", "vip", "chip48", "schip", "xochip"];
+
+//size of the quirks in save states and movies
+pub(crate) const QUIRKS_LEN: usize = 9;
+
+impl Quirks {
+    //preset by the name the frontends take on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "vip" => Some(Self::vip()),
+            "chip48" => Some(Self::chip48()),
+            "schip" => Some(Self::schip()),
+            "xochip" => Some(Self::xo_chip()),
+            _ => None,
+        }
+    }
+
+    //name of the preset these quirks are, None for a mix of them
+    pub fn name(&self) -> Option<&'static str> {
+        PRESETS
+            .into_iter()
+            .find(|name| Self::from_name(name) == Some(*self))
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; QUIRKS_LEN] {
+        let load_store = match self.load_store {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => 1,
+            IndexIncrement::XPlusOne => 2,
+        };
+        [
+            self.shift_uses_vy as u8,
+            load_store,
+            self.vf_reset as u8,
+            self.jump_uses_vx as u8,
+            self.clip_sprites as u8,
+            self.display_wait as u8,
+            self.extended_memory as u8,
+            self.mode_switch_clears as u8,
+            self.lores_big_sprites as u8,
+        ]
+    }
+
+    //None if a byte is out of range
+    pub(crate) fn from_bytes(bytes: [u8; QUIRKS_LEN]) -> Option<Self> {
+        let flag = |byte: u8| match byte {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        };
+        let load_store = match bytes[1] {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::X,
+            2 => IndexIncrement::XPlusOne,
+            _ => return None,
+        };
+        Some(Self {
+            shift_uses_vy: flag(bytes[0])?,
+            load_store,
+            vf_reset: flag(bytes[2])?,
+            jump_uses_vx: flag(bytes[3])?,
+            clip_sprites: flag(bytes[4])?,
+            display_wait: flag(bytes[5])?,
+            extended_memory: flag(bytes[6])?,
+            mode_switch_clears: flag(bytes[7])?,
+            lores_big_sprites: flag(bytes[8])?,
+        })
+    }
+
+    //original COSMAC VIP interpreter
+    pub const fn vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store: IndexIncrement::XPlusOne,
+            vf_reset: true,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            display_wait: true,
+            extended_memory: false,
+            mode_switch_clears: false,
+            lores_big_sprites: false,
+        }
+    }
+
+    //CHIP-48 on the HP-48
+    pub const fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::X,
+            vf_reset: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            display_wait: false,
+            extended_memory: false,
+            mode_switch_clears: false,
+            lores_big_sprites: false,
+        }
+    }
+
+    //SUPER-CHIP 1.1
+    pub const fn schip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::Unchanged,
+            vf_reset: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            display_wait: false,
+            extended_memory: false,
+            mode_switch_clears: false,
+            lores_big_sprites: false,
+        }
+    }
+
+    //XO-CHIP as implemented by Octo
+    pub const fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store: IndexIncrement::XPlusOne,
+            vf_reset: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+            extended_memory: true,
+            mode_switch_clears: true,
+            lores_big_sprites: true,
+        }
+    }
+}
+
+impl Default for Quirks {
+    //behaviour of this emulator before quirks were configurable
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: IndexIncrement::Unchanged,
+            vf_reset: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+            extended_memory: false,
+            mode_switch_clears: true,
+            lores_big_sprites: true,
+        }
+    }
+}
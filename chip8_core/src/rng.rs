@@ -0,0 +1,131 @@
+//random number source for CXNN, owned by the emulator so it is saved with the rest of the machine
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RngKind {
+    //xorshift64*, the same seed always gives the same numbers
+    Xorshift,
+    //a seed that advances with every executed instruction and is mixed with bytes of
+    //program code, so results depend on when CXNN runs like they did on the COSMAC VIP.
+    //It is not the VIP interpreter's routine and gives different bytes than the hardware
+    Timed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rng {
+    kind: RngKind,
+    state: u64,
+}
+
+//xorshift gets stuck on an all zero state
+const ZERO_SEED_REPLACEMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+impl Rng {
+    pub fn new(kind: RngKind, seed: u64) -> Self {
+        let state = match kind {
+            RngKind::Xorshift if seed == 0 => ZERO_SEED_REPLACEMENT,
+            RngKind::Xorshift => seed,
+            RngKind::Timed => seed & 0xFFFF,
+        };
+        Self { kind, state }
+    }
+
+    pub fn kind(&self) -> RngKind {
+        self.kind
+    }
+
+    //raw generator state, enough to recreate the generator with from_state
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn from_state(kind: RngKind, state: u64) -> Self {
+        Self { kind, state }
+    }
+
+    //called once per executed instruction
+    pub(crate) fn step(&mut self) {
+        if self.kind == RngKind::Timed {
+            self.state = (self.state + 1) & 0xFFFF;
+        }
+    }
+
+    //next random byte, code is the page of program memory the timed generator reads from
+    pub(crate) fn next_byte(&mut self, code: &[u8]) -> u8 {
+        match self.kind {
+            RngKind::Xorshift => {
+                let mut x = self.state;
+                x ^= x >> 12;
+                x ^= x << 25;
+                x ^= x >> 27;
+                self.state = x;
+                (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            }
+            RngKind::Timed => {
+                let low = self.state as u8;
+                let high = (self.state >> 8) as u8;
+                let mixed = high.wrapping_add(code[low as usize]).rotate_right(1) ^ low;
+                self.state = ((mixed as u64) << 8) | low as u64;
+                mixed
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emu;
+
+    #[rustfmt::skip]
+    const PROGRAM: [u8; 4] = [
+        0xC0, 0xFF, //RND V0, 0xFF
+        0x12, 0x00, //JP 0x200
+    ];
+
+    //the bytes CXNN draws from here on
+    fn draws(emu: &mut Emu, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                emu.tick().unwrap();
+                emu.tick().unwrap();
+                emu.v_reg()[0]
+            })
+            .collect()
+    }
+
+    fn seeded(kind: RngKind, seed: u64) -> Emu {
+        let mut emu = Emu::new();
+        emu.seed_rng(kind, seed);
+        emu.load(&PROGRAM).unwrap();
+        emu
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_numbers() {
+        for kind in [RngKind::Xorshift, RngKind::Timed] {
+            let first = draws(&mut seeded(kind, 1234), 32);
+            assert_eq!(draws(&mut seeded(kind, 1234), 32), first, "{kind:?}");
+            assert_ne!(draws(&mut seeded(kind, 4321), 32), first, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn zero_seed_still_gives_numbers() {
+        let numbers = draws(&mut seeded(RngKind::Xorshift, 0), 32);
+        assert!(numbers.iter().any(|&byte| byte != numbers[0]));
+    }
+
+    #[test]
+    fn snapshot_restores_the_generator() {
+        for kind in [RngKind::Xorshift, RngKind::Timed] {
+            let mut emu = seeded(kind, 99);
+            draws(&mut emu, 5);
+            let snapshot = emu.save_state();
+            let expected = draws(&mut emu, 16);
+            emu.seed_rng(kind, 7);
+            emu.load_state(&snapshot);
+            assert_eq!(emu.rng(), snapshot.rng, "{kind:?}");
+            assert_eq!(draws(&mut emu, 16), expected, "{kind:?}");
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::fmt;
+
+use crate::quirks::QUIRKS_LEN;
+use crate::{
+    Quirks, Rng, RngKind, AUDIO_PATTERN_SIZE, NUM_FLAGS, NUM_KEYS, NUM_REGS, SCREEN_SIZE,
+    STACK_SIZE,
+};
+
+//file layout: magic, version, machine state, CRC-32 of everything before it
+const MAGIC: &[u8; 4] = b"C8SS";
+pub const SNAPSHOT_VERSION: u16 = 3;
+//everything before the RAM size has a fixed size: magic, version, quirks, pc, i, sp, stack, v,
+//dt, st, keys, flags, the display and audio bytes, the audio pattern, the rng and the screen
+const RAM_LEN_OFFSET: usize = MAGIC.len()
+    + 2
+    + QUIRKS_LEN
+    + 6
+    + STACK_SIZE * 2
+    + NUM_REGS
+    + 2
+    + 2
+    + NUM_FLAGS
+    + 5
+    + 1
+    + AUDIO_PATTERN_SIZE
+    + 1
+    + 8
+    + SCREEN_SIZE;
+
+//complete machine state, taken with Emu::save_state and restored with Emu::load_state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) quirks: Quirks,
+    pub(crate) pc: u16,
+    pub(crate) ram: Vec<u8>,
+    pub(crate) screen: Vec<u8>,
+    pub(crate) planes: u8,
+    pub(crate) audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pub(crate) pitch: u8,
+    pub(crate) hires: bool,
+    pub(crate) flags: [u8; NUM_FLAGS],
+    pub(crate) exited: bool,
+    pub(crate) v_reg: [u8; NUM_REGS],
+    pub(crate) i_reg: u16,
+    pub(crate) sp: u16,
+    pub(crate) stack: [u16; STACK_SIZE],
+    pub(crate) keys: [bool; NUM_KEYS],
+    pub(crate) dt: u8,
+    pub(crate) st: u8,
+    pub(crate) vblank: bool,
+    pub(crate) rng: Rng,
+}
+
+//reasons a byte buffer is not a usable snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    //does not start with the snapshot magic
+    BadMagic,
+    //written by a newer or older, incompatible encoder
+    UnsupportedVersion(u16),
+    //stored checksum does not match the contents
+    ChecksumMismatch,
+    //buffer ends before the snapshot does
+    Truncated,
+    //decoded fine but describes a machine that cannot exist
+    Invalid,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a save state"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported save state version {}", v)
+            }
+            SnapshotError::ChecksumMismatch => write!(f, "save state is corrupted"),
+            SnapshotError::Truncated => write!(f, "save state is truncated"),
+            SnapshotError::Invalid => write!(f, "save state contains invalid values"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl Snapshot {
+    //encodes the snapshot so it can be written to disk
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.ram.len() + self.screen.len() + 128);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+
+        out.extend_from_slice(&self.quirks.to_bytes());
+
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.i_reg.to_le_bytes());
+        out.extend_from_slice(&self.sp.to_le_bytes());
+        for entry in self.stack.iter() {
+            out.extend_from_slice(&entry.to_le_bytes());
+        }
+        out.extend_from_slice(&self.v_reg);
+        out.push(self.dt);
+        out.push(self.st);
+        let keys = self
+            .keys
+            .iter()
+            .enumerate()
+            .fold(0u16, |acc, (idx, &down)| acc | ((down as u16) << idx));
+        out.extend_from_slice(&keys.to_le_bytes());
+        out.extend_from_slice(&self.flags);
+        out.extend_from_slice(&[
+            self.hires as u8,
+            self.exited as u8,
+            self.vblank as u8,
+            self.planes,
+            self.pitch,
+        ]);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                out.push(1);
+                out.extend_from_slice(pattern);
+            }
+            None => {
+                out.push(0);
+                out.extend_from_slice(&[0; AUDIO_PATTERN_SIZE]);
+            }
+        }
+        let rng_kind = match self.rng.kind() {
+            RngKind::Xorshift => 0,
+            RngKind::Timed => 1,
+        };
+        out.push(rng_kind);
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+        out.extend_from_slice(&self.screen);
+        out.extend_from_slice(&(self.ram.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.ram);
+
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    //decodes bytes produced by to_bytes, checking magic, version and checksum
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SnapshotError> {
+        if data.len() < MAGIC.len() + 2 {
+            return Err(SnapshotError::Truncated);
+        }
+        if &data[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        //the RAM is the only part whose size varies, a buffer shorter than the size it gives was
+        //cut off rather than corrupted
+        let ram_len = data
+            .get(RAM_LEN_OFFSET..RAM_LEN_OFFSET + 4)
+            .ok_or(SnapshotError::Truncated)?;
+        let ram_len = u32::from_le_bytes([ram_len[0], ram_len[1], ram_len[2], ram_len[3]]);
+        if data.len() < (RAM_LEN_OFFSET + 4 + 4).saturating_add(ram_len as usize) {
+            return Err(SnapshotError::Truncated);
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+
+        let mut r = Reader {
+            data: body,
+            pos: MAGIC.len() + 2,
+        };
+        let mut quirks = [0; QUIRKS_LEN];
+        quirks.copy_from_slice(r.bytes(QUIRKS_LEN)?);
+        let quirks = Quirks::from_bytes(quirks).ok_or(SnapshotError::Invalid)?;
+        let pc = r.u16()?;
+        let i_reg = r.u16()?;
+        let sp = r.u16()?;
+        let mut stack = [0; STACK_SIZE];
+        for entry in stack.iter_mut() {
+            *entry = r.u16()?;
+        }
+        let mut v_reg = [0; NUM_REGS];
+        v_reg.copy_from_slice(r.bytes(NUM_REGS)?);
+        let dt = r.u8()?;
+        let st = r.u8()?;
+        let key_bits = r.u16()?;
+        let mut keys = [false; NUM_KEYS];
+        for (idx, key) in keys.iter_mut().enumerate() {
+            *key = key_bits & (1 << idx) != 0;
+        }
+        let mut flags = [0; NUM_FLAGS];
+        flags.copy_from_slice(r.bytes(NUM_FLAGS)?);
+        let hires = r.bool()?;
+        let exited = r.bool()?;
+        let vblank = r.bool()?;
+        let planes = r.u8()?;
+        let pitch = r.u8()?;
+        let has_pattern = r.bool()?;
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern.copy_from_slice(r.bytes(AUDIO_PATTERN_SIZE)?);
+        let audio_pattern = if has_pattern { Some(pattern) } else { None };
+        let rng_kind = match r.u8()? {
+            0 => RngKind::Xorshift,
+            1 => RngKind::Timed,
+            _ => return Err(SnapshotError::Invalid),
+        };
+        let rng = Rng::from_state(rng_kind, r.u64()?);
+        let screen = r.bytes(SCREEN_SIZE)?.to_vec();
+        let ram_len = r.u32()? as usize;
+        let ram = r.bytes(ram_len)?.to_vec();
+
+        let snapshot = Snapshot {
+            quirks,
+            pc,
+            ram,
+            screen,
+            planes,
+            audio_pattern,
+            pitch,
+            hires,
+            flags,
+            exited,
+            v_reg,
+            i_reg,
+            sp,
+            stack,
+            keys,
+            dt,
+            st,
+            vblank,
+            rng,
+        };
+        if r.pos != body.len() || !snapshot.is_consistent() {
+            return Err(SnapshotError::Invalid);
+        }
+        Ok(snapshot)
+    }
+
+    //guards load_state against states the emulator could never have produced
+    fn is_consistent(&self) -> bool {
+        let ram_len = if self.quirks.extended_memory {
+            crate::RAM_SIZE
+        } else {
+            crate::CLASSIC_RAM_SIZE
+        };
+        self.ram.len() == ram_len
+            && self.screen.len() == SCREEN_SIZE
+            && self.sp as usize <= STACK_SIZE
+            && self.planes <= 0b11
+            && self.screen.iter().all(|&pixel| pixel <= 0b11)
+    }
+}
+
+//little endian cursor over the snapshot body
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.pos.checked_add(len).ok_or(SnapshotError::Truncated)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(SnapshotError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Invalid),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+}
+
+//CRC-32 (IEEE), bitwise since snapshots are small and rarely written
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emu;
+
+    //a machine that has run for a while, so most fields are not zero
+    fn snapshot_bytes(quirks: Quirks) -> (Snapshot, Vec<u8>) {
+        let mut emu = Emu::with_quirks(quirks);
+        emu.seed_rng(RngKind::Xorshift, 1234);
+        #[rustfmt::skip]
+        let rom = [
+            0x00, 0xFF, //hires
+            0x6A, 0x2C, //LD VA, 0x2C
+            0xCB, 0xFF, //RND VB, 0xFF
+            0xA2, 0x10, //LD I, 0x210
+            0xDA, 0xB5, //DRW VA, VB, 5
+            0xFA, 0x15, //LD DT, VA
+            0x22, 0x0E, //CALL 0x20E
+            0x00, 0x00,
+            0x12, 0x0E, //JP 0x20E
+            0xF0, 0x90, 0xF0, 0x90, 0xF0,
+        ];
+        emu.load(&rom).unwrap();
+        for _ in 0..10 {
+            emu.tick().unwrap();
+        }
+        emu.keypress(7, true);
+        let snapshot = emu.save_state();
+        let bytes = snapshot.to_bytes();
+        (snapshot, bytes)
+    }
+
+    //fixes up the checksum after the body was edited
+    fn reseal(bytes: &mut [u8]) {
+        let end = bytes.len() - 4;
+        let checksum = crc32(&bytes[..end]);
+        bytes[end..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        for quirks in [Quirks::schip(), Quirks::xo_chip()] {
+            let (snapshot, bytes) = snapshot_bytes(quirks);
+            assert_eq!(Snapshot::from_bytes(&bytes), Ok(snapshot.clone()));
+
+            let mut emu = Emu::new();
+            emu.load_state(&snapshot);
+            assert_eq!(emu.save_state(), snapshot);
+            assert_eq!(emu.save_state().to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        let (_, mut bytes) = snapshot_bytes(Quirks::schip());
+        bytes[0] = b'X';
+        assert_eq!(Snapshot::from_bytes(&bytes), Err(SnapshotError::BadMagic));
+        assert_eq!(
+            Snapshot::from_bytes(b"\x89PNG\r\n\x1a\n"),
+            Err(SnapshotError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn wrong_version() {
+        let (_, mut bytes) = snapshot_bytes(Quirks::schip());
+        bytes[4..6].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        reseal(&mut bytes);
+        assert_eq!(
+            Snapshot::from_bytes(&bytes),
+            Err(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn truncated_buffer() {
+        let (_, bytes) = snapshot_bytes(Quirks::schip());
+        for len in 0..bytes.len() {
+            assert_eq!(
+                Snapshot::from_bytes(&bytes[..len]),
+                Err(SnapshotError::Truncated),
+                "cut at {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted_crc() {
+        let (_, bytes) = snapshot_bytes(Quirks::schip());
+        //every register byte after the version, then a sample of the screen and RAM and the
+        //checksum itself; the RAM size is left out, changing it reads as a truncated buffer
+        let screen = RAM_LEN_OFFSET - SCREEN_SIZE;
+        let positions = (6..screen)
+            .chain((screen..RAM_LEN_OFFSET).step_by(97))
+            .chain((RAM_LEN_OFFSET + 4..bytes.len() - 4).step_by(97))
+            .chain(bytes.len() - 4..bytes.len());
+        for pos in positions {
+            let mut corrupted = bytes.clone();
+            corrupted[pos] ^= 0x10;
+            assert_eq!(
+                Snapshot::from_bytes(&corrupted),
+                Err(SnapshotError::ChecksumMismatch),
+                "flipped byte {}",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn impossible_values_are_invalid() {
+        let (_, bytes) = snapshot_bytes(Quirks::schip());
+        //shift_uses_vy is a bool, the stack pointer after pc and i is at most STACK_SIZE
+        let sp = 6 + QUIRKS_LEN + 4;
+        for (pos, value) in [(6, 2), (sp, STACK_SIZE as u8 + 1)] {
+            let mut edited = bytes.clone();
+            edited[pos] = value;
+            reseal(&mut edited);
+            assert_eq!(
+                Snapshot::from_bytes(&edited),
+                Err(SnapshotError::Invalid),
+                "byte {}",
+                pos
+            );
+        }
+        //trailing garbage that is covered by the checksum
+        let mut longer = bytes[..bytes.len() - 4].to_vec();
+        longer.extend_from_slice(&[0; 5]);
+        reseal(&mut longer);
+        assert_eq!(Snapshot::from_bytes(&longer), Err(SnapshotError::Invalid));
+    }
+}
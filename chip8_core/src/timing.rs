@@ -0,0 +1,157 @@
+//How much Emu::run_frame executes in one 60 Hz frame.
+//
+//The VIP model counts CDP1802 machine cycles (8 clocks, 4.54 us at 1.7609 MHz). A frame is 3668
+//of them, the CDP1861 takes 1024 for video DMA (128 lines of 8 bytes) and the display interrupt,
+//which also counts the timers down, about 46 more. The interpreter gets the rest.
+//Every instruction pays for the interpreter's fetch and decode loop plus its own routine, costs
+//are counted at 2 cycles per 1802 instruction (3 for long branches) and rounded.
+
+use crate::Emu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    //a fixed number of instructions per frame whatever they are, how the frontends always ran
+    Instructions(u32),
+    //COSMAC VIP cycle costs, slow instructions leave room for fewer others in the frame
+    Vip,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing::Instructions(10)
+    }
+}
+
+//machine cycles the interpreter has in every frame
+pub const VIP_FRAME_CYCLES: u32 = 3668 - 1024 - 46;
+
+//fetch, decode and the jump through the dispatch table, paid by every instruction
+const FETCH_CYCLES: u32 = 40;
+//a taken skip adds the second pc increment
+const SKIP_CYCLES: u32 = 4;
+
+//cycles of one instruction, looked at before it runs
+//instructions the VIP does not have (SUPER-CHIP, XO-CHIP) cost as much as an ALU operation
+pub(crate) fn vip_cycles(emu: &Emu, op: u16) -> u32 {
+    let v_reg = &emu.v_reg;
+    let x = ((op & 0x0F00) >> 8) as usize;
+    let y = ((op & 0x00F0) >> 4) as usize;
+    let n = (op & 0x000F) as u32;
+    let nn = (op & 0x00FF) as u8;
+    let skip = |taken: bool| if taken { SKIP_CYCLES } else { 0 };
+    let routine = match (op >> 12, nn) {
+        //the clear loop stores one byte of the 256 byte display page per 3 instructions
+        (0x0, 0xE0) => 12 + 256 * 6,
+        (0x0, 0xEE) => 10,
+        (0x1, _) => 12,
+        (0x2, _) => 26,
+        (0x3, _) => 10 + skip(v_reg[x] == nn),
+        (0x4, _) => 10 + skip(v_reg[x] != nn),
+        (0x5, _) => 14 + skip(v_reg[x] == v_reg[y]),
+        (0x6, _) => 6,
+        (0x7, _) => 10,
+        //the ALU instruction is assembled in RAM and called
+        (0x8, _) => 44,
+        (0x9, _) => 14 + skip(v_reg[x] != v_reg[y]),
+        (0xA, _) => 12,
+        (0xB, _) => 22,
+        (0xC, _) => 36,
+        (0xD, _) => draw_cycles(n, v_reg[x]),
+        (0xE, 0x9E) => 14 + skip(emu.keys[v_reg[x] as usize & 0xF]),
+        (0xE, 0xA1) => 14 + skip(!emu.keys[v_reg[x] as usize & 0xF]),
+        //one poll of the keypad, the instruction repeats until a key comes
+        (0xF, 0x0A) => 10,
+        (0xF, 0x07) | (0xF, 0x15) | (0xF, 0x18) => 10,
+        (0xF, 0x1E) => 16,
+        (0xF, 0x29) => 16,
+        //the digits are found by repeated subtraction of 100 and 10
+        (0xF, 0x33) => {
+            let value = v_reg[x] as u32;
+            24 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+        }
+        (0xF, 0x55) | (0xF, 0x65) => 14 + 14 * (x as u32 + 1),
+        _ => 44,
+    };
+    FETCH_CYCLES + routine
+}
+
+//every sprite row is shifted into place one bit at a time and written as two bytes,
+//checking both for collisions
+fn draw_cycles(rows: u32, x_coord: u8) -> u32 {
+    let shift = (x_coord % 8) as u32;
+    26 + rows * (20 + 4 * shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmuError;
+
+    //ADD V0, 1 costs 50 cycles
+    const ADD: [u8; 2] = [0x70, 0x01];
+
+    fn vip(program: &[u8]) -> Emu {
+        let mut emu = Emu::new();
+        emu.set_timing(Timing::Vip);
+        emu.load(program).unwrap();
+        emu
+    }
+
+    //instructions run by each of the frames
+    fn per_frame(emu: &mut Emu, frames: usize) -> Vec<u16> {
+        let mut pc = emu.pc();
+        (0..frames)
+            .map(|_| {
+                emu.run_frame().unwrap();
+                let count = (emu.pc() - pc) / 2;
+                pc = emu.pc();
+                count
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draw_cost_depends_on_rows_and_shift() {
+        let mut emu = Emu::new();
+        assert_eq!(vip_cycles(&emu, 0xD015), FETCH_CYCLES + 26 + 5 * 20);
+        assert_eq!(vip_cycles(&emu, 0xD010), FETCH_CYCLES + 26);
+        emu.v_reg[0] = 11;
+        assert_eq!(
+            vip_cycles(&emu, 0xD015),
+            FETCH_CYCLES + 26 + 5 * (20 + 4 * 3)
+        );
+        //only X decides the shift
+        assert_eq!(vip_cycles(&emu, 0xD105), FETCH_CYCLES + 26 + 5 * 20);
+    }
+
+    #[test]
+    fn cheap_instructions_fill_the_frame() {
+        let mut emu = vip(&ADD.repeat(1400));
+        //52 additions take 2600 of the 2598 cycles, the 2 extra come out of the next frame
+        let counts = per_frame(&mut emu, 26);
+        assert!(counts[..24].iter().all(|&count| count == 52), "{counts:?}");
+        //by now 48 cycles are owed, 51 additions use up the frame exactly
+        assert_eq!(counts[24..], [51, 52]);
+    }
+
+    #[test]
+    fn slow_instruction_at_the_end_of_a_frame_delays_the_next() {
+        let mut program = ADD.repeat(51);
+        //CLS, 1588 cycles
+        program.extend_from_slice(&[0x00, 0xE0]);
+        program.extend_from_slice(&ADD.repeat(100));
+        let mut emu = vip(&program);
+        //51 additions leave 48 cycles, CLS starts in this frame and runs 1540 into the next
+        assert_eq!(per_frame(&mut emu, 3), [52, 22, 52]);
+    }
+
+    #[test]
+    fn fetch_past_the_end_of_memory_is_an_error() {
+        let mut emu = vip(&ADD);
+        emu.set_pc(0xFFF);
+        assert_eq!(
+            emu.run_frame(),
+            Err(EmuError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::disasm::{self, Syntax};
+use crate::NUM_REGS;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+//writes one line per executed instruction, showing the state before it runs:
+//
+//    00000000 0200 6005 LD V0, 0x05           V 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I 0000 SP 00 DT 00 ST 00
+//
+//cycle (instructions since the tracer was attached, decimal), pc, opcode, Cowgod disassembly
+//padded to 20 columns, V0-VF, I, SP, DT and ST, all fixed width so logs line up for diffing
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    //only instructions in these ranges are written, all of them when empty
+    ranges: Vec<RangeInclusive<u16>>,
+    limit: Option<usize>,
+    lines: usize,
+    cycle: u64,
+    //first write error, tracing stops there and finish() reports it
+    error: Option<io::Error>,
+}
+
+//what the tracer needs from Emu for one line
+pub(crate) struct TraceState<'a> {
+    pub(crate) pc: u16,
+    pub(crate) memory: &'a [u8],
+    pub(crate) v_reg: &'a [u8; NUM_REGS],
+    pub(crate) i_reg: u16,
+    pub(crate) sp: u16,
+    pub(crate) dt: u8,
+    pub(crate) st: u8,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Box::new(out),
+            ranges: Vec::new(),
+            limit: None,
+            lines: 0,
+            cycle: 0,
+            error: None,
+        }
+    }
+
+    //restricts the trace to instructions in the range, can be called more than once
+    pub fn add_range(&mut self, range: RangeInclusive<u16>) {
+        self.ranges.push(range);
+    }
+
+    //stops after this many lines with a final note that the trace was cut
+    pub fn set_limit(&mut self, lines: usize) {
+        self.limit = Some(lines);
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    //flushes the output and reports the first write error, if any
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()
+    }
+
+    fn done(&self) -> bool {
+        self.error.is_some() || self.limit.is_some_and(|limit| self.lines > limit)
+    }
+
+    pub(crate) fn record(&mut self, state: &TraceState) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self.done() {
+            return;
+        }
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|range| range.contains(&state.pc)) {
+            return;
+        }
+        let line = if self.limit == Some(self.lines) {
+            format!("trace limit of {} lines reached\n", self.lines)
+        } else {
+            format_line(cycle, state)
+        };
+        self.lines += 1;
+        if let Err(err) = self.out.write_all(line.as_bytes()) {
+            self.error = Some(err);
+        }
+    }
+}
+
+fn format_line(cycle: u64, state: &TraceState) -> String {
+    let pc = state.pc as usize;
+    let op = match state.memory.get(pc..pc + 2) {
+        Some(&[high, low]) => format!("{:04X}", u16::from_be_bytes([high, low])),
+        _ => "????".to_string(),
+    };
+    let text = disasm::decode_at(state.memory, pc, Syntax::Cowgod)
+        .map_or_else(|| "???".to_string(), |(text, _)| text);
+    let regs: Vec<_> = state.v_reg.iter().map(|v| format!("{:02X}", v)).collect();
+    format!(
+        "{:08} {:04X} {} {:<20} V {} I {:04X} SP {:02X} DT {:02X} ST {:02X}\n",
+        cycle,
+        state.pc,
+        op,
+        text,
+        regs.join(" "),
+        state.i_reg,
+        state.sp,
+        state.dt,
+        state.st
+    )
+}
@@ -0,0 +1,121 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use std::f32::consts::TAU;
+
+const SAMPLE_RATE: i32 = 44100;
+//the tone fades in and out over this long, cutting a wave off mid-swing clicks
+const FADE_SECONDS: f32 = 0.005;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None,
+        }
+    }
+
+    //one period over phase 0-1, between -1 and 1
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+//runs on SDL's audio thread, the game loop only flips `on` through the device lock
+struct Tone {
+    waveform: Waveform,
+    volume: f32,
+    //the phase keeps running while silent so a restart picks the wave up where it was
+    phase: f32,
+    phase_step: f32,
+    gain: f32,
+    fade_step: f32,
+    on: bool,
+}
+
+impl AudioCallback for Tone {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let target = if self.on { 1.0 } else { 0.0 };
+        for sample in out.iter_mut() {
+            self.gain = if self.gain < target {
+                (self.gain + self.fade_step).min(target)
+            } else {
+                (self.gain - self.fade_step).max(target)
+            };
+            *sample = self.waveform.sample(self.phase) * self.volume * self.gain;
+            self.phase = (self.phase + self.phase_step) % 1.0;
+        }
+    }
+}
+
+//the buzzer, played while the sound timer runs
+pub struct Beeper {
+    device: AudioDevice<Tone>,
+    muted: bool,
+}
+
+impl Beeper {
+    //frequency in Hz, volume from 0 to 1
+    pub fn open(
+        audio: &AudioSubsystem,
+        frequency: f32,
+        waveform: Waveform,
+        volume: f32,
+    ) -> Result<Self, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let device = audio.open_playback(None, &desired, |spec| Tone {
+            waveform,
+            volume,
+            phase: 0.0,
+            phase_step: frequency / spec.freq as f32,
+            gain: 0.0,
+            fade_step: 1.0 / (FADE_SECONDS * spec.freq as f32),
+            on: false,
+        })?;
+        //the callback plays silence until the first beep
+        device.resume();
+        Ok(Self {
+            device,
+            muted: false,
+        })
+    }
+
+    //called once per loop iteration with whether the machine wants to beep
+    pub fn update(&mut self, active: bool) {
+        let on = active && !self.muted;
+        let mut tone = self.device.lock();
+        if tone.on != on {
+            tone.on = on;
+        }
+    }
+
+    //returns whether the sound is muted now
+    pub fn toggle_mute(&mut self) -> bool {
+        self.muted = !self.muted;
+        self.muted
+    }
+}
@@ -0,0 +1,347 @@
+use crate::config::{Config, Section};
+use sdl2::controller::Button;
+use sdl2::keyboard::{Keycode, Scancode};
+use std::path::{Path, PathBuf};
+
+//the hex keypad row by row, the order keys are listed and rebound in
+pub const KEYPAD: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+//the 1234/QWER/ASDF/ZXCV block by position, so AZERTY and Dvorak keyboards get the same shape
+const DEFAULT_KEYS: [Scancode; 16] = [
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Q,
+    Scancode::W,
+    Scancode::E,
+    Scancode::R,
+    Scancode::A,
+    Scancode::S,
+    Scancode::D,
+    Scancode::F,
+    Scancode::Z,
+    Scancode::X,
+    Scancode::C,
+    Scancode::V,
+];
+
+//most games steer with 5, 7, 8 and 9 and act with 6 or 4
+const DEFAULT_BUTTONS: [(Button, usize); 6] = [
+    (Button::DPadUp, 0x5),
+    (Button::DPadLeft, 0x7),
+    (Button::DPadDown, 0x8),
+    (Button::DPadRight, 0x9),
+    (Button::A, 0x6),
+    (Button::B, 0x4),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Key(Keycode),
+    Button(Button),
+}
+
+impl Input {
+    //SDL's name, the one the config file uses
+    pub fn name(self) -> String {
+        match self {
+            Input::Key(key) => key.name(),
+            Input::Button(button) => button.string(),
+        }
+    }
+
+    fn is_key(self) -> bool {
+        matches!(self, Input::Key(_))
+    }
+}
+
+//which keyboard keys and controller buttons press which hex key
+//
+//the defaults are replaced key by key by the [keys] and [controller] sections of the config
+//file, then by [keys game.ch8] and [controller game.ch8] for the ROM being run
+#[derive(Clone)]
+pub struct Bindings {
+    inputs: Vec<(Input, usize)>,
+}
+
+impl Bindings {
+    //needs SDL's video subsystem, it knows the keyboard layout
+    pub fn new(config: &Config, rom_path: &Path) -> Result<Self, String> {
+        let keys = DEFAULT_KEYS
+            .iter()
+            .zip(KEYPAD)
+            .filter_map(|(&scancode, btn)| {
+                Some((Input::Key(Keycode::from_scancode(scancode)?), btn))
+            });
+        let buttons = DEFAULT_BUTTONS
+            .iter()
+            .map(|&(button, btn)| (Input::Button(button), btn));
+        let mut bindings = Self {
+            inputs: keys.chain(buttons).collect(),
+        };
+        let rom = rom_name(rom_path);
+        for kind in ["keys", "controller"] {
+            if let Some(section) = config.section(kind) {
+                bindings.apply(config, section, kind)?;
+            }
+            if let Some(section) = config.section(&format!("{} {}", kind, rom)) {
+                bindings.apply(config, section, kind)?;
+            }
+        }
+        Ok(bindings)
+    }
+
+    //each line is a hex key and the comma separated keys or buttons for it, nothing unbinds it
+    fn apply(&mut self, config: &Config, section: &Section, kind: &str) -> Result<(), String> {
+        let is_key = kind == "keys";
+        for entry in &section.entries {
+            let btn = usize::from_str_radix(&entry.key, 16)
+                .ok()
+                .filter(|&btn| btn < KEYPAD.len())
+                .ok_or_else(|| config.error(entry.line, "expected a hex key from 0 to F"))?;
+            self.inputs
+                .retain(|&(input, bound)| bound != btn || input.is_key() != is_key);
+            for name in entry
+                .value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                let input = if is_key {
+                    Keycode::from_name(name).map(Input::Key)
+                } else {
+                    Button::from_string(name).map(Input::Button)
+                }
+                .ok_or_else(|| config.error(entry.line, &format!("unknown {}: {}", kind, name)))?;
+                self.bind(input, btn);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, input: Input) -> Option<usize> {
+        self.inputs
+            .iter()
+            .find(|&&(bound, _)| bound == input)
+            .map(|&(_, btn)| btn)
+    }
+
+    //an input presses a single hex key, binding it again moves it
+    pub fn bind(&mut self, input: Input, btn: usize) {
+        self.inputs.retain(|&(bound, _)| bound != input);
+        self.inputs.push((input, btn));
+    }
+
+    //drops the keyboard keys or controller buttons of a hex key, whichever kind the input is
+    pub fn unbind_like(&mut self, input: Input, btn: usize) {
+        self.inputs
+            .retain(|&(bound, bound_btn)| bound_btn != btn || bound.is_key() != input.is_key());
+    }
+
+    //names of what presses a hex key, for the terminal
+    pub fn describe(&self, btn: usize) -> String {
+        let names: Vec<_> = self
+            .inputs
+            .iter()
+            .filter(|&&(_, bound)| bound == btn)
+            .map(|&(input, _)| input.name())
+            .collect();
+        if names.is_empty() {
+            "nothing".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    //writes every binding to the ROM's profile if it has one, to [keys] and [controller] if not
+    pub fn save(&self, config: &mut Config, rom_path: &Path) -> Result<PathBuf, String> {
+        let rom = rom_name(rom_path);
+        let mut path = PathBuf::new();
+        for kind in ["keys", "controller"] {
+            let profile = format!("{} {}", kind, rom);
+            let name = if config.section(&profile).is_some() {
+                profile
+            } else {
+                kind.to_string()
+            };
+            let entries: Vec<_> = KEYPAD
+                .iter()
+                .map(|&btn| {
+                    let names: Vec<_> = self
+                        .inputs
+                        .iter()
+                        .filter(|&&(input, bound)| {
+                            bound == btn && input.is_key() == (kind == "keys")
+                        })
+                        .map(|&(input, _)| input.name())
+                        .collect();
+                    (format!("{:X}", btn), names.join(", "))
+                })
+                .collect();
+            path = config.save_section(&name, &entries)?;
+        }
+        Ok(path)
+    }
+}
+
+//profiles are named after the ROM's file name, game.ch8 in [keys game.ch8]
+fn rom_name(rom_path: &Path) -> String {
+    rom_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const PROFILES: &str = "[keys]\n\
+                            5 = Up\n\
+                            \n\
+                            [keys game.ch8]\n\
+                            5 = Space, W\n\
+                            \n\
+                            [controller game.ch8]\n\
+                            6 = x\n";
+
+    //loads text from a config file of its own, removed again by the caller
+    fn load_config(name: &str, text: &str) -> (Config, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "chip8-bindings-{}-{}.ini",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, text).unwrap();
+        (Config::load(Some(&path)).unwrap(), path)
+    }
+
+    fn error(name: &str, text: &str) -> String {
+        let (config, path) = load_config(name, text);
+        fs::remove_file(&path).unwrap();
+        let err = Bindings::new(&config, Path::new("game.ch8")).err().unwrap();
+        err.strip_prefix(&path.display().to_string())
+            .unwrap_or(&err)
+            .to_string()
+    }
+
+    #[test]
+    fn rom_profiles_replace_the_global_sections() {
+        let (config, path) = load_config("profiles", PROFILES);
+        fs::remove_file(&path).unwrap();
+
+        let game = Bindings::new(&config, Path::new("roms/game.ch8")).unwrap();
+        assert_eq!(game.lookup(Input::Key(Keycode::Space)), Some(0x5));
+        assert_eq!(game.lookup(Input::Key(Keycode::W)), Some(0x5));
+        assert_eq!(game.lookup(Input::Key(Keycode::Up)), None);
+        assert_eq!(game.lookup(Input::Button(Button::X)), Some(0x6));
+        //the default button for 6 went with the profile, the ones for other keys stay
+        assert_eq!(game.lookup(Input::Button(Button::A)), None);
+        assert_eq!(game.lookup(Input::Button(Button::DPadUp)), Some(0x5));
+
+        let other = Bindings::new(&config, Path::new("other.ch8")).unwrap();
+        assert_eq!(other.lookup(Input::Key(Keycode::Up)), Some(0x5));
+        assert_eq!(other.lookup(Input::Key(Keycode::Space)), None);
+        assert_eq!(other.lookup(Input::Button(Button::A)), Some(0x6));
+    }
+
+    #[test]
+    fn empty_value_unbinds_a_key() {
+        let (config, path) = load_config("unbind", "[controller]\n5 =\n");
+        fs::remove_file(&path).unwrap();
+        let bindings = Bindings::new(&config, Path::new("game.ch8")).unwrap();
+        assert_eq!(bindings.lookup(Input::Button(Button::DPadUp)), None);
+        assert_eq!(bindings.lookup(Input::Button(Button::DPadLeft)), Some(0x7));
+    }
+
+    #[test]
+    fn bad_bindings_point_at_the_line() {
+        assert_eq!(
+            error("hex", "[keys]\nG = Up\n"),
+            ":2: expected a hex key from 0 to F"
+        );
+        assert_eq!(
+            error("range", "[keys game.ch8]\n10 = Up\n"),
+            ":2: expected a hex key from 0 to F"
+        );
+        assert_eq!(
+            error("key", "[keys]\n5 = Up, NoSuchKey\n"),
+            ":2: unknown keys: NoSuchKey"
+        );
+        assert_eq!(
+            error("button", "\n[controller]\n5 = trigger\n"),
+            ":3: unknown controller: trigger"
+        );
+    }
+
+    #[test]
+    fn binding_moves_an_input() {
+        let (config, path) = load_config("bind", "");
+        fs::remove_file(&path).unwrap();
+        let mut bindings = Bindings::new(&config, Path::new("game.ch8")).unwrap();
+        bindings.bind(Input::Key(Keycode::Up), 0x1);
+        bindings.bind(Input::Key(Keycode::Up), 0x2);
+        assert_eq!(bindings.lookup(Input::Key(Keycode::Up)), Some(0x2));
+        bindings.bind(Input::Button(Button::X), 0x2);
+        //only the keyboard keys of 2 go
+        bindings.unbind_like(Input::Key(Keycode::Space), 0x2);
+        assert_eq!(bindings.lookup(Input::Key(Keycode::Up)), None);
+        assert_eq!(bindings.lookup(Input::Button(Button::X)), Some(0x2));
+    }
+
+    #[test]
+    fn saved_bindings_load_back() {
+        let (mut config, path) = load_config("save", PROFILES);
+        let rom = Path::new("game.ch8");
+        let mut bindings = Bindings::new(&config, rom).unwrap();
+        bindings.bind(Input::Key(Keycode::Return), 0xA);
+        assert_eq!(bindings.save(&mut config, rom).unwrap(), path);
+
+        //the ROM has a profile, the global sections are left alone
+        let config = Config::load(Some(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        let keys = config.section("keys").unwrap();
+        assert_eq!(keys.entries.len(), 1);
+        assert_eq!(keys.entries[0].value, "Up");
+        let profile = config.section("keys game.ch8").unwrap();
+        assert_eq!(profile.entries.len(), KEYPAD.len());
+
+        let loaded = Bindings::new(&config, rom).unwrap();
+        for input in [
+            Input::Key(Keycode::Return),
+            Input::Key(Keycode::Space),
+            Input::Key(Keycode::W),
+            Input::Key(Keycode::Up),
+            Input::Button(Button::X),
+            Input::Button(Button::A),
+            Input::Button(Button::DPadUp),
+        ] {
+            assert_eq!(loaded.lookup(input), bindings.lookup(input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn without_a_profile_saving_writes_the_global_sections() {
+        let (mut config, path) = load_config("global", "");
+        let rom = Path::new("game.ch8");
+        let mut bindings = Bindings::new(&config, rom).unwrap();
+        bindings.bind(Input::Key(Keycode::Return), 0xA);
+        bindings.save(&mut config, rom).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(config.section("keys game.ch8").is_none());
+        let keys = config.section("keys").unwrap();
+        assert!(keys
+            .entries
+            .iter()
+            .any(|entry| entry.key == "A" && entry.value == "Return"));
+        let controller = config.section("controller").unwrap();
+        assert!(controller
+            .entries
+            .iter()
+            .any(|entry| entry.key == "5" && entry.value == Button::DPadUp.string()));
+    }
+}
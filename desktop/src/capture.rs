@@ -0,0 +1,148 @@
+use crate::palette::Colors;
+use crate::scheduler::FRAME_RATE;
+use chip8_core::{Emu, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+//size of a hires pixel in screenshots and clips, lores pixels are twice as big like on screen
+const SHOT_SCALE: usize = 8;
+const CLIP_SCALE: usize = 4;
+
+//first unused game.ch8.shot1.png style name next to the ROM
+pub fn next_path(rom_path: &Path, kind: &str, extension: &str) -> PathBuf {
+    (1..)
+        .map(|number| {
+            let mut name = rom_path.as_os_str().to_owned();
+            name.push(format!(".{}{}.{}", kind, number, extension));
+            PathBuf::from(name)
+        })
+        .find(|path| !path.exists())
+        .unwrap_or_default()
+}
+
+//the display as palette indices, scaled up so hires and lores fill the same size
+fn scaled_pixels(emu: &Emu, scale: usize) -> Vec<u8> {
+    let (width, height) = (HIRES_SCREEN_WIDTH * scale, HIRES_SCREEN_HEIGHT * scale);
+    let pixel = width / emu.screen_width();
+    let display = emu.get_display();
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = &display[y / pixel * emu.screen_width()..][..emu.screen_width()];
+        pixels.extend((0..width).map(|x| row[x / pixel]));
+    }
+    pixels
+}
+
+fn palette_bytes(colors: &Colors) -> Vec<u8> {
+    colors.iter().flat_map(|c| [c.r, c.g, c.b]).collect()
+}
+
+//writes the current display as an indexed PNG
+pub fn screenshot(path: &Path, emu: &Emu, colors: &Colors) -> Result<(), Box<dyn Error>> {
+    let (width, height) = (
+        HIRES_SCREEN_WIDTH * SHOT_SCALE,
+        HIRES_SCREEN_HEIGHT * SHOT_SCALE,
+    );
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette_bytes(colors));
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&scaled_pixels(emu, SHOT_SCALE))?;
+    writer.finish()?;
+    Ok(())
+}
+
+//animated GIF of the display, pushed after every loop iteration
+//
+//frames that look like the previous one only make it stay up longer, and delays are rounded
+//against the total running time so 60 Hz adds up correctly in GIF's 1/100 s units
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    palette: Vec<u8>,
+    //frame waiting to be written until it is known how long it stays up
+    held: Option<(Vec<u8>, Vec<u8>)>,
+    held_since: u64,
+    frames: u64,
+}
+
+//hundredths of a second into the clip at the start of a frame, GIF's unit for delays
+fn centiseconds(frame: u64) -> u64 {
+    let rate = FRAME_RATE as u64;
+    (frame * 100 + rate / 2) / rate
+}
+
+impl GifRecorder {
+    pub fn create(path: &Path, colors: &Colors) -> Result<Self, Box<dyn Error>> {
+        let palette = palette_bytes(colors);
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            (HIRES_SCREEN_WIDTH * CLIP_SCALE) as u16,
+            (HIRES_SCREEN_HEIGHT * CLIP_SCALE) as u16,
+            &palette,
+        )?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Ok(Self {
+            encoder,
+            palette,
+            held: None,
+            held_since: 0,
+            frames: 0,
+        })
+    }
+
+    //adds the display as shown for the given number of frames
+    pub fn push(&mut self, emu: &Emu, colors: &Colors, frames: u64) -> Result<(), Box<dyn Error>> {
+        if frames == 0 {
+            return Ok(());
+        }
+        let frame = (scaled_pixels(emu, CLIP_SCALE), palette_bytes(colors));
+        if self.held.as_ref() != Some(&frame) {
+            self.write_held()?;
+            self.held = Some(frame);
+            self.held_since = self.frames;
+        }
+        self.frames += frames;
+        Ok(())
+    }
+
+    fn write_held(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some((pixels, palette)) = self.held.take() else {
+            return Ok(());
+        };
+        let mut delay = centiseconds(self.frames) - centiseconds(self.held_since);
+        let mut frame = gif::Frame::from_indexed_pixels(
+            (HIRES_SCREEN_WIDTH * CLIP_SCALE) as u16,
+            (HIRES_SCREEN_HEIGHT * CLIP_SCALE) as u16,
+            pixels,
+            None,
+        );
+        //a palette switched mid-recording goes into the frame itself
+        if palette != self.palette {
+            frame.palette = Some(palette);
+        }
+        //a frame longer than GIF's longest delay is repeated
+        loop {
+            frame.delay = delay.min(u16::MAX as u64) as u16;
+            self.encoder.write_frame(&frame)?;
+            delay -= frame.delay as u64;
+            if delay == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    //writes the last frame and the trailer, returns the clip's length in frames
+    pub fn finish(mut self) -> Result<u64, Box<dyn Error>> {
+        self.write_held()?;
+        let frames = self.frames;
+        self.encoder.into_inner()?.flush()?;
+        Ok(frames)
+    }
+}
@@ -0,0 +1,362 @@
+use crate::audio::Waveform;
+use crate::scheduler::FRAME_RATE;
+use chip8_core::disasm::Syntax;
+use chip8_core::{Condition, Quirks, Watchpoint, START_ADDR};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: cargo run [options] path/to/game [load address, e.g. 0x600]
+       cargo run disasm [--octo | --source] path/to/game [load address]
+       cargo run asm path/to/source path/to/game [load address]
+
+Options:
+  --record out.c8m   record the keys of every frame into a movie file
+  --play in.c8m      drive the emulator from a movie file instead of the keyboard,
+                     with the quirks, timing and load address it was recorded with
+  --seed N           seed for the random number generator (CXNN)
+  --profile NAME     quirks: default, vip, chip48, schip or xochip
+  --ips N            instructions per second with fixed timing (default 600)
+  --timing MODEL     fixed (--ips) or vip for COSMAC VIP cycle costs
+  --tone HZ          pitch of the buzzer (default 440)
+  --waveform NAME    buzzer waveform: square, sine or triangle (default square)
+  --volume PERCENT   buzzer volume from 0 to 100 (default 25)
+  --palette NAME     colours: classic, amber, green, lcd, octo, high-contrast, colorblind
+                     or one from the config file
+  --config FILE      settings file (default ~/.config/chip8/config.ini)
+  --break ADDR       stop in the debugger before the instruction at ADDR, repeatable
+                     \"ADDR if COND\" only stops when COND holds, e.g. \"0x20A if V3 == 0x10\"
+  --watch RANGE      stop after an instruction writes to RANGE (0x300 or 0x300-0x30F),
+                     add :r to watch reads or :rw for both, repeatable
+  --trace FILE       log every instruction with the registers before it runs
+  --trace-range A-B  only log instructions between A and B, repeatable
+  --trace-limit N    stop logging after N lines
+  --gdb PORT         accept a gdb or lldb remote connection on localhost:PORT
+  --dap PORT         accept a Debug Adapter Protocol client (an editor) on localhost:PORT
+
+disasm prints a listing of the ROM instead of running it, --octo switches
+from Cowgod's mnemonics to Octo syntax and --source prints it in a form asm reads back.
+asm assembles Cowgod style source into a ROM. Octo sources (.8o) are compiled when
+they are opened, they always load at 0x200.";
+
+const DEFAULT_TONE: f32 = 440.0;
+const DEFAULT_VOLUME: f32 = 0.25;
+
+//what the binary was asked to do
+pub enum Command {
+    Run(Box<Options>),
+    Disasm(DisasmOptions),
+    Asm(AsmOptions),
+}
+
+//command line of the desktop frontend
+pub struct Options {
+    pub rom: PathBuf,
+    //the machine settings are None when not given, a movie being played brings its own
+    pub load_addr: Option<u16>,
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub quirks: Option<Quirks>,
+    pub vip_timing: Option<bool>,
+    pub ips: Option<u32>,
+    pub tone: f32,
+    pub waveform: Waveform,
+    //0 to 1
+    pub volume: f32,
+    pub palette: Option<String>,
+    pub config: Option<PathBuf>,
+    pub breakpoints: Vec<(u16, Option<Condition>)>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub trace: Option<PathBuf>,
+    pub trace_ranges: Vec<RangeInclusive<u16>>,
+    pub trace_limit: Option<usize>,
+    pub gdb: Option<u16>,
+    pub dap: Option<u16>,
+}
+
+pub struct DisasmOptions {
+    pub rom: PathBuf,
+    pub base: u16,
+    pub syntax: Syntax,
+    pub source: bool,
+}
+
+pub struct AsmOptions {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub base: u16,
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.split_first() {
+        Some((cmd, rest)) if cmd == "disasm" => parse_disasm(rest).map(Command::Disasm),
+        Some((cmd, rest)) if cmd == "asm" => parse_asm(rest).map(Command::Asm),
+        _ => parse_run(args).map(|options| Command::Run(Box::new(options))),
+    }
+}
+
+fn parse_disasm(args: &[String]) -> Result<DisasmOptions, String> {
+    let mut syntax = Syntax::Cowgod;
+    let mut source = false;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            "--source" => source = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+    //the assembler only reads Cowgod's mnemonics
+    if source && syntax == Syntax::Octo {
+        return Err("--source and --octo cannot be used together".to_string());
+    }
+    let (rom, base) = parse_rom_and_addr(&positional)?;
+    let base = base.unwrap_or(START_ADDR);
+    Ok(DisasmOptions {
+        rom,
+        base,
+        syntax,
+        source,
+    })
+}
+
+//asm path/to/source path/to/game [load address]
+fn parse_asm(args: &[String]) -> Result<AsmOptions, String> {
+    match args {
+        [source, rest @ ..] if !source.starts_with("--") && !rest.is_empty() => {
+            let positional: Vec<_> = rest.iter().collect();
+            let (output, base) = parse_rom_and_addr(&positional)?;
+            Ok(AsmOptions {
+                source: PathBuf::from(source),
+                output,
+                base: base.unwrap_or(START_ADDR),
+            })
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn parse_run(args: &[String]) -> Result<Options, String> {
+    let mut positional = Vec::new();
+    let mut record = None;
+    let mut play = None;
+    let mut seed = None;
+    let mut quirks = None;
+    let mut vip_timing = None;
+    let mut ips = None;
+    let mut tone = DEFAULT_TONE;
+    let mut waveform = Waveform::Square;
+    let mut volume = DEFAULT_VOLUME;
+    let mut palette = None;
+    let mut config = None;
+    let mut breakpoints = Vec::new();
+    let mut watchpoints = Vec::new();
+    let mut trace = None;
+    let mut trace_ranges = Vec::new();
+    let mut trace_limit = None;
+    let mut gdb = None;
+    let mut dap = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        //every option takes exactly one value
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--record" => record = Some(PathBuf::from(value()?)),
+            "--play" => play = Some(PathBuf::from(value()?)),
+            "--seed" => {
+                let text = value()?;
+                seed = Some(parse_number(text).ok_or_else(|| format!("Invalid seed: {}", text))?);
+            }
+            "--profile" => {
+                let text = value()?;
+                quirks = Some(
+                    Quirks::from_name(text).ok_or_else(|| format!("Unknown profile: {}", text))?,
+                );
+            }
+            "--ips" => {
+                let text = value()?;
+                ips = Some(
+                    text.parse()
+                        .ok()
+                        .filter(|&ips| ips > 0)
+                        .ok_or_else(|| format!("Invalid instructions per second: {}", text))?,
+                );
+            }
+            "--tone" => {
+                let text = value()?;
+                tone = text
+                    .parse()
+                    .ok()
+                    .filter(|&tone: &f32| tone > 0.0 && tone < 20000.0)
+                    .ok_or_else(|| format!("Invalid tone: {}", text))?;
+            }
+            "--waveform" => {
+                let text = value()?;
+                waveform = Waveform::from_name(text)
+                    .ok_or_else(|| format!("Unknown waveform: {}", text))?;
+            }
+            "--volume" => {
+                let text = value()?;
+                let percent: u8 = text
+                    .parse()
+                    .ok()
+                    .filter(|&percent| percent <= 100)
+                    .ok_or_else(|| format!("Invalid volume: {}", text))?;
+                volume = percent as f32 / 100.0;
+            }
+            "--palette" => palette = Some(value()?.clone()),
+            "--config" => config = Some(PathBuf::from(value()?)),
+            "--timing" => match value()?.as_str() {
+                "fixed" => vip_timing = Some(false),
+                "vip" => vip_timing = Some(true),
+                text => return Err(format!("Unknown timing model: {}", text)),
+            },
+            "--break" => breakpoints.push(parse_breakpoint(value()?)?),
+            "--watch" => watchpoints.push(parse_watchpoint(value()?)?),
+            "--trace" => trace = Some(PathBuf::from(value()?)),
+            "--trace-range" => {
+                let text = value()?;
+                trace_ranges
+                    .push(parse_range(text).ok_or_else(|| format!("Invalid range: {}", text))?);
+            }
+            "--trace-limit" => {
+                let text = value()?;
+                trace_limit = Some(
+                    text.parse()
+                        .map_err(|_| format!("Invalid trace limit: {}", text))?,
+                );
+            }
+            "--gdb" => {
+                let text = value()?;
+                gdb = Some(
+                    text.parse()
+                        .map_err(|_| format!("Invalid port: {}", text))?,
+                );
+            }
+            "--dap" => {
+                let text = value()?;
+                dap = Some(
+                    text.parse()
+                        .map_err(|_| format!("Invalid port: {}", text))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    if vip_timing == Some(true) && ips.is_some() {
+        return Err("--ips only applies to fixed timing".to_string());
+    }
+    if record.is_some() && play.is_some() {
+        return Err("--record and --play cannot be used together".to_string());
+    }
+    //movies store whole instructions per frame
+    let movie = record.is_some() || play.is_some();
+    if movie && ips.is_some_and(|ips| ips % FRAME_RATE != 0) {
+        return Err(format!(
+            "--ips has to be a multiple of {} with a movie",
+            FRAME_RATE
+        ));
+    }
+    let debugging =
+        !breakpoints.is_empty() || !watchpoints.is_empty() || gdb.is_some() || dap.is_some();
+    if debugging && (record.is_some() || play.is_some()) {
+        return Err("--break, --watch, --gdb and --dap cannot be used with a movie".to_string());
+    }
+    let (rom, load_addr) = parse_rom_and_addr(&positional)?;
+    if rom.extension().is_some_and(|ext| ext == "8o")
+        && load_addr.is_some_and(|addr| addr != START_ADDR)
+    {
+        return Err("Octo sources always load at 0x200".to_string());
+    }
+
+    Ok(Options {
+        rom,
+        load_addr,
+        record,
+        play,
+        seed,
+        quirks,
+        vip_timing,
+        ips,
+        tone,
+        waveform,
+        volume,
+        palette,
+        config,
+        breakpoints,
+        watchpoints,
+        trace,
+        trace_ranges,
+        trace_limit,
+        gdb,
+        dap,
+    })
+}
+
+pub fn parse_addr(text: &str) -> Option<u16> {
+    parse_number(text.trim()).and_then(|addr| u16::try_from(addr).ok())
+}
+
+//ADDR or "ADDR if COND"
+fn parse_breakpoint(text: &str) -> Result<(u16, Option<Condition>), String> {
+    let (addr_text, condition) = match text.split_once(" if ") {
+        Some((addr, condition)) => {
+            let condition = Condition::parse(condition)
+                .map_err(|err| format!("Invalid breakpoint condition `{}`: {}", condition, err))?;
+            (addr, Some(condition))
+        }
+        None => (text, None),
+    };
+    let addr = parse_addr(addr_text)
+        .ok_or_else(|| format!("Invalid breakpoint address: {}", addr_text))?;
+    Ok((addr, condition))
+}
+
+//START[-END][:r|:w|:rw], writes only by default
+fn parse_watchpoint(text: &str) -> Result<Watchpoint, String> {
+    let invalid = || format!("Invalid watchpoint: {}", text);
+    let (range, (read, write)) = match text.rsplit_once(':') {
+        Some((range, "r")) => (range, (true, false)),
+        Some((range, "w")) => (range, (false, true)),
+        Some((range, "rw")) => (range, (true, true)),
+        Some(_) => return Err(invalid()),
+        None => (text, (false, true)),
+    };
+    let range = parse_range(range).ok_or_else(invalid)?;
+    Ok(Watchpoint::new(range, read, write))
+}
+
+//START-END or a single address
+fn parse_range(text: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (parse_addr(start)?, parse_addr(end)?),
+        None => (parse_addr(text)?, parse_addr(text)?),
+    };
+    (start <= end).then_some(start..=end)
+}
+
+//path/to/game [load address]
+fn parse_rom_and_addr(positional: &[&String]) -> Result<(PathBuf, Option<u16>), String> {
+    match positional {
+        [rom] => Ok((PathBuf::from(rom), None)),
+        [rom, addr] => {
+            let addr = parse_number(addr)
+                .and_then(|addr| u16::try_from(addr).ok())
+                .ok_or_else(|| format!("Invalid load address: {}", addr))?;
+            Ok((PathBuf::from(rom), Some(addr)))
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+//accepts 0x600 style hex as well as plain decimal numbers
+pub fn parse_number(arg: &str) -> Option<u64> {
+    match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
@@ -0,0 +1,321 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+//settings file of the desktop frontend, ini style:
+//
+//  palette = amber
+//
+//  [palette mine]
+//  background = #202020
+//
+//keys before the first [section] belong to the unnamed top section, lines starting with ; or #
+//are comments
+#[derive(Default)]
+pub struct Config {
+    //None when there is nowhere to look for the file
+    path: Option<PathBuf>,
+    //the file as read, kept so writing a section back leaves the rest as it was
+    lines: Vec<String>,
+    sections: Vec<Section>,
+}
+
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<Entry>,
+    //lines of the file from the [header] up to the next one
+    lines: Range<usize>,
+}
+
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    pub line: usize,
+}
+
+//~/.config/chip8/config.ini, or where XDG_CONFIG_HOME or APPDATA point to
+pub fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(dir.join("chip8").join("config.ini"))
+}
+
+impl Config {
+    //a file given on the command line has to exist, the default one is optional
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(path, &text),
+            //a missing default file is created when something is saved
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => Self::parse(path, ""),
+            Err(err) => Err(format!("Unable to read {}: {}", path.display(), err)),
+        }
+    }
+
+    fn parse(path: PathBuf, text: &str) -> Result<Self, String> {
+        let mut config = Self {
+            path: Some(path),
+            lines: text.lines().map(str::to_string).collect(),
+            sections: vec![Section {
+                name: String::new(),
+                entries: Vec::new(),
+                lines: 0..0,
+            }],
+        };
+        for (index, line) in config.lines.iter().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            //blank lines and comments belong to the section they follow
+            if let Some(section) = config
+                .sections
+                .last_mut()
+                .filter(|_| !line.starts_with('['))
+            {
+                section.lines.end = index + 1;
+            }
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| config.error(line_number, "missing ]"))?;
+                config.sections.push(Section {
+                    //"palette  mine" and "palette mine" are the same section
+                    name: name.split_whitespace().collect::<Vec<_>>().join(" "),
+                    entries: Vec::new(),
+                    lines: index..index + 1,
+                });
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| config.error(line_number, "expected key = value"))?;
+            if let Some(section) = config.sections.last_mut() {
+                section.entries.push(Entry {
+                    key: key.trim().to_string(),
+                    value: value.trim().to_string(),
+                    line: line_number,
+                });
+            }
+        }
+        Ok(config)
+    }
+
+    //error message pointing at a line of the file
+    pub fn error(&self, line: usize, msg: &str) -> String {
+        let path = self.path.as_deref().unwrap_or(Path::new("config"));
+        format!("{}:{}: {}", path.display(), line, msg)
+    }
+
+    //value of a key in the top section
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.section("")?
+            .entries
+            .iter()
+            .rev()
+            .find(|entry| entry.key == key)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    //sections named "kind something", with the something
+    pub fn sections_of<'a>(
+        &'a self,
+        kind: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a Section)> {
+        self.sections.iter().filter_map(move |section| {
+            let name = section.name.strip_prefix(kind)?.strip_prefix(' ')?;
+            Some((name, section))
+        })
+    }
+
+    //replaces a section, or adds it at the end, and writes the file
+    //
+    //comments inside the replaced section are lost, everything else stays as it was
+    pub fn save_section(
+        &mut self,
+        name: &str,
+        entries: &[(String, String)],
+    ) -> Result<PathBuf, String> {
+        let path = self
+            .path
+            .clone()
+            .ok_or("No place for a config file, use --config")?;
+        let mut lines = vec![format!("[{}]", name)];
+        lines.extend(
+            entries
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, value)),
+        );
+        lines.push(String::new());
+        let range = match self.section(name) {
+            Some(section) => section.lines.clone(),
+            None => {
+                if self
+                    .lines
+                    .last()
+                    .is_some_and(|line| !line.trim().is_empty())
+                {
+                    self.lines.push(String::new());
+                }
+                self.lines.len()..self.lines.len()
+            }
+        };
+        self.lines.splice(range, lines);
+        while self.lines.last().is_some_and(|line| line.trim().is_empty()) {
+            self.lines.pop();
+        }
+        let text = self.lines.join("\n") + "\n";
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|err| format!("Unable to create {}: {}", dir.display(), err))?;
+        }
+        fs::write(&path, &text)
+            .map_err(|err| format!("Unable to write {}: {}", path.display(), err))?;
+        *self = Self::parse(path.clone(), &text)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Config, String> {
+        Config::parse(PathBuf::from("test.ini"), text)
+    }
+
+    //a config file of its own in the temp directory
+    fn temp_file(name: &str, text: &str) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("chip8-config-{}-{}.ini", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn entries(section: &Section) -> Vec<(&str, &str, usize)> {
+        section
+            .entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.value.as_str(), entry.line))
+            .collect()
+    }
+
+    #[test]
+    fn sections_and_entries() {
+        let config = parse(
+            "; settings\n\
+             palette = amber\n\
+             palette=green\n\
+             \n\
+             [palette   mine]\n\
+             # colours\n\
+             background = #202020 \n\
+             [keys game.ch8]\n\
+             5 = Up, W\n",
+        )
+        .unwrap();
+        //the last one wins
+        assert_eq!(config.get("palette").unwrap().value, "green");
+        assert_eq!(config.get("palette").unwrap().line, 3);
+        assert!(config.get("background").is_none());
+        let mine = config.section("palette mine").unwrap();
+        assert_eq!(entries(mine), [("background", "#202020", 7)]);
+        let keys = config.section("keys game.ch8").unwrap();
+        assert_eq!(entries(keys), [("5", "Up, W", 9)]);
+        let palettes: Vec<_> = config
+            .sections_of("palette")
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(palettes, ["mine"]);
+        assert!(config.sections_of("key").next().is_none());
+    }
+
+    #[test]
+    fn bad_lines_are_reported_with_their_number() {
+        assert_eq!(
+            parse("a = 1\n[keys\n").err().as_deref(),
+            Some("test.ini:2: missing ]")
+        );
+        assert_eq!(
+            parse("[keys]\n\n5 Up\n").err().as_deref(),
+            Some("test.ini:3: expected key = value")
+        );
+    }
+
+    #[test]
+    fn empty_file_has_an_empty_top_section() {
+        let config = parse("").unwrap();
+        assert!(config.section("").unwrap().entries.is_empty());
+        assert!(config.get("palette").is_none());
+    }
+
+    #[test]
+    fn saving_a_section_keeps_the_rest_of_the_file() {
+        let path = temp_file(
+            "save",
+            "; my settings\n\
+             palette = amber\n\
+             \n\
+             [keys]\n\
+             ; old comment\n\
+             5 = Up\n\
+             \n\
+             [palette mine]\n\
+             both = #FFFFFF\n",
+        );
+        let mut config = Config::load(Some(&path)).unwrap();
+        let saved = config
+            .save_section("keys", &[("5".to_string(), "Space".to_string())])
+            .unwrap();
+        assert_eq!(saved, path);
+        config
+            .save_section("controller", &[("6".to_string(), "x".to_string())])
+            .unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            text,
+            "; my settings\n\
+             palette = amber\n\
+             \n\
+             [keys]\n\
+             5 = Space\n\
+             \n\
+             [palette mine]\n\
+             both = #FFFFFF\n\
+             \n\
+             [controller]\n\
+             6 = x\n"
+        );
+        //the config in memory follows the file
+        assert_eq!(config.section("keys").unwrap().entries[0].value, "Space");
+        assert_eq!(config.section("controller").unwrap().entries[0].line, 11);
+        assert_eq!(config.get("palette").unwrap().value, "amber");
+    }
+
+    #[test]
+    fn missing_files() {
+        let path = env::temp_dir().join(format!("chip8-config-{}-missing.ini", std::process::id()));
+        let err = Config::load(Some(&path)).err().unwrap();
+        assert!(err.starts_with("Unable to read"), "{}", err);
+        let mut config = Config::default();
+        assert_eq!(
+            config.save_section("keys", &[]).err().as_deref(),
+            Some("No place for a config file, use --config")
+        );
+    }
+}
@@ -0,0 +1,651 @@
+use crate::cli::parse_addr;
+use chip8_core::octo::Program;
+use chip8_core::{Condition, Emu, Quirks, RngKind, StopReason, START_ADDR};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+
+//Debug Adapter Protocol server, polled once per frame from the game loop like the GDB stub
+//
+//editors connect to localhost:PORT (a "debugServer" launch configuration in VS Code), the emulator
+//is paused until configurationDone. Source breakpoints need an Octo program, its line map
+//turns lines into addresses, instruction breakpoints work for any ROM
+pub struct DapServer {
+    listener: TcpListener,
+    client: Option<Client>,
+    //program being debugged and its symbols, empty for binary ROMs
+    path: PathBuf,
+    program: Program,
+    setup: Setup,
+}
+
+//what the machine was started with on the command line, a launch starts it the same way
+pub struct Setup {
+    pub load_addr: u16,
+    pub quirks: Quirks,
+    pub rng_kind: RngKind,
+    pub seed: u64,
+}
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    seq: u64,
+    //the editor was told the emulator runs, a stopped event is due once it stops
+    running: bool,
+    stop_on_entry: bool,
+    //whether the emulator ran before the client paused it, it runs on after a disconnect
+    resume_on_disconnect: bool,
+    //breakpoints owned by the session, replaced as a whole by every set request
+    source_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+}
+
+//the emulator has a single thread of execution
+const THREAD_ID: u64 = 1;
+//variablesReference of the two scopes
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+//limits on what a client may make the buffer hold, real requests are far smaller
+const MAX_HEADER_LEN: usize = 1024;
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+impl DapServer {
+    //listens on localhost only, the protocol has no authentication
+    pub fn bind(port: u16, path: &Path, program: Program, setup: Setup) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+            path: path.to_path_buf(),
+            program,
+            setup,
+        })
+    }
+
+    //accepts an editor and handles its requests, true when a launch loaded a new program
+    pub fn poll(&mut self, emu: &mut Emu) -> bool {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if stream.set_nonblocking(true).is_err() {
+                        return false;
+                    }
+                    println!("Debug adapter client connected from {}", addr);
+                    //breakpoints arrive before configurationDone, nothing may run until then
+                    let resume_on_disconnect = !emu.is_stopped();
+                    emu.pause();
+                    self.client = Some(Client {
+                        stream,
+                        buffer: Vec::new(),
+                        seq: 1,
+                        running: false,
+                        stop_on_entry: false,
+                        resume_on_disconnect,
+                        source_breakpoints: Vec::new(),
+                        instruction_breakpoints: Vec::new(),
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return false,
+                Err(err) => {
+                    eprintln!("Debug adapter connection failed: {}", err);
+                    return false;
+                }
+            }
+        }
+        let Some(mut client) = self.client.take() else {
+            return false;
+        };
+        let mut loaded = false;
+        match self.serve(&mut client, emu, &mut loaded) {
+            Ok(()) => self.client = Some(client),
+            Err(err) => {
+                if err.kind() != ErrorKind::ConnectionAborted {
+                    eprintln!("Debug adapter connection lost: {}", err);
+                }
+                println!("Debug adapter client disconnected");
+                for addr in client
+                    .source_breakpoints
+                    .iter()
+                    .chain(&client.instruction_breakpoints)
+                {
+                    emu.remove_breakpoint(*addr);
+                }
+                //a machine stopped before the session, e.g. with F8, stays stopped
+                if client.resume_on_disconnect {
+                    emu.resume();
+                } else {
+                    emu.pause();
+                }
+            }
+        }
+        loaded
+    }
+
+    fn serve(&mut self, client: &mut Client, emu: &mut Emu, loaded: &mut bool) -> io::Result<()> {
+        let mut chunk = [0; 4096];
+        loop {
+            match client.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(len) => client.buffer.extend_from_slice(&chunk[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        while let Some(message) = client.next_message()? {
+            if message["type"] == "request" {
+                self.request(client, &message, emu, loaded)?;
+            }
+        }
+        if client.running && emu.is_stopped() {
+            client.running = false;
+            let reason = match emu.stop_reason() {
+                Some(StopReason::Breakpoint(_)) => "breakpoint",
+                Some(StopReason::Watchpoint { .. }) => "data breakpoint",
+                Some(StopReason::Paused) => "pause",
+                Some(StopReason::Step) | None => "step",
+            };
+            client.stopped(reason)?;
+        }
+        Ok(())
+    }
+
+    fn request(
+        &mut self,
+        client: &mut Client,
+        message: &Value,
+        emu: &mut Emu,
+        loaded: &mut bool,
+    ) -> io::Result<()> {
+        let command = message["command"].as_str().unwrap_or_default();
+        let args = &message["arguments"];
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+                "supportsSetVariable": true,
+            })),
+            "launch" => self.launch(client, args, emu, loaded),
+            "attach" => Ok(Value::Null),
+            "setBreakpoints" => Ok(self.set_breakpoints(client, args, emu)),
+            "setInstructionBreakpoints" => Ok(set_instruction_breakpoints(client, args, emu)),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => Ok(Value::Null),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace(emu)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+            ] })),
+            "variables" => Ok(variables(emu, args["variablesReference"].as_u64())),
+            "setVariable" => set_variable(emu, args),
+            "evaluate" => evaluate(emu, args),
+            "continue" => {
+                emu.resume();
+                client.running = true;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" => {
+                let result = if command == "next" {
+                    emu.step_over()
+                } else {
+                    emu.step_into()
+                };
+                client.running = true;
+                result.map(|()| Value::Null).map_err(|err| err.to_string())
+            }
+            "stepOut" => {
+                emu.step_out();
+                client.running = true;
+                Ok(Value::Null)
+            }
+            "pause" => {
+                emu.pause();
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => {
+                client.respond(message, Ok(Value::Null))?;
+                return Err(ErrorKind::ConnectionAborted.into());
+            }
+            _ => Err(format!("Unsupported request: {}", command)),
+        };
+        client.respond(message, body)?;
+        match command {
+            //the client sends its breakpoints once it hears this
+            "initialize" => client.event("initialized", Value::Null),
+            "configurationDone" if client.stop_on_entry => client.stopped("entry"),
+            "configurationDone" => {
+                emu.resume();
+                client.running = true;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    //launch arguments: program (a ROM or .8o, defaults to the one on the command line)
+    //and stopOnEntry
+    fn launch(
+        &mut self,
+        client: &mut Client,
+        args: &Value,
+        emu: &mut Emu,
+        loaded: &mut bool,
+    ) -> Result<Value, String> {
+        client.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        let Some(path) = args["program"].as_str().map(PathBuf::from) else {
+            return Ok(Value::Null);
+        };
+        let program = crate::read_rom(&path)?;
+        //Octo sources always start at 0x200, other ROMs at the command line address
+        let addr = if program.lines.is_empty() && same_file(&path, &self.path) {
+            self.setup.load_addr
+        } else {
+            START_ADDR
+        };
+        //a fresh start, with the same profile and random numbers as the first one
+        emu.reset();
+        emu.set_quirks(self.setup.quirks);
+        emu.seed_rng(self.setup.rng_kind, self.setup.seed);
+        emu.load_at(addr, &program.rom)
+            .map_err(|err| format!("Unable to load {}: {}", path.display(), err))?;
+        self.path = path;
+        self.program = program;
+        *loaded = true;
+        Ok(Value::Null)
+    }
+
+    //maps each requested line to the first instruction on it or the next line with code
+    fn set_breakpoints(&mut self, client: &mut Client, args: &Value, emu: &mut Emu) -> Value {
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let path = args["source"]["path"].as_str().map(PathBuf::from);
+        let ours = path.is_some_and(|path| same_file(&path, &self.path));
+        if !ours || self.program.lines.is_empty() {
+            let unverified: Vec<_> = requested
+                .iter()
+                .map(|_| json!({ "verified": false, "message": "no symbols for this source" }))
+                .collect();
+            return json!({ "breakpoints": unverified });
+        }
+        for addr in client.source_breakpoints.drain(..) {
+            emu.remove_breakpoint(addr);
+        }
+        let mut breakpoints = Vec::new();
+        for bp in &requested {
+            let line = bp["line"].as_u64().unwrap_or(0) as usize;
+            let target = self
+                .program
+                .lines
+                .iter()
+                .filter(|(_, &at)| at >= line)
+                .min_by_key(|(&addr, &at)| (at, addr));
+            let Some((&addr, &at)) = target else {
+                breakpoints
+                    .push(json!({ "verified": false, "message": "no code on or after this line" }));
+                continue;
+            };
+            let mut result = add_breakpoint(emu, addr, bp["condition"].as_str());
+            if result["verified"] == true {
+                client.source_breakpoints.push(addr);
+            }
+            result["line"] = json!(at);
+            breakpoints.push(result);
+        }
+        json!({ "breakpoints": breakpoints })
+    }
+
+    //innermost frame first, every return address on the stack is one CALL further out
+    fn stack_trace(&self, emu: &Emu) -> Value {
+        let calls = emu.stack().iter().rev().map(|ret| ret.wrapping_sub(2));
+        let frames: Vec<_> = std::iter::once(emu.pc())
+            .chain(calls)
+            .enumerate()
+            .map(|(id, addr)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": self.symbol(addr),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#05X}", addr),
+                });
+                if let Some(&line) = self.program.lines.get(&addr) {
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                    frame["source"] = json!({ "path": self.path.display().to_string() });
+                }
+                frame
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    //nearest label at or before the address, e.g. `draw-player+0x4`
+    fn symbol(&self, addr: u16) -> String {
+        let label = self
+            .program
+            .labels
+            .iter()
+            .filter(|(_, &at)| at <= addr)
+            .max_by_key(|(_, &at)| at);
+        match label {
+            Some((name, &at)) if at == addr => name.clone(),
+            Some((name, &at)) => format!("{}+{:#X}", name, addr - at),
+            None => format!("{:#05X}", addr),
+        }
+    }
+}
+
+fn add_breakpoint(emu: &mut Emu, addr: u16, condition: Option<&str>) -> Value {
+    match condition.filter(|text| !text.trim().is_empty()) {
+        Some(text) => match Condition::parse(text) {
+            Ok(condition) => emu.add_conditional_breakpoint(addr, condition),
+            Err(err) => return json!({ "verified": false, "message": err.to_string() }),
+        },
+        None => emu.add_breakpoint(addr),
+    }
+    json!({ "verified": true, "instructionReference": format!("{:#05X}", addr) })
+}
+
+fn set_instruction_breakpoints(client: &mut Client, args: &Value, emu: &mut Emu) -> Value {
+    for addr in client.instruction_breakpoints.drain(..) {
+        emu.remove_breakpoint(addr);
+    }
+    let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+    let mut breakpoints = Vec::new();
+    for bp in &requested {
+        let reference = bp["instructionReference"].as_str().and_then(parse_addr);
+        let offset = bp["offset"].as_i64().unwrap_or(0);
+        let addr = reference.and_then(|addr| u16::try_from(addr as i64 + offset).ok());
+        let Some(addr) = addr else {
+            breakpoints.push(json!({ "verified": false, "message": "invalid address" }));
+            continue;
+        };
+        let result = add_breakpoint(emu, addr, bp["condition"].as_str());
+        if result["verified"] == true {
+            client.instruction_breakpoints.push(addr);
+        }
+        breakpoints.push(result);
+    }
+    json!({ "breakpoints": breakpoints })
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+fn variables(emu: &Emu, reference: Option<u64>) -> Value {
+    let mut list = Vec::new();
+    match reference {
+        Some(REGISTERS) => {
+            list.push(variable("PC", format!("{:#05X}", emu.pc())));
+            list.push(variable("I", format!("{:#05X}", emu.i_reg())));
+            list.push(variable("SP", emu.sp().to_string()));
+            for (idx, val) in emu.v_reg().iter().enumerate() {
+                list.push(variable(&format!("V{:X}", idx), format!("{:#04X}", val)));
+            }
+        }
+        Some(TIMERS) => {
+            list.push(variable("DT", emu.delay_timer().to_string()));
+            list.push(variable("ST", emu.sound_timer().to_string()));
+        }
+        _ => (),
+    }
+    json!({ "variables": list })
+}
+
+//the new value is an expression in breakpoint condition syntax, so `V3 + 1` works too
+fn set_variable(emu: &mut Emu, args: &Value) -> Result<Value, String> {
+    let name = args["name"]
+        .as_str()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let text = args["value"].as_str().unwrap_or_default();
+    let condition = Condition::parse(text).map_err(|err| err.to_string())?;
+    let value = emu.evaluate(&condition);
+    let value = match name.as_str() {
+        "PC" => {
+            emu.set_pc(value as u16);
+            format!("{:#05X}", emu.pc())
+        }
+        "I" => {
+            emu.set_i_reg(value as u16);
+            format!("{:#05X}", emu.i_reg())
+        }
+        "DT" => {
+            emu.set_delay_timer(value as u8);
+            emu.delay_timer().to_string()
+        }
+        "ST" => {
+            emu.set_sound_timer(value as u8);
+            emu.sound_timer().to_string()
+        }
+        _ => {
+            let idx = name
+                .strip_prefix('V')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+                .ok_or_else(|| format!("{} cannot be changed", name))?;
+            emu.set_v_reg(idx, value as u8);
+            format!("{:#04X}", emu.v_reg()[idx])
+        }
+    };
+    Ok(json!({ "value": value }))
+}
+
+//watch and hover expressions use the breakpoint condition syntax
+fn evaluate(emu: &Emu, args: &Value) -> Result<Value, String> {
+    let text = args["expression"].as_str().unwrap_or_default();
+    let condition = Condition::parse(text).map_err(|err| err.to_string())?;
+    let value = emu.evaluate(&condition);
+    Ok(json!({ "result": format!("{} ({:#X})", value, value), "variablesReference": 0 }))
+}
+
+impl Client {
+    //takes the next complete message out of the buffer
+    fn next_message(&mut self) -> io::Result<Option<Value>> {
+        let Some(end) = self.buffer.windows(4).position(|w| w == b"\r\n\r\n") else {
+            if self.buffer.len() > MAX_HEADER_LEN {
+                return Err(io::Error::new(ErrorKind::InvalidData, "header too long"));
+            }
+            return Ok(None);
+        };
+        let header = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+        let len = header
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length:"))
+            .and_then(|len| len.trim().parse::<usize>().ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "missing Content-Length"))?;
+        if len > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(ErrorKind::InvalidData, "message too long"));
+        }
+        let start = end + 4;
+        let stop = start
+            .checked_add(len)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "message too long"))?;
+        if self.buffer.len() < stop {
+            return Ok(None);
+        }
+        let message = serde_json::from_slice(&self.buffer[start..stop])
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        self.buffer.drain(..stop);
+        Ok(Some(message))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        let data = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        //messages are small, blocking for them keeps the frame loop simple
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(data.as_bytes());
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+
+    fn respond(&mut self, request: &Value, body: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(Value::Null) => (),
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    const SEED: u64 = 42;
+
+    //a server on a free port with an editor connected to it
+    fn connect() -> (DapServer, TcpStream) {
+        let setup = Setup {
+            load_addr: START_ADDR,
+            quirks: Quirks::vip(),
+            rng_kind: RngKind::Xorshift,
+            seed: SEED,
+        };
+        let server = DapServer::bind(0, Path::new("game.ch8"), Program::default(), setup).unwrap();
+        let peer = TcpStream::connect(server.listener.local_addr().unwrap()).unwrap();
+        (server, peer)
+    }
+
+    fn request(peer: &mut TcpStream, command: &str, arguments: Value) {
+        let body = json!({
+            "seq": 1,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        write!(peer, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    }
+
+    //polls like the game loop until the condition holds
+    fn poll_until(server: &mut DapServer, emu: &mut Emu, done: impl Fn(&DapServer) -> bool) {
+        for _ in 0..500 {
+            server.poll(emu);
+            if done(server) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        panic!("the server did not get there");
+    }
+
+    fn disconnect_after_session(emu: &mut Emu) {
+        let (mut server, mut peer) = connect();
+        poll_until(&mut server, emu, |server| server.client.is_some());
+        assert!(emu.is_stopped());
+        request(&mut peer, "disconnect", Value::Null);
+        poll_until(&mut server, emu, |server| server.client.is_none());
+    }
+
+    #[test]
+    fn disconnect_resumes_a_running_machine() {
+        let mut emu = Emu::new();
+        disconnect_after_session(&mut emu);
+        assert!(!emu.is_stopped());
+    }
+
+    #[test]
+    fn disconnect_keeps_a_paused_machine_paused() {
+        let mut emu = Emu::new();
+        emu.pause();
+        disconnect_after_session(&mut emu);
+        assert!(emu.is_stopped());
+    }
+
+    //the client is dropped for a message the server refuses to buffer
+    fn rejects(data: &[u8]) {
+        let mut emu = Emu::new();
+        let (mut server, mut peer) = connect();
+        poll_until(&mut server, &mut emu, |server| server.client.is_some());
+        peer.write_all(data).unwrap();
+        poll_until(&mut server, &mut emu, |server| server.client.is_none());
+    }
+
+    #[test]
+    fn huge_content_length_drops_the_client() {
+        rejects(format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX).as_bytes());
+        rejects(format!("Content-Length: {}\r\n\r\n{{}}", MAX_MESSAGE_LEN + 1).as_bytes());
+    }
+
+    #[test]
+    fn endless_header_drops_the_client() {
+        rejects(&[b'x'; MAX_HEADER_LEN + 1]);
+    }
+
+    #[test]
+    fn launch_restarts_with_the_profile_and_seed() {
+        //RND V0, 0xFF
+        let rom = [0xC0, 0xFF];
+        let path = std::env::temp_dir().join(format!("dap-launch-{}.ch8", std::process::id()));
+        fs::write(&path, rom).unwrap();
+
+        let mut expected = Emu::with_quirks(Quirks::vip());
+        expected.seed_rng(RngKind::Xorshift, SEED);
+        expected.load(&rom).unwrap();
+        expected.tick().unwrap();
+
+        //a machine that has drawn random numbers with other settings since it started
+        let mut emu = Emu::new();
+        emu.seed_rng(RngKind::Timed, 7);
+        emu.load(&rom).unwrap();
+        for _ in 0..5 {
+            emu.tick().unwrap();
+            emu.set_pc(START_ADDR);
+        }
+
+        let (mut server, mut peer) = connect();
+        poll_until(&mut server, &mut emu, |server| server.client.is_some());
+        request(
+            &mut peer,
+            "launch",
+            json!({ "program": path.to_string_lossy() }),
+        );
+        poll_until(&mut server, &mut emu, |server| server.path == path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(emu.quirks(), Quirks::vip());
+        emu.step_into().unwrap();
+        assert_eq!(emu.v_reg()[0], expected.v_reg()[0]);
+    }
+}
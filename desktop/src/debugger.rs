@@ -0,0 +1,52 @@
+use chip8_core::disasm::{self, Syntax};
+use chip8_core::{Access, Emu, StopReason};
+
+//machine state printed whenever the debugger stops, e.g.
+//
+//    breakpoint at 0x20A: LD V3, 0x04
+//    V0-7: 01 00 03 00 00 00 00 00  V8-F: 00 00 00 00 00 00 00 00
+//    I=0x000 SP=1 DT=00 ST=00 stack: 0x204
+pub fn describe(emu: &Emu) -> String {
+    let pc = emu.pc();
+    let instruction = emu
+        .memory()
+        .get(pc as usize..pc as usize + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .and_then(|op| disasm::decode(op, Syntax::Cowgod))
+        .unwrap_or_else(|| "???".to_string());
+    let reason = match emu.stop_reason() {
+        Some(StopReason::Breakpoint(_)) => "breakpoint at".to_string(),
+        Some(StopReason::Paused) => "paused at".to_string(),
+        Some(StopReason::Watchpoint { pc, addr, access }) => {
+            let access = match access {
+                Access::Read => "read from",
+                Access::Write => "write to",
+            };
+            format!("{} {:#05X} by {:#05X}, now at", access, addr, pc)
+        }
+        Some(StopReason::Step) | None => "at".to_string(),
+    };
+    let regs: Vec<_> = emu.v_reg().iter().map(|v| format!("{:02X}", v)).collect();
+    let stack: Vec<_> = emu
+        .stack()
+        .iter()
+        .map(|addr| format!("{:#05X}", addr))
+        .collect();
+    format!(
+        "{} {:#05X}: {}\nV0-7: {}  V8-F: {}\nI={:#05X} SP={} DT={:02X} ST={:02X} stack: {}",
+        reason,
+        pc,
+        instruction,
+        regs[..8].join(" "),
+        regs[8..].join(" "),
+        emu.i_reg(),
+        emu.sp(),
+        emu.delay_timer(),
+        emu.sound_timer(),
+        if stack.is_empty() {
+            "empty".to_string()
+        } else {
+            stack.join(" ")
+        }
+    )
+}
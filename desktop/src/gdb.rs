@@ -0,0 +1,546 @@
+use chip8_core::{Access, Emu, StopReason, Watchpoint, NUM_REGS};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+//GDB remote serial protocol stub, polled once per frame from the game loop
+//
+//registers in target.xml order: pc, v0-vf, i, sp, dt, st
+//memory is the addressable RAM, breakpoints (Z0/Z1) and watchpoints (Z2-Z4) map onto the
+//core debugger, the emulator is paused while a debugger is attached and not continuing
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    //QStartNoAckMode was negotiated
+    no_ack: bool,
+    //a `c` is running, the stop reply is sent once the emulator stops
+    running: bool,
+}
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="0"/>
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+//register numbers as in TARGET_XML
+const REG_PC: usize = 0;
+const REG_V0: usize = 1;
+const REG_I: usize = REG_V0 + NUM_REGS;
+const REG_SP: usize = REG_I + 1;
+const REG_DT: usize = REG_SP + 1;
+const REG_ST: usize = REG_DT + 1;
+
+//largest packet body gdb may send or expect, in bytes
+const PACKET_SIZE: usize = 0x4000;
+
+//SIGTRAP for breakpoints and steps, SIGINT for ^C
+const SIGTRAP: u8 = 5;
+const SIGINT: u8 = 2;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+//registers are sent little endian
+fn read_register(emu: &Emu, reg: usize) -> Option<Vec<u8>> {
+    Some(match reg {
+        REG_PC => emu.pc().to_le_bytes().to_vec(),
+        REG_I => emu.i_reg().to_le_bytes().to_vec(),
+        REG_SP => vec![emu.sp() as u8],
+        REG_DT => vec![emu.delay_timer()],
+        REG_ST => vec![emu.sound_timer()],
+        _ if (REG_V0..REG_I).contains(&reg) => vec![emu.v_reg()[reg - REG_V0]],
+        _ => return None,
+    })
+}
+
+//false for unknown registers and sp, which only CALL and RET may change
+fn write_register(emu: &mut Emu, reg: usize, bytes: &[u8]) -> bool {
+    let word = || {
+        bytes
+            .get(..2)
+            .map(|word| u16::from_le_bytes([word[0], word[1]]))
+    };
+    match (reg, bytes.first()) {
+        (REG_PC, _) => word().map(|pc| emu.set_pc(pc)).is_some(),
+        (REG_I, _) => word().map(|i| emu.set_i_reg(i)).is_some(),
+        (REG_DT, Some(&val)) => {
+            emu.set_delay_timer(val);
+            true
+        }
+        (REG_ST, Some(&val)) => {
+            emu.set_sound_timer(val);
+            true
+        }
+        (_, Some(&val)) if (REG_V0..REG_I).contains(&reg) => {
+            emu.set_v_reg(reg - REG_V0, val);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn register_len(reg: usize) -> usize {
+    if reg == REG_PC || reg == REG_I {
+        2
+    } else {
+        1
+    }
+}
+
+//T packet describing why the emulator stopped
+fn stop_reply(emu: &Emu) -> String {
+    match emu.stop_reason() {
+        Some(StopReason::Paused) => format!("T{:02x}", SIGINT),
+        Some(StopReason::Breakpoint(_)) => format!("T{:02x}swbreak:;", SIGTRAP),
+        Some(StopReason::Watchpoint { addr, access, .. }) => {
+            let kind = match access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+        }
+        Some(StopReason::Step) | None => format!("T{:02x}", SIGTRAP),
+    }
+}
+
+impl GdbServer {
+    //listens on localhost only, the protocol has no authentication
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+        })
+    }
+
+    //accepts a debugger, handles its packets and reports stops, never blocks
+    pub fn poll(&mut self, emu: &mut Emu) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if stream.set_nonblocking(true).is_err() {
+                        return;
+                    }
+                    println!("Debugger attached from {}", addr);
+                    //gdb expects a stopped target when it attaches
+                    emu.pause();
+                    self.client = Some(Client {
+                        stream,
+                        buffer: Vec::new(),
+                        no_ack: false,
+                        running: false,
+                    });
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(err) => {
+                    eprintln!("Debugger connection failed: {}", err);
+                    return;
+                }
+            }
+        }
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        if let Err(err) = client.poll(emu) {
+            if err.kind() != ErrorKind::ConnectionAborted {
+                eprintln!("Debugger connection lost: {}", err);
+            }
+            println!("Debugger detached");
+            //nobody could resume the emulator otherwise
+            emu.resume();
+            self.client = None;
+        }
+    }
+}
+
+impl Client {
+    fn poll(&mut self, emu: &mut Emu) -> io::Result<()> {
+        let mut chunk = [0; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        while let Some(packet) = self.next_packet()? {
+            self.handle(&packet, emu)?;
+        }
+        if self.running && emu.is_stopped() {
+            self.running = false;
+            self.send(&stop_reply(emu))?;
+        }
+        Ok(())
+    }
+
+    //takes the next complete packet out of the buffer, acks and ^C are handled on the way
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.buffer.first() {
+                None => return Ok(None),
+                Some(b'+') | Some(b'-') => {
+                    self.buffer.remove(0);
+                }
+                //^C, gdb wants the emulator to stop
+                Some(0x03) => {
+                    self.buffer.remove(0);
+                    return Ok(Some("\x03".to_string()));
+                }
+                Some(b'$') => {
+                    let Some(end) = self.buffer.iter().position(|&b| b == b'#') else {
+                        return Ok(None);
+                    };
+                    if self.buffer.len() < end + 3 {
+                        return Ok(None);
+                    }
+                    let body = self.buffer[1..end].to_vec();
+                    let checksum = std::str::from_utf8(&self.buffer[end + 1..end + 3])
+                        .ok()
+                        .and_then(|text| u8::from_str_radix(text, 16).ok());
+                    self.buffer.drain(..end + 3);
+                    let sum = body.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+                    if !self.no_ack {
+                        let ack: &[u8] = if checksum == Some(sum) { b"+" } else { b"-" };
+                        self.write(ack)?;
+                    }
+                    if checksum == Some(sum) {
+                        return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+                    }
+                }
+                //line noise between packets
+                Some(_) => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        //replies are small, blocking for them keeps the frame loop simple
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(data);
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+
+    fn send(&mut self, body: &str) -> io::Result<()> {
+        let sum = body.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        self.write(format!("${}#{:02x}", body, sum).as_bytes())
+    }
+
+    fn handle(&mut self, packet: &str, emu: &mut Emu) -> io::Result<()> {
+        if packet == "\x03" {
+            emu.pause();
+            if self.running {
+                self.running = false;
+                return self.send(&stop_reply(emu));
+            }
+            return Ok(());
+        }
+        //an empty or unknown packet gets the empty reply, whatever a client sends
+        let mut chars = packet.chars();
+        let Some(command) = chars.next() else {
+            return self.send("");
+        };
+        let args = chars.as_str();
+        let reply = match command {
+            '?' => stop_reply(emu),
+            'g' => {
+                let mut bytes = Vec::new();
+                for reg in REG_PC..=REG_ST {
+                    bytes.extend(read_register(emu, reg).unwrap_or_default());
+                }
+                hex(&bytes)
+            }
+            'G' => {
+                let bytes = unhex(args).unwrap_or_default();
+                let mut offset = 0;
+                let mut ok = true;
+                for reg in REG_PC..=REG_ST {
+                    let len = register_len(reg);
+                    let Some(value) = bytes.get(offset..offset + len) else {
+                        ok = false;
+                        break;
+                    };
+                    //sp is read-only, writing back what gdb read is fine
+                    if reg != REG_SP {
+                        ok &= write_register(emu, reg, value);
+                    }
+                    offset += len;
+                }
+                if ok { "OK" } else { "E01" }.to_string()
+            }
+            'p' => match parse_hex(args).and_then(|reg| read_register(emu, reg)) {
+                Some(bytes) => hex(&bytes),
+                None => "E01".to_string(),
+            },
+            'P' => {
+                let written = args.split_once('=').is_some_and(|(reg, value)| {
+                    match (parse_hex(reg), unhex(value)) {
+                        (Some(reg), Some(bytes)) => write_register(emu, reg, &bytes),
+                        _ => false,
+                    }
+                });
+                if written { "OK" } else { "E01" }.to_string()
+            }
+            'm' => {
+                let range = args
+                    .split_once(',')
+                    .and_then(|(addr, len)| Some((parse_hex(addr)?, parse_hex(len)?)));
+                match range {
+                    Some((addr, len)) if addr < emu.memory().len() => {
+                        //a reply has to fit into the packet size announced in qSupported
+                        let len = len.min(PACKET_SIZE / 2);
+                        let end = addr.saturating_add(len).min(emu.memory().len());
+                        hex(&emu.memory()[addr..end])
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            'M' => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let addr = parse_hex(range.split_once(',')?.0)?;
+                    Some((addr, unhex(data)?))
+                });
+                match write.map(|(addr, data)| emu.write_memory(addr, &data)) {
+                    Some(Ok(())) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            }
+            'c' => {
+                if let Some(addr) = parse_hex(args).and_then(|addr| u16::try_from(addr).ok()) {
+                    emu.set_pc(addr);
+                }
+                emu.resume();
+                self.running = true;
+                return Ok(());
+            }
+            's' => {
+                if let Some(addr) = parse_hex(args).and_then(|addr| u16::try_from(addr).ok()) {
+                    emu.set_pc(addr);
+                }
+                if let Err(err) = emu.step_into() {
+                    eprintln!("Emulator error: {}", err);
+                }
+                stop_reply(emu)
+            }
+            'Z' | 'z' => self.breakpoint(command == 'Z', args, emu),
+            'D' => {
+                self.send("OK")?;
+                return Err(ErrorKind::ConnectionAborted.into());
+            }
+            'k' => return Err(ErrorKind::ConnectionAborted.into()),
+            'H' => "OK".to_string(),
+            'T' => "OK".to_string(),
+            'q' | 'Q' => self.query(packet),
+            //everything else is unsupported, gdb falls back to what it knows
+            _ => String::new(),
+        };
+        self.send(&reply)
+    }
+
+    //Z0/Z1 breakpoints, Z2 write, Z3 read and Z4 access watchpoints: type,addr,kind
+    fn breakpoint(&mut self, insert: bool, args: &str, emu: &mut Emu) -> String {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(addr), Some(len)) = (
+            fields.next(),
+            fields
+                .next()
+                .and_then(parse_hex)
+                .and_then(|addr| u16::try_from(addr).ok()),
+            fields.next().and_then(parse_hex),
+        ) else {
+            return "E01".to_string();
+        };
+        let (read, write) = match kind {
+            "0" | "1" => {
+                if insert {
+                    emu.add_breakpoint(addr);
+                } else {
+                    emu.remove_breakpoint(addr);
+                }
+                return "OK".to_string();
+            }
+            "2" => (false, true),
+            "3" => (true, false),
+            "4" => (true, true),
+            _ => return String::new(),
+        };
+        let len = u16::try_from(len).unwrap_or(u16::MAX).max(1);
+        let end = addr.saturating_add(len - 1);
+        let watchpoint = Watchpoint::new(addr..=end, read, write);
+        if insert {
+            emu.add_watchpoint(watchpoint);
+        } else {
+            emu.remove_watchpoint(&watchpoint);
+        }
+        "OK".to_string()
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+",
+                PACKET_SIZE
+            );
+        }
+        if packet == "QStartNoAckMode" {
+            //the OK itself is still acked
+            self.no_ack = true;
+            return "OK".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = range
+                .split_once(',')
+                .and_then(|(offset, len)| Some((parse_hex(offset)?, parse_hex(len)?)))
+            else {
+                return "E01".to_string();
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = (start + len).min(xml.len());
+            let more = if end < xml.len() { "m" } else { "l" };
+            return format!("{}{}", more, String::from_utf8_lossy(&xml[start..end]));
+        }
+        match packet {
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a client on one end of a loopback connection, the test reads replies from the other
+    fn connect() -> (Client, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let client = Client {
+            stream,
+            buffer: Vec::new(),
+            no_ack: true,
+            running: false,
+        };
+        (client, peer)
+    }
+
+    fn reply(peer: &mut TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut byte = [0];
+        while !data.ends_with(b"#") {
+            peer.read_exact(&mut byte).unwrap();
+            data.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        peer.read_exact(&mut checksum).unwrap();
+        assert_eq!(data[0], b'$');
+        String::from_utf8(data[1..data.len() - 1].to_vec()).unwrap()
+    }
+
+    fn exchange(packet: &str, emu: &mut Emu) -> String {
+        let (mut client, mut peer) = connect();
+        client.handle(packet, emu).unwrap();
+        reply(&mut peer)
+    }
+
+    #[test]
+    fn empty_and_unknown_packets_get_the_empty_reply() {
+        let mut emu = Emu::new();
+        assert_eq!(exchange("", &mut emu), "");
+        assert_eq!(exchange("é", &mut emu), "");
+        assert_eq!(exchange("Xfoo", &mut emu), "");
+    }
+
+    #[test]
+    fn malformed_memory_reads_are_errors() {
+        let mut emu = Emu::new();
+        assert_eq!(exchange("m", &mut emu), "E01");
+        assert_eq!(exchange("m200", &mut emu), "E01");
+        assert_eq!(exchange("mzz,2", &mut emu), "E01");
+        assert_eq!(exchange("mffffffffffffffff,1", &mut emu), "E01");
+        assert_eq!(exchange("m0,2", &mut emu), "f090");
+    }
+
+    #[test]
+    fn oversized_memory_reads_are_clamped() {
+        let mut emu = Emu::new();
+        let end = emu.memory().len();
+        let data = exchange("m1,ffffffffffffffff", &mut emu);
+        assert_eq!(data.len(), (end - 1) * 2);
+        let data = exchange(&format!("m{:x},ffff", end - 2), &mut emu);
+        assert_eq!(data.len(), 4);
+    }
+
+    #[test]
+    fn watchpoint_lengths_are_clamped() {
+        let mut emu = Emu::new();
+        assert_eq!(exchange("Z2,300,12345", &mut emu), "OK");
+        assert_eq!(emu.watchpoints()[0].range, 0x300..=0xFFFF);
+        assert_eq!(exchange("z2,300,12345", &mut emu), "OK");
+        assert!(emu.watchpoints().is_empty());
+        assert_eq!(exchange("Z3,300,0", &mut emu), "OK");
+        assert_eq!(emu.watchpoints()[0].range, 0x300..=0x300);
+    }
+
+    #[test]
+    fn malformed_watchpoints_are_errors() {
+        let mut emu = Emu::new();
+        assert_eq!(exchange("Z2,300", &mut emu), "E01");
+        assert_eq!(exchange("Z2", &mut emu), "E01");
+        assert_eq!(exchange("Z2,10000,1", &mut emu), "E01");
+        assert_eq!(exchange("Z9,300,1", &mut emu), "");
+        assert!(emu.watchpoints().is_empty());
+    }
+}
@@ -1,83 +1,353 @@
 use chip8_core::*;
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod audio;
+mod bindings;
+mod capture;
+mod cli;
+mod config;
+mod dap;
+mod debugger;
+mod gdb;
+mod palette;
+mod rebind;
+mod rewind;
+mod scheduler;
+mod slots;
+use audio::Beeper;
+use bindings::{Bindings, Input};
+use capture::GifRecorder;
+use cli::{AsmOptions, Command, DisasmOptions};
+use config::Config;
+use dap::{DapServer, Setup};
+use gdb::GdbServer;
+use palette::{Colors, Palettes};
+use rebind::Rebinder;
+use rewind::RewindBuffer;
+use scheduler::{Scheduler, FRAME_RATE};
+use slots::SaveSlots;
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
-const TICKS_PER_FRAME: usize = 10; //EXPERIMENTAL TICK RATE
-const TIMER_TICK_PER_NUMER_OF_FRAMES: usize = 2; //EXPERIMENTAL TIMER TICK RATE
-
-const SCALE: u32 = 15;
-const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
-const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
-
-fn key2btn(key: Keycode) -> Option<usize> {
-    match key {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q => Some(0x4),
-        Keycode::W => Some(0x5),
-        Keycode::E => Some(0x6),
-        Keycode::R => Some(0xD),
-        Keycode::A => Some(0x7),
-        Keycode::S => Some(0x8),
-        Keycode::D => Some(0x9),
-        Keycode::F => Some(0xE),
-        Keycode::Z => Some(0xA),
-        Keycode::X => Some(0x0),
-        Keycode::C => Some(0xB),
-        Keycode::V => Some(0xF),
-        _ => None,
-    }
-}
+//10 instructions per frame
+const DEFAULT_IPS: u32 = 600;
+
+//one minute of rewind history at 60 frames per second, a full state every second
+const REWIND_FRAMES: usize = 60 * 60;
+const REWIND_KEYFRAME_INTERVAL: usize = 60;
+
+//size of a hires pixel, lores pixels are twice as big
+const SCALE: u32 = 8;
+const WINDOW_WIDTH: u32 = (HIRES_SCREEN_WIDTH as u32) * SCALE;
+const WINDOW_HEIGHT: u32 = (HIRES_SCREEN_HEIGHT as u32) * SCALE;
 
-fn draw_screen(emu: &Emu, canvas: &mut Canvas<Window>) {
-    //clear to black
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+fn draw_screen(emu: &Emu, colors: &Colors, canvas: &mut Canvas<Window>) {
+    //clear to the background colour
+    canvas.set_draw_color(colors[0]);
     canvas.clear();
 
     let screen_buf = emu.get_display();
-    //set draw color to white and iterate through each point
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    let width = emu.screen_width();
+    //pixels grow as the resolution shrinks so the picture always fills the window
+    let scale = WINDOW_WIDTH / width as u32;
+    //iterate through each point, colour 0 is the background already drawn
     for (i, pixel) in screen_buf.iter().enumerate() {
-        if *pixel {
+        if *pixel != 0 {
+            canvas.set_draw_color(colors[*pixel as usize]);
             //1d array to 2d positions
-            let x = (i % SCREEN_WIDTH) as u32;
-            let y = (i / SCREEN_WIDTH) as u32;
-            let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
+            let x = (i % width) as u32;
+            let y = (i / width) as u32;
+            let rect = Rect::new((x * scale) as i32, (y * scale) as i32, scale, scale);
             canvas.fill_rect(rect).unwrap();
         }
     }
     canvas.present();
 }
 
-fn main() {
-    //argument handling
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: cargo run path/to/game");
+fn finish_gif(recorder: GifRecorder, path: &Path) {
+    match recorder.finish() {
+        Ok(frames) => println!("Recorded {} frames to {}", frames, path.display()),
+        Err(err) => eprintln!("Unable to write {}: {}", path.display(), err),
+    }
+}
+
+fn read_movie(path: &Path, rom: &[u8]) -> Result<Movie, String> {
+    let data =
+        fs::read(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+    let movie = Movie::from_bytes(&data).map_err(|err| format!("{}: {}", path.display(), err))?;
+    movie.check_rom(rom).map_err(|err| err.to_string())?;
+    Ok(movie)
+}
+
+//movies store whole instructions per frame, the command line makes sure ips fits
+fn timing(vip_timing: bool, ips: u32) -> Timing {
+    if vip_timing {
+        Timing::Vip
+    } else {
+        Timing::Instructions(ips / FRAME_RATE)
+    }
+}
+
+//takes over the settings the movie was recorded with, those given on the command line have
+//to be the same
+fn apply_movie(options: &mut cli::Options, movie: &Movie) -> Result<(), String> {
+    let timing = match (options.vip_timing, options.ips) {
+        (None, None) => None,
+        (vip_timing, ips) => Some(timing(
+            vip_timing.unwrap_or(false),
+            ips.unwrap_or(DEFAULT_IPS),
+        )),
+    };
+    movie
+        .check_settings(options.quirks, timing, options.load_addr)
+        .map_err(|err| err.to_string())?;
+    options.quirks = Some(movie.quirks);
+    options.load_addr = Some(movie.load_addr);
+    match movie.timing {
+        Timing::Vip => options.vip_timing = Some(true),
+        Timing::Instructions(count) => {
+            options.vip_timing = Some(false);
+            options.ips = Some(count.saturating_mul(FRAME_RATE));
+        }
+    }
+    Ok(())
+}
+
+//reads a ROM, Octo sources (.8o) are compiled for START_ADDR on the way
+//only compiled sources come with labels and line numbers for the debug adapter
+fn read_rom(path: &Path) -> Result<octo::Program, String> {
+    if path.extension().is_some_and(|ext| ext == "8o") {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        return octo::compile(&source).map_err(|err| format!("{}:{}", path.display(), err));
+    }
+    let rom =
+        fs::read(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+    Ok(octo::Program {
+        rom,
+        ..Default::default()
+    })
+}
+
+//disasm subcommand, prints the listing without opening a window
+fn disassemble(options: &DisasmOptions) {
+    let rom = match read_rom(&options.rom) {
+        Ok(program) => program.rom,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        }
+    };
+    let lines = disasm::disassemble(&rom, options.base, options.syntax);
+    if options.source {
+        print!("{}", disasm::to_source(&lines));
         return;
     }
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+//asm subcommand, writes the assembled ROM
+fn assemble(options: &AsmOptions) {
+    let source = match fs::read_to_string(&options.source) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Unable to read {}: {}", options.source.display(), err);
+            process::exit(1);
+        }
+    };
+    let rom = match asm::assemble(&source, options.base) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}:{}", options.source.display(), err);
+            process::exit(1);
+        }
+    };
+    if let Err(err) = fs::write(&options.output, &rom) {
+        eprintln!("Unable to write {}: {}", options.output.display(), err);
+        process::exit(1);
+    }
+}
 
-    let mut chip8 = Emu::new();
+fn main() {
+    //argument handling
+    let args: Vec<_> = env::args().skip(1).collect();
+    let mut options = match cli::parse(&args) {
+        Ok(Command::Run(options)) => *options,
+        Ok(Command::Disasm(options)) => {
+            disassemble(&options);
+            return;
+        }
+        Ok(Command::Asm(options)) => {
+            assemble(&options);
+            return;
+        }
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        }
+    };
+
+    //the command line wins over the config file
+    let mut config = Config::load(options.config.as_deref()).unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        process::exit(1);
+    });
+    let mut palettes = Palettes::new(&config).unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        process::exit(1);
+    });
+    let palette = match (&options.palette, config.get("palette")) {
+        (Some(name), _) => palettes.select(name),
+        (None, Some(entry)) => palettes
+            .select(&entry.value)
+            .map_err(|msg| config.error(entry.line, &msg)),
+        (None, None) => Ok(()),
+    };
+    if let Err(msg) = palette {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
 
     //File opening logic
-    let mut rom = File::open(&args[1]).expect("Unable to open file");
-    let mut buffer = Vec::new();
-    rom.read_to_end(&mut buffer).unwrap();
-    chip8.load(&buffer);
+    let program = match read_rom(&options.rom) {
+        Ok(program) => program,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        }
+    };
+    let buffer = program.rom.clone();
+
+    //a movie brings its own seed and settings, otherwise use the requested seed or the clock
+    let mut playback = match &options.play {
+        Some(path) => match read_movie(path, &buffer) {
+            Ok(movie) => Some(movie),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    if let Some(movie) = &playback {
+        if let Err(msg) = apply_movie(&mut options, movie) {
+            eprintln!("{}", msg);
+            process::exit(1);
+        }
+    }
+    let vip_timing = options.vip_timing.unwrap_or(false);
+    let ips = options.ips.unwrap_or(DEFAULT_IPS);
+    let load_addr = options.load_addr.unwrap_or(START_ADDR);
+
+    let mut chip8 = Emu::with_quirks(options.quirks.unwrap_or_default());
+    if let Err(err) = chip8.load_at(load_addr, &buffer) {
+        eprintln!("Unable to load {}: {}", options.rom.display(), err);
+        process::exit(1);
+    }
+    let (rng_kind, seed) = match &playback {
+        Some(movie) => (movie.rng_kind, movie.seed),
+        None => (
+            RngKind::Xorshift,
+            options.seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_nanos() as u64)
+                    .unwrap_or_default()
+            }),
+        ),
+    };
+    chip8.seed_rng(rng_kind, seed);
+    if vip_timing {
+        chip8.set_timing(Timing::Vip);
+    }
+    for (addr, condition) in &options.breakpoints {
+        match condition {
+            Some(condition) => chip8.add_conditional_breakpoint(*addr, condition.clone()),
+            None => chip8.add_breakpoint(*addr),
+        }
+    }
+    for watchpoint in &options.watchpoints {
+        chip8.add_watchpoint(watchpoint.clone());
+    }
+    if let Some(path) = &options.trace {
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("Unable to create {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        };
+        let mut tracer = Tracer::new(BufWriter::new(file));
+        for range in &options.trace_ranges {
+            tracer.add_range(range.clone());
+        }
+        if let Some(limit) = options.trace_limit {
+            tracer.set_limit(limit);
+        }
+        chip8.set_tracer(tracer);
+    }
+    let mut gdb_server = options.gdb.map(|port| match GdbServer::bind(port) {
+        Ok(server) => {
+            println!("Waiting for a debugger on localhost:{}", port);
+            server
+        }
+        Err(err) => {
+            eprintln!("Unable to listen on port {}: {}", port, err);
+            process::exit(1);
+        }
+    });
+    let mut dap_server = options.dap.map(|port| {
+        let setup = Setup {
+            load_addr,
+            quirks: chip8.quirks(),
+            rng_kind,
+            seed,
+        };
+        match DapServer::bind(port, &options.rom, program, setup) {
+            Ok(server) => {
+                println!("Waiting for a debug adapter client on localhost:{}", port);
+                server
+            }
+            Err(err) => {
+                eprintln!("Unable to listen on port {}: {}", port, err);
+                process::exit(1);
+            }
+        }
+    });
+    let mut recording = options.record.as_ref().map(|_| {
+        Movie::new(
+            rng_kind,
+            seed,
+            &buffer,
+            chip8.quirks(),
+            timing(vip_timing, ips),
+            load_addr,
+        )
+    });
+    //frames run so far, the index into the movie being played or recorded
+    let mut movie_frame = 0;
 
     // Setup SDL
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    //no sound is better than no game when there is no audio device
+    let mut beeper = sdl_context
+        .audio()
+        .and_then(|audio| Beeper::open(&audio, options.tone, options.waveform, options.volume))
+        .map_err(|err| eprintln!("Sound disabled: {}", err))
+        .ok();
     let window = video_subsystem
         .window("Chip8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
         .position_centered()
@@ -88,40 +358,376 @@ fn main() {
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
+    //controllers are opened as SDL reports them, those plugged in at start included
+    let controller_subsystem = sdl_context
+        .game_controller()
+        .map_err(|err| eprintln!("Controllers disabled: {}", err))
+        .ok();
+    let mut controllers = Vec::new();
+    let mut bindings = Bindings::new(&config, &options.rom).unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        process::exit(1);
+    });
+    let mut rebinder: Option<Rebinder> = None;
+
+    let mut save_slots = SaveSlots::new(&options.rom);
+    let mut rewind = RewindBuffer::new(REWIND_FRAMES, REWIND_KEYFRAME_INTERVAL);
+    let mut rewinding = false;
+
+    let mut gif: Option<(GifRecorder, PathBuf)> = None;
 
-    let mut frame_count = 0;
+    let mut scheduler = Scheduler::new(ips);
+    let mut exit_code = 0;
     'gameloop: loop {
-        frame_count += 1;
+        if let Some(server) = gdb_server.as_mut() {
+            server.poll(&mut chip8);
+        }
+        if let Some(server) = dap_server.as_mut() {
+            //history of the previous program must not be rewound into
+            if server.poll(&mut chip8) {
+                rewind = RewindBuffer::new(REWIND_FRAMES, REWIND_KEYFRAME_INTERVAL);
+            }
+        }
         for evt in event_pump.poll_iter() {
+            //the rebinding screen takes every key and button until it is done
+            if let Some(screen) = rebinder.as_mut() {
+                let done = match evt {
+                    Event::Quit { .. } => break 'gameloop,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => true,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Tab),
+                        ..
+                    } => screen.skip(),
+                    //hotkeys keep their jobs, a hex key bound to one would never be pressed
+                    Event::KeyDown {
+                        keycode:
+                            Some(
+                                Keycode::F1
+                                | Keycode::F2
+                                | Keycode::F3
+                                | Keycode::F4
+                                | Keycode::F5
+                                | Keycode::F6
+                                | Keycode::F7
+                                | Keycode::F8
+                                | Keycode::F9
+                                | Keycode::F10
+                                | Keycode::F11
+                                | Keycode::F12
+                                | Keycode::Backspace,
+                            ),
+                        ..
+                    } => continue,
+                    Event::KeyDown {
+                        keycode: Some(key),
+                        repeat: false,
+                        ..
+                    } => screen.assign(Input::Key(key)),
+                    Event::ControllerButtonDown { button, .. } => {
+                        screen.assign(Input::Button(button))
+                    }
+                    _ => continue,
+                };
+                if !done {
+                    println!("{}", screen.prompt());
+                    continue;
+                }
+                if let Some(screen) = rebinder.take() {
+                    bindings = screen.finish();
+                    match bindings.save(&mut config, &options.rom) {
+                        Ok(path) => println!("Saved key bindings to {}", path.display()),
+                        Err(msg) => eprintln!("Unable to save key bindings: {}", msg),
+                    }
+                }
+                continue;
+            }
             match evt {
                 Event::Quit { .. } => {
                     break 'gameloop;
                 }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.as_ref().map(|sub| sub.open(which)) {
+                        Some(Ok(controller)) => {
+                            println!("Controller connected: {}", controller.name());
+                            controllers.push(controller);
+                        }
+                        Some(Err(err)) => eprintln!("Unable to open controller: {}", err),
+                        None => (),
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers.retain(|controller| controller.instance_id() != which);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => {
+                    //nothing stays held down behind the screen
+                    if playback.is_none() {
+                        chip8.set_key_state(0);
+                    }
+                    rewinding = false;
+                    let screen = Rebinder::new(&bindings);
+                    println!("{}", screen.prompt());
+                    rebinder = Some(screen);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    palettes.next();
+                    println!("Palette {}", palettes.name());
+                }
+                //quick-save, quick-load and slot selection hotkeys
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => match save_slots.save(&chip8) {
+                    Ok(path) => println!("Saved state to {}", path.display()),
+                    Err(err) => eprintln!("Unable to save state: {}", err),
+                },
+                //a loaded state would desync the movie being played or recorded
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } if playback.is_some() || recording.is_some() => {
+                    eprintln!("Quick-load is disabled while a movie is active");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => match save_slots.load(&mut chip8) {
+                    Ok(path) => println!("Loaded state from {}", path.display()),
+                    Err(err) => eprintln!("Unable to load state: {}", err),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    save_slots.next_slot();
+                    println!("Save slot {}", save_slots.current());
+                }
+                //screenshots and GIF clips go next to the ROM like the save slots
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    let path = capture::next_path(&options.rom, "shot", "png");
+                    match capture::screenshot(&path, &chip8, palettes.colors()) {
+                        Ok(()) => println!("Saved screenshot to {}", path.display()),
+                        Err(err) => eprintln!("Unable to write {}: {}", path.display(), err),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => match gif.take() {
+                    Some((recorder, path)) => finish_gif(recorder, &path),
+                    None => {
+                        let path = capture::next_path(&options.rom, "clip", "gif");
+                        match GifRecorder::create(&path, palettes.colors()) {
+                            Ok(recorder) => {
+                                println!("Recording {}", path.display());
+                                gif = Some((recorder, path));
+                            }
+                            Err(err) => eprintln!("Unable to write {}: {}", path.display(), err),
+                        }
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    if let Some(beeper) = beeper.as_mut() {
+                        let muted = beeper.toggle_mute();
+                        println!("Sound {}", if muted { "muted" } else { "on" });
+                    }
+                }
+                //debugger: F8 pauses and resumes, F10/F11/F12 step over, into and out
+                //stepping would desync a movie just like loading a state
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8 | Keycode::F10 | Keycode::F11 | Keycode::F12),
+                    ..
+                } if playback.is_some() || recording.is_some() => {
+                    eprintln!("The debugger is disabled while a movie is active");
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    if chip8.is_stopped() {
+                        chip8.resume();
+                        println!("Running");
+                    } else {
+                        chip8.pause();
+                        println!("{}", debugger::describe(&chip8));
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(key @ (Keycode::F10 | Keycode::F11 | Keycode::F12)),
+                    ..
+                } if chip8.is_stopped() => {
+                    let result = match key {
+                        Keycode::F10 => chip8.step_over(),
+                        Keycode::F11 => chip8.step_into(),
+                        _ => {
+                            chip8.step_out();
+                            Ok(())
+                        }
+                    };
+                    if let Err(err) = result {
+                        eprintln!("Emulator error: {}", err);
+                        exit_code = 1;
+                        break 'gameloop;
+                    }
+                    //step over and out keep running until the call returns
+                    if chip8.is_stopped() {
+                        println!("{}", debugger::describe(&chip8));
+                    }
+                }
+                //rewind for as long as the key is held
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                //keys come from the movie during playback
+                Event::KeyDown { .. }
+                | Event::KeyUp { .. }
+                | Event::ControllerButtonDown { .. }
+                | Event::ControllerButtonUp { .. }
+                    if playback.is_some() => {}
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
-                    if let Some(btn) = key2btn(key) {
+                    if let Some(btn) = bindings.lookup(Input::Key(key)) {
                         chip8.keypress(btn, true);
                     }
-                },
+                }
                 Event::KeyUp {
                     keycode: Some(key), ..
                 } => {
-                    if let Some(btn) = key2btn(key) {
+                    if let Some(btn) = bindings.lookup(Input::Key(key)) {
                         chip8.keypress(btn, false);
                     }
-                },
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(btn) = bindings.lookup(Input::Button(button)) {
+                        chip8.keypress(btn, true);
+                    }
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    if let Some(btn) = bindings.lookup(Input::Button(button)) {
+                        chip8.keypress(btn, false);
+                    }
+                }
                 _ => (),
             }
         }
 
-        for _ in 0..TICKS_PER_FRAME {
-            chip8.tick();
+        //the timers and the CPU run at a fixed rate per emulated frame, not per displayed one
+        let frames = scheduler.due_frames();
+        for _ in 0..frames {
+            //the machine waits while the keys are being rebound
+            if rebinder.is_some() {
+                continue;
+            }
+            if rewinding {
+                //step back one frame per emulated frame
+                if let Some(state) = rewind.pop() {
+                    //the keys held now stay held, not the ones held back then
+                    let keys = chip8.key_state();
+                    chip8.load_state(&state);
+                    chip8.set_key_state(keys);
+                    //keep the movie in step with the rewound machine
+                    movie_frame -= 1;
+                    if let Some(movie) = recording.as_mut() {
+                        movie.truncate(movie_frame);
+                    }
+                }
+                continue;
+            }
+            if chip8.is_stopped() {
+                continue;
+            }
+            if let Some(movie) = &playback {
+                match movie.frame(movie_frame) {
+                    Some(keys) => chip8.set_key_state(keys),
+                    None => {
+                        println!("Movie finished after {} frames", movie.len());
+                        chip8.set_key_state(0);
+                        playback = None;
+                    }
+                }
+            }
+            if let Some(movie) = recording.as_mut() {
+                movie.record_frame(chip8.key_state());
+            }
+            movie_frame += 1;
+
+            //the VIP model decides itself how much fits into a frame
+            let result = if vip_timing {
+                chip8.run_frame()
+            } else {
+                (0..scheduler.instructions())
+                    .try_for_each(|_| chip8.tick())
+                    .map(|()| chip8.tick_timers())
+            };
+            if let Err(err) = result {
+                eprintln!("Emulator error: {}", err);
+                exit_code = 1;
+                break 'gameloop;
+            }
+            rewind.push(&chip8);
+            //a breakpoint or the end of a step over/out was hit during the frame
+            if chip8.is_stopped() {
+                println!("{}", debugger::describe(&chip8));
+            }
+            if chip8.has_exited() {
+                break;
+            }
+        }
+        //the sound timer does not count down while the debugger holds the machine
+        if let Some(beeper) = beeper.as_mut() {
+            let running = !chip8.is_stopped() && !rewinding && rebinder.is_none();
+            beeper.update(chip8.sound_active() && running);
+        }
+        match &rebinder {
+            Some(screen) => screen.draw(palettes.colors(), &mut canvas),
+            None => draw_screen(&chip8, palettes.colors(), &mut canvas),
         }
-        chip8.tick_timers();
-        if frame_count % TIMER_TICK_PER_NUMER_OF_FRAMES == 0 {
-            chip8.tick_timers();
+        //the clip shows what the window showed, pauses and rewinds included
+        if let Some((recorder, path)) = gif.as_mut() {
+            if let Err(err) = recorder.push(&chip8, palettes.colors(), frames as u64) {
+                eprintln!("Unable to write {}: {}", path.display(), err);
+                gif = None;
+            }
+        }
+        if chip8.has_exited() {
+            break 'gameloop;
+        }
+        thread::sleep(scheduler.until_next_frame());
+    }
+
+    if let Some((recorder, path)) = gif {
+        finish_gif(recorder, &path);
+    }
+    if let (Some(movie), Some(path)) = (&recording, &options.record) {
+        match fs::write(path, movie.to_bytes()) {
+            Ok(()) => println!("Recorded {} frames to {}", movie.len(), path.display()),
+            Err(err) => eprintln!("Unable to write {}: {}", path.display(), err),
+        }
+    }
+    if let (Some(tracer), Some(path)) = (chip8.take_tracer(), &options.trace) {
+        if let Err(err) = tracer.finish() {
+            eprintln!("Unable to write {}: {}", path.display(), err);
         }
-        draw_screen(&chip8, &mut canvas);
     }
+    process::exit(exit_code);
 }
@@ -0,0 +1,226 @@
+use crate::config::Config;
+use sdl2::pixels::Color;
+
+//background, plane 1, XO-CHIP plane 2 and both planes overlapping
+pub type Colors = [Color; 4];
+
+const fn rgb(hex: u32) -> Color {
+    Color::RGB((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+}
+
+//single plane programs only ever show the first two colours
+const BUILTIN: [(&str, Colors); 7] = [
+    (
+        "classic",
+        [rgb(0x000000), rgb(0xFFFFFF), rgb(0xAAAAAA), rgb(0x555555)],
+    ),
+    (
+        "amber",
+        [rgb(0x1A0F00), rgb(0xFFB000), rgb(0x9C5A00), rgb(0xFFE0A0)],
+    ),
+    (
+        "green",
+        [rgb(0x041A04), rgb(0x33FF33), rgb(0x168C16), rgb(0xB8FFB8)],
+    ),
+    //the four shades of a Game Boy screen, dark pixels on a light background
+    (
+        "lcd",
+        [rgb(0x9BBC0F), rgb(0x0F380F), rgb(0x8BAC0F), rgb(0x306230)],
+    ),
+    //Octo's default XO-CHIP colours
+    (
+        "octo",
+        [rgb(0x996600), rgb(0xFFCC00), rgb(0xFF6600), rgb(0x662200)],
+    ),
+    (
+        "high-contrast",
+        [rgb(0x000000), rgb(0xFFFFFF), rgb(0xFFFF00), rgb(0x00FFFF)],
+    ),
+    //Okabe-Ito colours, told apart with any kind of colour blindness
+    (
+        "colorblind",
+        [rgb(0x000000), rgb(0xFFFFFF), rgb(0xE69F00), rgb(0x56B4E9)],
+    ),
+];
+
+//names of the colours in a [palette name] section of the config file
+const KEYS: [&str; 4] = ["background", "plane1", "plane2", "both"];
+
+//the built in palettes followed by the ones from the config file, F4 cycles through them
+pub struct Palettes {
+    list: Vec<(String, Colors)>,
+    current: usize,
+}
+
+impl Palettes {
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let mut list: Vec<_> = BUILTIN
+            .iter()
+            .map(|(name, colors)| (name.to_string(), *colors))
+            .collect();
+        for (name, section) in config.sections_of("palette") {
+            //a section named like an existing palette changes it, a new one starts out classic
+            let index = match list.iter().position(|(existing, _)| existing == name) {
+                Some(index) => index,
+                None => {
+                    list.push((name.to_string(), BUILTIN[0].1));
+                    list.len() - 1
+                }
+            };
+            for entry in &section.entries {
+                let key = KEYS
+                    .iter()
+                    .position(|&key| key == entry.key)
+                    .ok_or_else(|| {
+                        config.error(entry.line, "expected background, plane1, plane2 or both")
+                    })?;
+                list[index].1[key] = parse_color(&entry.value)
+                    .ok_or_else(|| config.error(entry.line, "expected a colour like #FFB000"))?;
+            }
+        }
+        Ok(Self { list, current: 0 })
+    }
+
+    pub fn select(&mut self, name: &str) -> Result<(), String> {
+        self.current = self
+            .list
+            .iter()
+            .position(|(existing, _)| existing == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = self.list.iter().map(|(name, _)| name.as_str()).collect();
+                format!("Unknown palette: {} (try {})", name, names.join(", "))
+            })?;
+        Ok(())
+    }
+
+    //switches to the next palette, wrapping after the last one
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.list.len();
+    }
+
+    pub fn name(&self) -> &str {
+        &self.list[self.current].0
+    }
+
+    pub fn colors(&self) -> &Colors {
+        &self.list[self.current].1
+    }
+}
+
+//#RRGGBB, the # is optional
+fn parse_color(text: &str) -> Option<Color> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn palettes(name: &str, text: &str) -> Result<Palettes, String> {
+        let path =
+            std::env::temp_dir().join(format!("chip8-palette-{}-{}.ini", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        let config = Config::load(Some(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        Palettes::new(&config).map_err(|err| error_at(&path, err))
+    }
+
+    //the error without the temp file's name
+    fn error_at(path: &Path, err: String) -> String {
+        err.strip_prefix(&path.display().to_string())
+            .unwrap_or(&err)
+            .to_string()
+    }
+
+    #[test]
+    fn builtin_palettes_can_be_selected_and_cycled() {
+        let mut palettes = palettes("builtin", "").unwrap();
+        assert_eq!(palettes.name(), "classic");
+        palettes.select("amber").unwrap();
+        assert_eq!(palettes.colors()[1], Color::RGB(0xFF, 0xB0, 0x00));
+        palettes.next();
+        assert_eq!(palettes.name(), "green");
+        palettes.select("colorblind").unwrap();
+        palettes.next();
+        assert_eq!(palettes.name(), "classic");
+        assert_eq!(
+            palettes.select("sepia"),
+            Err("Unknown palette: sepia (try classic, amber, green, lcd, octo, high-contrast, colorblind)"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn config_sections_add_and_change_palettes() {
+        let mut palettes = palettes(
+            "sections",
+            "[palette mine]\n\
+             background = #202020\n\
+             plane1 = ffb000\n\
+             \n\
+             [palette amber]\n\
+             both = #000000\n",
+        )
+        .unwrap();
+        palettes.select("mine").unwrap();
+        //colours that are not given start out classic
+        assert_eq!(
+            *palettes.colors(),
+            [
+                Color::RGB(0x20, 0x20, 0x20),
+                Color::RGB(0xFF, 0xB0, 0x00),
+                BUILTIN[0].1[2],
+                BUILTIN[0].1[3],
+            ]
+        );
+        //new palettes come after the built in ones
+        palettes.next();
+        assert_eq!(palettes.name(), "classic");
+        palettes.select("amber").unwrap();
+        assert_eq!(palettes.colors()[3], Color::RGB(0, 0, 0));
+        assert_eq!(palettes.colors()[1], Color::RGB(0xFF, 0xB0, 0x00));
+    }
+
+    #[test]
+    fn bad_entries_point_at_the_line() {
+        let cases = [
+            (
+                "[palette x]\nforeground = #FFFFFF\n",
+                ":2: expected background, plane1, plane2 or both",
+            ),
+            (
+                "[palette x]\n\nplane1 = #FFF\n",
+                ":3: expected a colour like #FFB000",
+            ),
+            (
+                "[palette x]\nplane2 = #GGGGGG\n",
+                ":2: expected a colour like #FFB000",
+            ),
+        ];
+        for (idx, (text, message)) in cases.into_iter().enumerate() {
+            assert_eq!(
+                palettes(&format!("bad{}", idx), text).err().as_deref(),
+                Some(message),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn colours_are_six_hex_digits() {
+        assert_eq!(parse_color("#FFB000"), Some(Color::RGB(0xFF, 0xB0, 0x00)));
+        assert_eq!(parse_color("0f380f"), Some(Color::RGB(0x0F, 0x38, 0x0F)));
+        for bad in [
+            "", "#", "#FFB00", "#FFB0000", "##FFB000", "+FFB00", "#FF B00",
+        ] {
+            assert_eq!(parse_color(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::bindings::{Bindings, Input, KEYPAD};
+use crate::palette::Colors;
+use chip8_core::FONTSET;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+//size of a font pixel on the keypad, a digit is 4x5 of them
+const DIGIT_SCALE: u32 = 16;
+
+//in-app rebinding screen: walks through the keypad and binds the next key or button pressed to
+//each hex key in turn, keyboard keys replace keyboard keys and buttons replace buttons
+pub struct Rebinder {
+    bindings: Bindings,
+    position: usize,
+}
+
+impl Rebinder {
+    pub fn new(bindings: &Bindings) -> Self {
+        Self {
+            bindings: bindings.clone(),
+            position: 0,
+        }
+    }
+
+    //the hex key being asked for
+    pub fn current(&self) -> usize {
+        KEYPAD[self.position]
+    }
+
+    pub fn prompt(&self) -> String {
+        format!(
+            "Press a key or button for {:X} (now {}), Tab keeps it, Esc stops",
+            self.current(),
+            self.bindings.describe(self.current())
+        )
+    }
+
+    //returns whether every hex key has been asked for
+    pub fn assign(&mut self, input: Input) -> bool {
+        let btn = self.current();
+        self.bindings.unbind_like(input, btn);
+        self.bindings.bind(input, btn);
+        self.skip()
+    }
+
+    pub fn skip(&mut self) -> bool {
+        self.position += 1;
+        self.position == KEYPAD.len()
+    }
+
+    pub fn finish(self) -> Bindings {
+        self.bindings
+    }
+
+    //the keypad as a 4x4 grid of digits, the one being asked for highlighted
+    pub fn draw(&self, colors: &Colors, canvas: &mut Canvas<Window>) {
+        canvas.set_draw_color(colors[0]);
+        canvas.clear();
+        let (width, height) = canvas.output_size().unwrap_or_default();
+        let (cell_width, cell_height) = (width / 4, height / 4);
+        for (position, &btn) in KEYPAD.iter().enumerate() {
+            let x = (position % 4) as u32 * cell_width;
+            let y = (position / 4) as u32 * cell_height;
+            let ink = if position == self.position {
+                canvas.set_draw_color(colors[1]);
+                canvas
+                    .fill_rect(Rect::new(x as i32, y as i32, cell_width, cell_height))
+                    .unwrap();
+                colors[0]
+            } else {
+                colors[1]
+            };
+            canvas.set_draw_color(ink);
+            //centre the digit in its cell
+            let left = x + cell_width.saturating_sub(4 * DIGIT_SCALE) / 2;
+            let top = y + cell_height.saturating_sub(5 * DIGIT_SCALE) / 2;
+            for (row, bits) in FONTSET[btn * 5..][..5].iter().enumerate() {
+                for col in 0..4 {
+                    if bits & (0x80 >> col) != 0 {
+                        let rect = Rect::new(
+                            (left + col * DIGIT_SCALE) as i32,
+                            (top + row as u32 * DIGIT_SCALE) as i32,
+                            DIGIT_SCALE,
+                            DIGIT_SCALE,
+                        );
+                        canvas.fill_rect(rect).unwrap();
+                    }
+                }
+            }
+        }
+        canvas.present();
+    }
+}
@@ -0,0 +1,216 @@
+use chip8_core::{Emu, Snapshot};
+use std::collections::VecDeque;
+
+//a keyframe followed by the frames encoded against it
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+//ring buffer of per-frame save states for rewinding
+//every keyframe_interval frames a full snapshot is kept, the frames in between only
+//store the bytes that differ from that keyframe, so a minute of history stays small
+pub struct RewindBuffer {
+    groups: VecDeque<Group>,
+    capacity: usize,
+    keyframe_interval: usize,
+    len: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize, keyframe_interval: usize) -> Self {
+        Self {
+            groups: VecDeque::new(),
+            capacity,
+            keyframe_interval,
+            len: 0,
+        }
+    }
+
+    //records the state at the end of a frame
+    pub fn push(&mut self, emu: &Emu) {
+        let state = emu.save_state().to_bytes();
+        match self.groups.back_mut() {
+            Some(group)
+                if group.deltas.len() + 1 < self.keyframe_interval
+                    && group.keyframe.len() == state.len() =>
+            {
+                group.deltas.push(encode_delta(&group.keyframe, &state));
+            }
+            _ => self.groups.push_back(Group {
+                keyframe: state,
+                deltas: Vec::new(),
+            }),
+        }
+        self.len += 1;
+
+        //drop whole groups, their deltas are useless without the keyframe
+        while self.len > self.capacity {
+            match self.groups.pop_front() {
+                Some(group) => self.len -= group.deltas.len() + 1,
+                None => break,
+            }
+        }
+    }
+
+    //steps back one frame: drops the newest state, the one the machine is in since it was
+    //pushed at the end of the last frame, and returns the one before it
+    //None once history runs out
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        let group = self.groups.back_mut()?;
+        if group.deltas.pop().is_none() {
+            self.groups.pop_back();
+        }
+        self.len -= 1;
+
+        let group = self.groups.back()?;
+        let state = match group.deltas.last() {
+            Some(delta) => decode_delta(&group.keyframe, delta),
+            None => group.keyframe.clone(),
+        };
+        Snapshot::from_bytes(&state).ok()
+    }
+}
+
+//XORs the state against the keyframe and run-length encodes the result as
+//(unchanged bytes, changed bytes, changed byte values...) records
+fn encode_delta(keyframe: &[u8], state: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < state.len() {
+        let same_start = pos;
+        while pos < state.len() && state[pos] == keyframe[pos] {
+            pos += 1;
+        }
+        let diff_start = pos;
+        while pos < state.len() && state[pos] != keyframe[pos] {
+            pos += 1;
+        }
+        push_varint(&mut out, diff_start - same_start);
+        push_varint(&mut out, pos - diff_start);
+        out.extend(
+            state[diff_start..pos]
+                .iter()
+                .zip(&keyframe[diff_start..pos])
+                .map(|(new, old)| new ^ old),
+        );
+    }
+    out
+}
+
+fn decode_delta(keyframe: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut state = keyframe.to_vec();
+    let mut pos = 0;
+    let mut read = 0;
+    while read < delta.len() {
+        pos += read_varint(delta, &mut read);
+        let changed = read_varint(delta, &mut read);
+        for byte in &delta[read..read + changed] {
+            state[pos] ^= byte;
+            pos += 1;
+        }
+        read += changed;
+    }
+    state
+}
+
+//LEB128, runs are usually tiny so most lengths take a single byte
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //V0 counts the frames, each one runs ADD V0, 1 and JP 0x200
+    fn emu() -> Emu {
+        let mut emu = Emu::new();
+        emu.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        emu
+    }
+
+    fn run_frame(emu: &mut Emu, rewind: &mut RewindBuffer) {
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        rewind.push(emu);
+    }
+
+    //V0 of every state popped until history runs out
+    fn pop_all(rewind: &mut RewindBuffer) -> Vec<u8> {
+        let mut emu = emu();
+        let mut frames = Vec::new();
+        while let Some(state) = rewind.pop() {
+            emu.load_state(&state);
+            frames.push(emu.v_reg()[0]);
+        }
+        frames
+    }
+
+    #[test]
+    fn pop_steps_back_across_groups() {
+        let mut emu = emu();
+        let mut rewind = RewindBuffer::new(100, 4);
+        for _ in 0..10 {
+            run_frame(&mut emu, &mut rewind);
+        }
+        //the machine is at frame 10, the first step goes back to frame 9
+        assert_eq!(pop_all(&mut rewind), [9, 8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(rewind.len, 0);
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn running_on_after_a_rewind() {
+        let mut emu = emu();
+        let mut rewind = RewindBuffer::new(100, 4);
+        for _ in 0..6 {
+            run_frame(&mut emu, &mut rewind);
+        }
+        for _ in 0..3 {
+            let state = rewind.pop().unwrap();
+            emu.load_state(&state);
+        }
+        assert_eq!(emu.v_reg()[0], 3);
+        //frames 4 and 5 again, into what is left of the group of 1 to 4 and a new one
+        run_frame(&mut emu, &mut rewind);
+        run_frame(&mut emu, &mut rewind);
+        assert_eq!(pop_all(&mut rewind), [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn capacity_evicts_whole_groups() {
+        let mut emu = emu();
+        let mut rewind = RewindBuffer::new(10, 4);
+        for _ in 0..20 {
+            run_frame(&mut emu, &mut rewind);
+            assert!(rewind.len <= 10);
+        }
+        //1 to 12 went in groups of 4, the group of 13 to 16 is the oldest left
+        assert_eq!(rewind.len, 8);
+        assert_eq!(rewind.groups.len(), 2);
+        assert_eq!(pop_all(&mut rewind), [19, 18, 17, 16, 15, 14, 13]);
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+//emulated frames per second, the rate of the timers whatever the monitor does
+pub const FRAME_RATE: u32 = 60;
+//frames run back to back when the host falls behind, after that the backlog is dropped
+//so the game slows down for a moment instead of never catching up
+const MAX_CATCH_UP: u32 = 4;
+
+//fixed timestep clock for the game loop
+//
+//every loop iteration asks how many 60 Hz frames are due, runs them and draws once, so a fast
+//monitor draws the same frame more than once and a slow host skips drawing frames
+pub struct Scheduler {
+    frame: Duration,
+    last: Instant,
+    //wall clock time not yet turned into frames
+    lag: Duration,
+    //CPU speed in instructions per second
+    ips: u32,
+    //instructions per second not yet handed out, spreads ips evenly over the frames
+    carry: u32,
+}
+
+impl Scheduler {
+    pub fn new(ips: u32) -> Self {
+        Self {
+            frame: Duration::from_secs(1) / FRAME_RATE,
+            last: Instant::now(),
+            lag: Duration::ZERO,
+            ips,
+            carry: 0,
+        }
+    }
+
+    //frames that are due since the last call
+    pub fn due_frames(&mut self) -> u32 {
+        let now = Instant::now();
+        self.lag += now - self.last;
+        self.last = now;
+        let mut frames = 0;
+        while self.lag >= self.frame && frames < MAX_CATCH_UP {
+            self.lag -= self.frame;
+            frames += 1;
+        }
+        if self.lag >= self.frame {
+            self.lag = Duration::ZERO;
+        }
+        frames
+    }
+
+    //time left until the next frame is due
+    pub fn until_next_frame(&self) -> Duration {
+        self.frame.saturating_sub(self.lag + self.last.elapsed())
+    }
+
+    //instructions to run in the next frame, e.g. 700 IPS gives 11 or 12
+    pub fn instructions(&mut self) -> u32 {
+        self.carry += self.ips;
+        let count = self.carry / FRAME_RATE;
+        self.carry %= FRAME_RATE;
+        count
+    }
+}
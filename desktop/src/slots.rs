@@ -0,0 +1,49 @@
+use chip8_core::{Emu, Snapshot};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NUM_SLOTS: usize = 10;
+
+//quick-save slots, stored next to the ROM as game.ch8.state0 .. game.ch8.state9
+pub struct SaveSlots {
+    rom_path: PathBuf,
+    current: usize,
+}
+
+impl SaveSlots {
+    pub fn new(rom_path: &Path) -> Self {
+        Self {
+            rom_path: rom_path.to_path_buf(),
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    //cycles to the next slot, wrapping after the last one
+    pub fn next_slot(&mut self) {
+        self.current = (self.current + 1) % NUM_SLOTS;
+    }
+
+    fn path(&self) -> PathBuf {
+        let mut name = self.rom_path.as_os_str().to_owned();
+        name.push(format!(".state{}", self.current));
+        PathBuf::from(name)
+    }
+
+    pub fn save(&self, emu: &Emu) -> Result<PathBuf, Box<dyn Error>> {
+        let path = self.path();
+        fs::write(&path, emu.save_state().to_bytes())?;
+        Ok(path)
+    }
+
+    pub fn load(&self, emu: &mut Emu) -> Result<PathBuf, Box<dyn Error>> {
+        let path = self.path();
+        let snapshot = Snapshot::from_bytes(&fs::read(&path)?)?;
+        emu.load_state(&snapshot);
+        Ok(path)
+    }
+}
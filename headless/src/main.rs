@@ -0,0 +1,466 @@
+use chip8_core::*;
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::process;
+
+mod script;
+mod wav;
+use script::InputScript;
+
+const USAGE: &str = "Usage: headless [options] path/to/game
+
+Runs a ROM without a window for a number of frames, then prints the screen
+and the final register state. Exits with 1 on emulator errors.
+
+Options:
+  --frames N         frames to run at 60 per second (default 600)
+  --ticks N          instructions per frame (default 10)
+  --timing MODEL     fixed (--ticks per frame) or vip for COSMAC VIP cycle costs
+  --profile NAME     quirks: default, vip, chip48, schip or xochip
+  --seed N           seed for the random number generator (default 0)
+  --input FILE       scripted key presses, lines of `<frame> <key> down|up`
+  --movie FILE       key presses from a movie recorded by the desktop frontend,
+                     played with the quirks and timing it was recorded with
+  --format FORMAT    screen dump format: ascii or pbm (default ascii)
+  --output FILE      write the screen dump to FILE instead of stdout
+  --trace FILE       log every instruction with the registers before it runs
+  --trace-range A-B  only log instructions between A and B, repeatable
+  --trace-limit N    stop logging after N lines
+  --wav FILE         write the buzzer's sound to a 16 bit mono WAV file
+  --sample-rate HZ   sample rate of the WAV file (default 44100)";
+
+const DEFAULT_FRAMES: usize = 600;
+const DEFAULT_TICKS_PER_FRAME: u32 = 10;
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    Pbm,
+}
+
+struct Options {
+    rom: String,
+    frames: usize,
+    //None when not given, a movie then brings its own
+    timing: Option<Timing>,
+    quirks: Option<Quirks>,
+    seed: u64,
+    input: Option<String>,
+    movie: Option<String>,
+    format: Format,
+    output: Option<String>,
+    trace: Option<String>,
+    trace_ranges: Vec<RangeInclusive<u16>>,
+    trace_limit: Option<usize>,
+    wav: Option<String>,
+    sample_rate: u32,
+}
+
+fn parse_number(arg: &str) -> Option<u64> {
+    match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
+//A-B or a single address
+fn parse_range(arg: &str) -> Option<RangeInclusive<u16>> {
+    let addr = |text: &str| parse_number(text).and_then(|addr| u16::try_from(addr).ok());
+    let (start, end) = match arg.split_once('-') {
+        Some((start, end)) => (addr(start)?, addr(end)?),
+        None => (addr(arg)?, addr(arg)?),
+    };
+    (start <= end).then_some(start..=end)
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        frames: DEFAULT_FRAMES,
+        timing: None,
+        quirks: None,
+        seed: 0,
+        input: None,
+        movie: None,
+        format: Format::Ascii,
+        output: None,
+        trace: None,
+        trace_ranges: Vec::new(),
+        trace_limit: None,
+        wav: None,
+        sample_rate: DEFAULT_SAMPLE_RATE,
+    };
+    let mut rom = None;
+    let mut ticks = None;
+    let mut vip_timing = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        //every option takes exactly one value
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--frames" => {
+                let text = value()?;
+                options.frames = text
+                    .parse()
+                    .map_err(|_| format!("Invalid frame count: {}", text))?;
+            }
+            "--ticks" => {
+                let text = value()?;
+                ticks = Some(
+                    text.parse()
+                        .map_err(|_| format!("Invalid tick count: {}", text))?,
+                );
+            }
+            "--wav" => options.wav = Some(value()?.clone()),
+            "--sample-rate" => {
+                let text = value()?;
+                options.sample_rate = text
+                    .parse()
+                    .ok()
+                    .filter(|&rate| (1000..=192000).contains(&rate))
+                    .ok_or_else(|| format!("Invalid sample rate: {}", text))?;
+            }
+            "--timing" => match value()?.as_str() {
+                "fixed" => vip_timing = Some(false),
+                "vip" => vip_timing = Some(true),
+                text => return Err(format!("Unknown timing model: {}", text)),
+            },
+            "--profile" => {
+                let text = value()?;
+                options.quirks = Some(
+                    Quirks::from_name(text).ok_or_else(|| format!("Unknown profile: {}", text))?,
+                );
+            }
+            "--seed" => {
+                let text = value()?;
+                options.seed =
+                    parse_number(text).ok_or_else(|| format!("Invalid seed: {}", text))?;
+            }
+            "--input" => options.input = Some(value()?.clone()),
+            "--movie" => options.movie = Some(value()?.clone()),
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "ascii" => Format::Ascii,
+                    "pbm" => Format::Pbm,
+                    other => return Err(format!("Unknown format: {}", other)),
+                }
+            }
+            "--output" => options.output = Some(value()?.clone()),
+            "--trace" => options.trace = Some(value()?.clone()),
+            "--trace-range" => {
+                let text = value()?;
+                options
+                    .trace_ranges
+                    .push(parse_range(text).ok_or_else(|| format!("Invalid range: {}", text))?);
+            }
+            "--trace-limit" => {
+                let text = value()?;
+                options.trace_limit = Some(
+                    text.parse()
+                        .map_err(|_| format!("Invalid trace limit: {}", text))?,
+                );
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if rom.is_none() => rom = Some(arg.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    if options.input.is_some() && options.movie.is_some() {
+        return Err("--input and --movie cannot be used together".to_string());
+    }
+    options.timing = match (vip_timing, ticks) {
+        (Some(true), Some(_)) => return Err("--ticks only applies to fixed timing".to_string()),
+        (Some(true), None) => Some(Timing::Vip),
+        (None, None) => None,
+        (_, ticks) => Some(Timing::Instructions(
+            ticks.unwrap_or(DEFAULT_TICKS_PER_FRAME),
+        )),
+    };
+    options.rom = rom.ok_or_else(|| USAGE.to_string())?;
+    Ok(options)
+}
+
+//one character per pixel, the four XO-CHIP colours get their own character
+fn dump_ascii(emu: &Emu) -> String {
+    const CHARS: [char; 4] = ['.', '#', '+', '%'];
+    let mut out = String::new();
+    for row in emu.get_display().chunks(emu.screen_width()) {
+        out.extend(row.iter().map(|&pixel| CHARS[pixel as usize & 0b11]));
+        out.push('\n');
+    }
+    out
+}
+
+//plain PBM, any lit plane counts as black
+fn dump_pbm(emu: &Emu) -> String {
+    let mut out = format!("P1\n{} {}\n", emu.screen_width(), emu.screen_height());
+    for row in emu.get_display().chunks(emu.screen_width()) {
+        let bits: Vec<_> = row
+            .iter()
+            .map(|&pixel| if pixel != 0 { "1" } else { "0" })
+            .collect();
+        out.push_str(&bits.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+fn dump_registers(emu: &Emu, frames: usize) -> String {
+    let mut out = format!(
+        "frames={} pc={:04X} i={:04X} sp={:02X} dt={:02X} st={:02X}\n",
+        frames,
+        emu.pc(),
+        emu.i_reg(),
+        emu.sp(),
+        emu.delay_timer(),
+        emu.sound_timer()
+    );
+    let regs: Vec<_> = emu
+        .v_reg()
+        .iter()
+        .enumerate()
+        .map(|(idx, val)| format!("v{:X}={:02X}", idx, val))
+        .collect();
+    out.push_str(&regs.join(" "));
+    out.push('\n');
+    out
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom =
+        fs::read(&options.rom).map_err(|err| format!("Unable to read {}: {}", options.rom, err))?;
+    let script = match &options.input {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .map_err(|err| format!("Unable to read {}: {}", path, err))?;
+            Some(InputScript::parse(&text).map_err(|err| format!("{}: {}", path, err))?)
+        }
+        None => None,
+    };
+    //a movie brings its own seed and settings, those given on the command line have to match
+    let movie = match &options.movie {
+        Some(path) => {
+            let data = fs::read(path).map_err(|err| format!("Unable to read {}: {}", path, err))?;
+            let movie = Movie::from_bytes(&data).map_err(|err| format!("{}: {}", path, err))?;
+            movie.check_rom(&rom).map_err(|err| err.to_string())?;
+            movie
+                .check_settings(options.quirks, options.timing, None)
+                .map_err(|err| err.to_string())?;
+            Some(movie)
+        }
+        None => None,
+    };
+    let (quirks, timing, load_addr) = match &movie {
+        Some(movie) => (movie.quirks, movie.timing, movie.load_addr),
+        None => (
+            options.quirks.unwrap_or_default(),
+            options
+                .timing
+                .unwrap_or(Timing::Instructions(DEFAULT_TICKS_PER_FRAME)),
+            START_ADDR,
+        ),
+    };
+    let mut chip8 = Emu::with_quirks(quirks);
+    chip8
+        .load_at(load_addr, &rom)
+        .map_err(|err| format!("Unable to load {}: {}", options.rom, err))?;
+    match &movie {
+        Some(movie) => chip8.seed_rng(movie.rng_kind, movie.seed),
+        None => chip8.seed_rng(RngKind::Xorshift, options.seed),
+    }
+    if let Some(path) = &options.trace {
+        let file =
+            fs::File::create(path).map_err(|err| format!("Unable to create {}: {}", path, err))?;
+        let mut tracer = Tracer::new(BufWriter::new(file));
+        for range in &options.trace_ranges {
+            tracer.add_range(range.clone());
+        }
+        if let Some(limit) = options.trace_limit {
+            tracer.set_limit(limit);
+        }
+        chip8.set_tracer(tracer);
+    }
+
+    let mut audio = options
+        .wav
+        .as_ref()
+        .map(|_| (AudioRenderer::new(options.sample_rate), Vec::new()));
+
+    let mut result = Ok(());
+    let mut frames_run = 0;
+    chip8.set_timing(timing);
+    for frame in 0..options.frames {
+        if let Some(script) = &script {
+            for &(key, pressed) in script.events_at(frame) {
+                chip8.keypress(key, pressed);
+            }
+        }
+        if let Some(keys) = movie.as_ref().and_then(|movie| movie.frame(frame)) {
+            chip8.set_key_state(keys);
+        }
+        if let Err(err) = chip8.run_frame() {
+            result = Err(format!("Emulator error in frame {}: {}", frame, err));
+            break;
+        }
+        if let Some((renderer, samples)) = audio.as_mut() {
+            samples.extend(renderer.render(&chip8, 1));
+        }
+        frames_run += 1;
+        if chip8.has_exited() {
+            break;
+        }
+    }
+
+    //the state at the point of failure is the most useful part of a failing run
+    let screen = match options.format {
+        Format::Ascii => dump_ascii(&chip8),
+        Format::Pbm => dump_pbm(&chip8),
+    };
+    match &options.output {
+        Some(path) => {
+            fs::write(path, screen).map_err(|err| format!("Unable to write {}: {}", path, err))?
+        }
+        None => print!("{}", screen),
+    }
+    if let (Some((renderer, samples)), Some(path)) = (&audio, &options.wav) {
+        let file =
+            fs::File::create(path).map_err(|err| format!("Unable to create {}: {}", path, err))?;
+        let mut out = BufWriter::new(file);
+        wav::write_wav(&mut out, renderer.sample_rate(), samples)
+            .and_then(|()| out.flush())
+            .map_err(|err| format!("Unable to write {}: {}", path, err))?;
+    }
+    if let (Some(tracer), Some(path)) = (chip8.take_tracer(), &options.trace) {
+        tracer
+            .finish()
+            .map_err(|err| format!("Unable to write {}: {}", path, err))?;
+    }
+    print!("{}", dump_registers(&chip8, frames_run));
+    io::stdout().flush().map_err(|err| err.to_string())?;
+    result
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    };
+    if let Err(msg) = run(&options) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn defaults_leave_the_settings_to_a_movie() {
+        let options = parse_args(&args("game.ch8")).unwrap();
+        assert_eq!(options.rom, "game.ch8");
+        assert_eq!(options.frames, DEFAULT_FRAMES);
+        assert_eq!(options.timing, None);
+        assert_eq!(options.quirks, None);
+        assert_eq!(options.seed, 0);
+        assert!(options.format == Format::Ascii);
+        assert_eq!(options.sample_rate, DEFAULT_SAMPLE_RATE);
+        assert!(options.trace_ranges.is_empty());
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = parse_args(&args(
+            "--frames 5 --ticks 20 --profile schip --seed 0x10 --format pbm --output out.pbm \
+             --trace t.log --trace-range 0x200-0x210 --trace-range 0x300 --trace-limit 7 \
+             --wav out.wav --sample-rate 8000 --input keys.txt game.ch8",
+        ))
+        .unwrap();
+        assert_eq!(options.rom, "game.ch8");
+        assert_eq!(options.frames, 5);
+        assert_eq!(options.timing, Some(Timing::Instructions(20)));
+        assert_eq!(options.quirks, Some(Quirks::schip()));
+        assert_eq!(options.seed, 16);
+        assert!(options.format == Format::Pbm);
+        assert_eq!(options.output.as_deref(), Some("out.pbm"));
+        assert_eq!(options.trace.as_deref(), Some("t.log"));
+        assert_eq!(options.trace_ranges, [0x200..=0x210, 0x300..=0x300]);
+        assert_eq!(options.trace_limit, Some(7));
+        assert_eq!(options.wav.as_deref(), Some("out.wav"));
+        assert_eq!(options.sample_rate, 8000);
+        assert_eq!(options.input.as_deref(), Some("keys.txt"));
+    }
+
+    #[test]
+    fn timing_models() {
+        let timing = |text: &str| parse_args(&args(text)).unwrap().timing;
+        assert_eq!(timing("--timing vip g"), Some(Timing::Vip));
+        assert_eq!(
+            timing("--timing fixed g"),
+            Some(Timing::Instructions(DEFAULT_TICKS_PER_FRAME))
+        );
+        assert_eq!(
+            timing("--timing fixed --ticks 3 g"),
+            Some(Timing::Instructions(3))
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        let cases = [
+            (
+                "--timing vip --ticks 5 g",
+                "--ticks only applies to fixed timing",
+            ),
+            ("g --frames", "--frames needs a value"),
+            ("--frames x g", "Invalid frame count: x"),
+            ("--ticks -1 g", "Invalid tick count: -1"),
+            ("--sample-rate 10 g", "Invalid sample rate: 10"),
+            ("--timing slow g", "Unknown timing model: slow"),
+            ("--profile nes g", "Unknown profile: nes"),
+            ("--seed ten g", "Invalid seed: ten"),
+            ("--format png g", "Unknown format: png"),
+            ("--trace-range 0x210-0x200 g", "Invalid range: 0x210-0x200"),
+            ("--trace-limit many g", "Invalid trace limit: many"),
+            ("--bogus g", "Unknown option: --bogus"),
+            (
+                "--input i --movie m g",
+                "--input and --movie cannot be used together",
+            ),
+            ("", USAGE),
+            ("a b", USAGE),
+        ];
+        for (text, message) in cases {
+            assert_eq!(
+                parse_args(&args(text)).err().as_deref(),
+                Some(message),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("0x200-0x210"), Some(0x200..=0x210));
+        assert_eq!(parse_range("512-0X2FF"), Some(0x200..=0x2FF));
+        assert_eq!(parse_range("0x300"), Some(0x300..=0x300));
+        assert_eq!(parse_range("0xFFFF"), Some(0xFFFF..=0xFFFF));
+        for bad in ["0x200-0x1FF", "0x10000", "0x200-", "-0x200", "a-b", ""] {
+            assert_eq!(parse_range(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+//scripted input for headless runs, one event per line:
+//
+//    # frame  key  action
+//    30       5    down
+//    45       5    up
+//
+//frames count from 0 and events apply before that frame runs, keys are hex digits
+pub struct InputScript {
+    events: BTreeMap<usize, Vec<(usize, bool)>>,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut events: BTreeMap<usize, Vec<(usize, bool)>> = BTreeMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |msg: &str| format!("line {}: {}", idx + 1, msg);
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [frame, key, action] = fields.as_slice() else {
+                return Err(error("expected `<frame> <key> down|up`"));
+            };
+            let frame = frame.parse().map_err(|_| error("invalid frame number"))?;
+            let key = usize::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| error("key must be a hex digit 0-F"))?;
+            let pressed = match *action {
+                "down" => true,
+                "up" => false,
+                _ => return Err(error("action must be `down` or `up`")),
+            };
+            events.entry(frame).or_default().push((key, pressed));
+        }
+        Ok(Self { events })
+    }
+
+    //key changes to apply before the given frame
+    pub fn events_at(&self, frame: usize) -> &[(usize, bool)] {
+        self.events.get(&frame).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_grouped_by_frame() {
+        let script = InputScript::parse(
+            "# frame key action\n\
+             \n\
+             30 5 down\n\
+             30 a down # comment\n\
+             \t45  5  up\n\
+             45 F up\n",
+        )
+        .unwrap();
+        assert_eq!(script.events_at(30), [(5, true), (0xA, true)]);
+        assert_eq!(script.events_at(45), [(5, false), (0xF, false)]);
+        assert!(script.events_at(0).is_empty());
+        assert!(script.events_at(31).is_empty());
+    }
+
+    #[test]
+    fn empty_script_has_no_events() {
+        assert!(InputScript::parse("").unwrap().events_at(0).is_empty());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let cases = [
+            ("1 5", "line 1: expected `<frame> <key> down|up`"),
+            ("1 5 down now", "line 1: expected `<frame> <key> down|up`"),
+            ("\n-1 5 down", "line 2: invalid frame number"),
+            ("1 10 down", "line 1: key must be a hex digit 0-F"),
+            ("1 G down", "line 1: key must be a hex digit 0-F"),
+            (
+                "1 5 down\n2 5 pressed",
+                "line 2: action must be `down` or `up`",
+            ),
+        ];
+        for (text, message) in cases {
+            assert_eq!(
+                InputScript::parse(text).err().as_deref(),
+                Some(message),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+
+//16 bit mono PCM in a RIFF WAVE container
+pub fn write_wav(out: &mut impl Write, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    //format chunk: PCM, 1 channel, 2 bytes per sample
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    #[test]
+    fn header_describes_16_bit_mono() {
+        let mut out = Vec::new();
+        write_wav(&mut out, 22050, &[0, 1, -1, i16::MAX]).unwrap();
+        assert_eq!(out.len(), 44 + 8);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(u32_at(&out, 4), 36 + 8);
+        assert_eq!(&out[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&out, 16), 16);
+        //PCM, mono
+        assert_eq!(u16_at(&out, 20), 1);
+        assert_eq!(u16_at(&out, 22), 1);
+        assert_eq!(u32_at(&out, 24), 22050);
+        assert_eq!(u32_at(&out, 28), 44100);
+        assert_eq!(u16_at(&out, 32), 2);
+        assert_eq!(u16_at(&out, 34), 16);
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(u32_at(&out, 40), 8);
+        assert_eq!(&out[44..], [0, 0, 1, 0, 0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn no_samples_is_a_valid_file() {
+        let mut out = Vec::new();
+        write_wav(&mut out, 44100, &[]).unwrap();
+        assert_eq!(out.len(), 44);
+        assert_eq!(u32_at(&out, 4), 36);
+        assert_eq!(u32_at(&out, 40), 0);
+    }
+}